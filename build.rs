@@ -0,0 +1,182 @@
+// With the `embedded-shaders` feature, compiles every shader under src/bin/shader to SPIR-V and
+// generates the table src/render/shader.rs embeds. Paths are the same VFS paths the renderer asks
+// for, e.g. "shader/triangle/triangle.vert".
+//
+// HLSL sources are named by stage the same way ShaderSource expects (foo.vs.hlsl, foo.ps.hlsl,
+// foo.cs.hlsl) and compiled with the default VSMain/PSMain/CSMain entry points.
+//
+// Every shader gets its variant without defines. Other variants have to be listed in
+// src/bin/shader/variants.txt, one per line: path followed by defines (NAME or NAME=VALUE).
+
+fn main() {
+    #[cfg(feature = "embedded-shaders")]
+    embedded::compile_all();
+}
+
+#[cfg(feature = "embedded-shaders")]
+mod embedded {
+    use std::env;
+    use std::fmt::Write as _;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use shaderc_build as shaderc;
+
+    const ASSET_ROOT: &str = "src/bin";
+    const SHADER_DIR: &str = "shader";
+    const INCLUDE_DIR: &str = "shader/include";
+    const VARIANTS_FILE: &str = "src/bin/shader/variants.txt";
+
+    // Kind, whether it's HLSL and the entry point, mirroring shader::ShaderSource::new
+    fn shader_kind(path: &str) -> Option<(shaderc::ShaderKind, bool, &'static str)> {
+        let path = Path::new(path);
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        let hlsl = extension == "hlsl";
+        let stage = if hlsl || extension == "glsl" {
+            Path::new(path.file_stem()?)
+                .extension()?
+                .to_str()?
+                .to_ascii_lowercase()
+        } else {
+            extension
+        };
+        let (kind, entry_point) = match stage.as_str() {
+            "vert" | "vs" => (shaderc::ShaderKind::Vertex, "VSMain"),
+            "frag" | "ps" => (shaderc::ShaderKind::Fragment, "PSMain"),
+            "comp" | "cs" => (shaderc::ShaderKind::Compute, "CSMain"),
+            _ => return None,
+        };
+        Some((kind, hlsl, if hlsl { entry_point } else { "main" }))
+    }
+
+    // VFS style paths of every shader below `dir`. Directories are watched too, so adding a
+    // shader reruns the script.
+    fn collect_shaders(root: &Path, dir: &str, shaders: &mut Vec<String>) {
+        println!("cargo:rerun-if-changed={}/{}", ASSET_ROOT, dir);
+        for entry in fs::read_dir(root.join(dir)).unwrap() {
+            let entry = entry.unwrap();
+            let name = entry.file_name().into_string().unwrap();
+            let path = format!("{}/{}", dir, name);
+            if entry.file_type().unwrap().is_dir() {
+                collect_shaders(root, &path, shaders);
+            } else if shader_kind(&path).is_some() {
+                shaders.push(path);
+            }
+        }
+    }
+
+    // Same rules as ShaderCompiler: next to the includer first for "...", then the include dir
+    fn resolve_include(root: &Path, name: &str, relative: bool, requester: &str) -> Option<String> {
+        if relative {
+            let dir = requester.rfind('/').map_or("", |i| &requester[..i + 1]);
+            let path = format!("{}{}", dir, name);
+            if root.join(&path).is_file() {
+                return Some(path);
+            }
+        }
+        let path = format!("{}/{}", INCLUDE_DIR, name);
+        if root.join(&path).is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    // Sorted by name, matching Defines
+    fn parse_defines<'a>(words: impl Iterator<Item = &'a str>) -> Vec<(String, Option<String>)> {
+        let mut defines: Vec<(String, Option<String>)> = words
+            .map(|word| match word.find('=') {
+                Some(i) => (word[..i].to_string(), Some(word[i + 1..].to_string())),
+                None => (word.to_string(), None),
+            })
+            .collect();
+        defines.sort();
+        defines.dedup_by(|a, b| a.0 == b.0);
+        defines
+    }
+
+    pub fn compile_all() {
+        let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join(ASSET_ROOT);
+        let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+        let mut shaders = Vec::new();
+        collect_shaders(&root, SHADER_DIR, &mut shaders);
+        shaders.sort();
+
+        let mut variants: Vec<(String, Vec<(String, Option<String>)>)> =
+            shaders.into_iter().map(|path| (path, Vec::new())).collect();
+
+        println!("cargo:rerun-if-changed={}", VARIANTS_FILE);
+        if let Ok(list) = fs::read_to_string(VARIANTS_FILE) {
+            for line in list.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut words = line.split_whitespace();
+                let path = words.next().unwrap().to_string();
+                let defines = parse_defines(words);
+                if !variants.iter().any(|(p, d)| *p == path && *d == defines) {
+                    variants.push((path, defines));
+                }
+            }
+        }
+
+        let mut compiler = shaderc::Compiler::new().expect("Failed to create shader compiler");
+        let mut table = String::from("pub static EMBEDDED_SHADERS: &[EmbeddedShader] = &[\n");
+
+        for (i, (path, defines)) in variants.iter().enumerate() {
+            let (kind, hlsl, entry_point) =
+                shader_kind(path).unwrap_or_else(|| panic!("{}: unknown shader stage", path));
+            let source =
+                fs::read_to_string(root.join(path)).unwrap_or_else(|e| panic!("{}: {}", path, e));
+            println!("cargo:rerun-if-changed={}/{}", ASSET_ROOT, path);
+
+            let mut options = shaderc::CompileOptions::new().unwrap();
+            options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+            if hlsl {
+                options.set_source_language(shaderc::SourceLanguage::HLSL);
+            }
+            for (name, value) in defines {
+                options.add_macro_definition(name, value.as_deref());
+            }
+            let include_root = root.clone();
+            options.set_include_callback(move |name, include_type, requester, _depth| {
+                let relative = include_type == shaderc::IncludeType::Relative;
+                let resolved = resolve_include(&include_root, name, relative, requester)
+                    .ok_or_else(|| format!("Include {} not found", name))?;
+                println!("cargo:rerun-if-changed={}/{}", ASSET_ROOT, resolved);
+                let content = fs::read_to_string(include_root.join(&resolved))
+                    .map_err(|e| format!("{}: {}", resolved, e))?;
+                Ok(shaderc::ResolvedInclude {
+                    resolved_name: resolved,
+                    content,
+                })
+            });
+
+            let artifact = compiler
+                .compile_into_spirv(&source, kind, path, entry_point, Some(&options))
+                .unwrap_or_else(|e| panic!("{}", e));
+
+            let spv_name = format!("shader_{}.spv", i);
+            fs::write(out_dir.join(&spv_name), artifact.as_binary_u8()).unwrap();
+
+            let mut defines_src = String::new();
+            for (name, value) in defines {
+                match value {
+                    Some(value) => write!(defines_src, "({:?}, Some({:?})), ", name, value),
+                    None => write!(defines_src, "({:?}, None), ", name),
+                }
+                .unwrap();
+            }
+            writeln!(
+                table,
+                "    EmbeddedShader {{ path: {:?}, defines: &[{}], spirv: include_bytes!(concat!(env!(\"OUT_DIR\"), \"/{}\")) }},",
+                path, defines_src, spv_name
+            )
+            .unwrap();
+        }
+
+        table.push_str("];\n");
+        fs::write(out_dir.join("embedded_shaders.rs"), table).unwrap();
+    }
+}
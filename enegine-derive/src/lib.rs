@@ -0,0 +1,214 @@
+// Derives for enegine. Kept in their own crate since proc macros have to be.
+//
+// #[derive(VertexLayout)] implements `enegine::render::vertex::VertexType`, so the vertex input
+// layout of a struct comes from its fields instead of being written out by hand:
+//
+//     #[derive(Clone, Copy, VertexLayout)]
+//     #[repr(C)]
+//     #[vertex(binding = 1, instance)]
+//     struct Instance {
+//         #[vertex(location = 5)]
+//         model: Mat4,                     // locations 5..8, one per column
+//         #[vertex(format = "R8G8B8A8_UNORM")]
+//         tint: [u8; 4],
+//         #[vertex(skip)]
+//         _padding: u32,
+//     }
+//
+// Locations count up from 0, or from the last explicit one. Formats come from the field types
+// through `AttributeType` unless given.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta};
+
+#[derive(Default)]
+struct ContainerOptions {
+    binding: u32,
+    instance: bool,
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    location: Option<u32>,
+    format: Option<Ident>,
+    skip: bool,
+}
+
+// Everything inside `#[vertex(...)]`
+fn vertex_args(attrs: &[Attribute]) -> syn::Result<Vec<NestedMeta>> {
+    let mut args = Vec::new();
+    for attr in attrs.iter().filter(|a| a.path.is_ident("vertex")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => args.extend(list.nested),
+            meta => return Err(syn::Error::new_spanned(meta, "expected #[vertex(...)]")),
+        }
+    }
+    Ok(args)
+}
+
+fn int_arg(lit: &Lit) -> syn::Result<u32> {
+    match lit {
+        Lit::Int(int) => int.base10_parse(),
+        _ => Err(syn::Error::new_spanned(lit, "expected an integer")),
+    }
+}
+
+fn container_options(attrs: &[Attribute]) -> syn::Result<ContainerOptions> {
+    let mut options = ContainerOptions::default();
+    for arg in vertex_args(attrs)? {
+        match arg {
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("instance") => {
+                options.instance = true;
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("binding") => {
+                options.binding = int_arg(&nv.lit)?;
+            }
+            arg => {
+                return Err(syn::Error::new_spanned(
+                    arg,
+                    "expected `binding = N` or `instance`",
+                ))
+            }
+        }
+    }
+    Ok(options)
+}
+
+fn field_options(attrs: &[Attribute]) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for arg in vertex_args(attrs)? {
+        match arg {
+            NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("skip") => {
+                options.skip = true;
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("location") => {
+                options.location = Some(int_arg(&nv.lit)?);
+            }
+            NestedMeta::Meta(Meta::NameValue(ref nv)) if nv.path.is_ident("format") => {
+                match nv.lit {
+                    Lit::Str(ref s) => {
+                        let mut format = syn::parse_str::<Ident>(&s.value()).map_err(|_| {
+                            syn::Error::new_spanned(s, "expected a vk::Format name")
+                        })?;
+                        format.set_span(s.span());
+                        options.format = Some(format);
+                    }
+                    ref lit => {
+                        return Err(syn::Error::new_spanned(lit, "expected a vk::Format name"))
+                    }
+                }
+            }
+            arg => {
+                return Err(syn::Error::new_spanned(
+                    arg,
+                    "expected `location = N`, `format = \"...\"` or `skip`",
+                ))
+            }
+        }
+    }
+    Ok(options)
+}
+
+fn derive_vertex_layout(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "VertexLayout needs named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "VertexLayout can only be derived for structs",
+            ))
+        }
+    };
+
+    let container = container_options(&input.attrs)?;
+    let binding = container.binding;
+    let input_rate = if container.instance {
+        quote!(::enegine::ash::vk::VertexInputRate::INSTANCE)
+    } else {
+        quote!(::enegine::ash::vk::VertexInputRate::VERTEX)
+    };
+
+    let mut pushes = Vec::new();
+    for field in fields {
+        let options = field_options(&field.attrs)?;
+        if options.skip {
+            continue;
+        }
+
+        let name = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let set_location = options.location.map(|l| quote!(location = #l;));
+        // An explicit format is one location, whatever the field type is
+        let (format, locations) = match options.format {
+            Some(format) => (quote!(::enegine::ash::vk::Format::#format), quote!(1u32)),
+            None => (
+                quote!(<#ty as AttributeType>::FORMAT),
+                quote!(<#ty as AttributeType>::LOCATIONS),
+            ),
+        };
+
+        pushes.push(quote! {
+            #set_location
+            {
+                let locations = #locations;
+                let offset = ::std::mem::offset_of!(Self, #name) as u32;
+                let stride = ::std::mem::size_of::<#ty>() as u32 / locations;
+                for i in 0..locations {
+                    attributes.push(VertexAttribute {
+                        location: location + i,
+                        binding: #binding,
+                        format: #format,
+                        offset: offset + i * stride,
+                    });
+                }
+                location += locations;
+            }
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::enegine::render::vertex::VertexType for #ident #ty_generics #where_clause {
+            #[allow(unused_assignments, unused_mut)]
+            fn vertex_layout() -> ::enegine::render::pipeline::VertexLayout {
+                use ::enegine::render::pipeline::{VertexAttribute, VertexBinding, VertexLayout};
+                use ::enegine::render::vertex::AttributeType;
+
+                let mut attributes = ::std::vec::Vec::new();
+                let mut location = 0u32;
+                #(#pushes)*
+
+                VertexLayout {
+                    bindings: ::std::vec![VertexBinding {
+                        binding: #binding,
+                        stride: ::std::mem::size_of::<Self>() as u32,
+                        input_rate: #input_rate,
+                    }],
+                    attributes,
+                }
+            }
+        }
+    })
+}
+
+#[proc_macro_derive(VertexLayout, attributes(vertex))]
+pub fn vertex_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive_vertex_layout(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
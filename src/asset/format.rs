@@ -0,0 +1,404 @@
+// Baked asset formats written by enegine-bake. Everything is little endian and laid out so the
+// runtime only has to check a header before handing the payload to a staging buffer.
+//
+// Mesh (.emesh):
+//   magic "EMSH", version, vertex stride, vertex count, index size (2 or 4), index count,
+//   vertex data, index data (starts 4 byte aligned)
+//
+// Texture (.etex):
+//   magic "ETEX", version, format, width, height, mip count,
+//   mip count * (offset, size) relative to the start of the data, data
+
+use std::io::{self, Write};
+use std::mem;
+
+pub const MESH_MAGIC: [u8; 4] = *b"EMSH";
+pub const TEXTURE_MAGIC: [u8; 4] = *b"ETEX";
+// Bump whenever the layout of either format changes
+pub const FORMAT_VERSION: u32 = 1;
+
+pub const MESH_EXTENSION: &str = "emesh";
+pub const TEXTURE_EXTENSION: &str = "etex";
+
+const MESH_HEADER_SIZE: usize = 24;
+const TEXTURE_HEADER_SIZE: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    U16,
+    U32,
+}
+
+impl IndexFormat {
+    pub fn size(self) -> usize {
+        match self {
+            IndexFormat::U16 => 2,
+            IndexFormat::U32 => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    Rgba8Srgb,
+    Rgba8Unorm,
+}
+
+impl TextureFormat {
+    fn to_raw(self) -> u32 {
+        match self {
+            TextureFormat::Rgba8Srgb => 0,
+            TextureFormat::Rgba8Unorm => 1,
+        }
+    }
+
+    pub fn bytes_per_texel(self) -> u32 {
+        match self {
+            TextureFormat::Rgba8Srgb | TextureFormat::Rgba8Unorm => 4,
+        }
+    }
+
+    fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(TextureFormat::Rgba8Srgb),
+            1 => Some(TextureFormat::Rgba8Unorm),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshData<'a> {
+    pub vertex_stride: u32,
+    pub vertex_count: u32,
+    pub index_format: IndexFormat,
+    pub index_count: u32,
+    pub vertices: &'a [u8],
+    pub indices: &'a [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextureData<'a> {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub mips: Vec<MipLevel>,
+    // All mips back to back, offsets in `mips` index into this
+    pub data: &'a [u8],
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, &'static str> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or("Unexpected end of asset data")
+}
+
+fn check_header(bytes: &[u8], magic: [u8; 4]) -> Result<(), &'static str> {
+    if bytes.len() < 8 || bytes[0..4] != magic {
+        return Err("Not a baked asset of the expected type");
+    }
+    if read_u32(bytes, 4)? != FORMAT_VERSION {
+        return Err("Baked asset version mismatch, rebake with enegine-bake");
+    }
+    Ok(())
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+impl<'a> MeshData<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        check_header(bytes, MESH_MAGIC)?;
+        let vertex_stride = read_u32(bytes, 8)?;
+        let vertex_count = read_u32(bytes, 12)?;
+        let index_format = match read_u32(bytes, 16)? {
+            2 => IndexFormat::U16,
+            4 => IndexFormat::U32,
+            _ => return Err("Invalid index size"),
+        };
+        let index_count = read_u32(bytes, 20)?;
+        if vertex_stride == 0 || vertex_count == 0 || index_count == 0 {
+            return Err("Empty mesh");
+        }
+        if index_count % 3 != 0 {
+            return Err("Index count isn't a whole number of triangles");
+        }
+
+        // In u64 so corrupt counts can't overflow on the way to the length check
+        let vertices_end =
+            MESH_HEADER_SIZE as u64 + u64::from(vertex_stride) * u64::from(vertex_count);
+        let indices_start = (vertices_end + 3) & !3;
+        let indices_end = indices_start + u64::from(index_count) * index_format.size() as u64;
+        if (bytes.len() as u64) < indices_end {
+            return Err("Unexpected end of asset data");
+        }
+        let (vertices_end, indices_start, indices_end) = (
+            vertices_end as usize,
+            indices_start as usize,
+            indices_end as usize,
+        );
+
+        let indices = &bytes[indices_start..indices_end];
+        let in_range = match index_format {
+            IndexFormat::U16 => indices
+                .chunks_exact(2)
+                .all(|i| u32::from(u16::from_le_bytes([i[0], i[1]])) < vertex_count),
+            IndexFormat::U32 => indices
+                .chunks_exact(4)
+                .all(|i| u32::from_le_bytes([i[0], i[1], i[2], i[3]]) < vertex_count),
+        };
+        if !in_range {
+            return Err("Mesh index past the last vertex");
+        }
+
+        Ok(MeshData {
+            vertex_stride,
+            vertex_count,
+            index_format,
+            index_count,
+            vertices: &bytes[MESH_HEADER_SIZE..vertices_end],
+            indices,
+        })
+    }
+}
+
+impl<'a> TextureData<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        check_header(bytes, TEXTURE_MAGIC)?;
+        let format =
+            TextureFormat::from_raw(read_u32(bytes, 8)?).ok_or("Unknown texture format")?;
+        let width = read_u32(bytes, 12)?;
+        let height = read_u32(bytes, 16)?;
+        let mip_count = read_u32(bytes, 20)? as usize;
+        if width == 0 || height == 0 {
+            return Err("Texture has no texels");
+        }
+        // Down to 1x1 at most
+        let max_mip_count = (32 - width.max(height).leading_zeros()) as usize;
+        if mip_count == 0 || mip_count > max_mip_count {
+            return Err("Invalid mip count");
+        }
+
+        let data_start = TEXTURE_HEADER_SIZE + mip_count * 8;
+        let data = bytes
+            .get(data_start..)
+            .ok_or("Unexpected end of asset data")?;
+
+        let mut mips = Vec::with_capacity(mip_count);
+        for level in 0..mip_count {
+            let offset = read_u32(bytes, TEXTURE_HEADER_SIZE + level * 8)?;
+            let size = read_u32(bytes, TEXTURE_HEADER_SIZE + level * 8 + 4)?;
+            if offset as usize + size as usize > data.len() {
+                return Err("Mip level out of bounds");
+            }
+            let (mip_width, mip_height) = ((width >> level).max(1), (height >> level).max(1));
+            let expected_size =
+                u64::from(mip_width) * u64::from(mip_height) * u64::from(format.bytes_per_texel());
+            if u64::from(size) != expected_size {
+                return Err("Mip level size doesn't match its dimensions");
+            }
+            mips.push(MipLevel {
+                width: mip_width,
+                height: mip_height,
+                offset,
+                size,
+            });
+        }
+
+        Ok(TextureData {
+            format,
+            width,
+            height,
+            mips,
+            data,
+        })
+    }
+}
+
+fn as_bytes<T: Copy>(slice: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(slice.as_ptr() as *const u8, mem::size_of_val(slice)) }
+}
+
+// Vertices are written as is, so V needs to be the #[repr(C)] type the runtime reads back.
+// 16 bit indices are used whenever they can address every vertex.
+pub fn write_mesh<V: Copy, W: Write>(w: &mut W, vertices: &[V], indices: &[u32]) -> io::Result<()> {
+    let index_format = if vertices.len() <= u16::MAX as usize + 1 {
+        IndexFormat::U16
+    } else {
+        IndexFormat::U32
+    };
+
+    w.write_all(&MESH_MAGIC)?;
+    for value in &[
+        FORMAT_VERSION,
+        mem::size_of::<V>() as u32,
+        vertices.len() as u32,
+        index_format.size() as u32,
+        indices.len() as u32,
+    ] {
+        w.write_all(&value.to_le_bytes())?;
+    }
+
+    let vertex_bytes = as_bytes(vertices);
+    w.write_all(vertex_bytes)?;
+    let padding = align4(vertex_bytes.len()) - vertex_bytes.len();
+    w.write_all(&[0u8; 3][..padding])?;
+
+    match index_format {
+        IndexFormat::U16 => {
+            for &i in indices {
+                w.write_all(&(i as u16).to_le_bytes())?;
+            }
+        }
+        IndexFormat::U32 => {
+            for &i in indices {
+                w.write_all(&i.to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Each mip is tightly packed RGBA8, largest first
+pub fn write_texture<W: Write>(
+    w: &mut W,
+    format: TextureFormat,
+    mips: &[image::RgbaImage],
+) -> io::Result<()> {
+    let (width, height) = mips.first().map_or((0, 0), |m| m.dimensions());
+
+    w.write_all(&TEXTURE_MAGIC)?;
+    for value in &[
+        FORMAT_VERSION,
+        format.to_raw(),
+        width,
+        height,
+        mips.len() as u32,
+    ] {
+        w.write_all(&value.to_le_bytes())?;
+    }
+
+    let mut offset = 0u32;
+    for mip in mips {
+        let size = mip.as_raw().len() as u32;
+        w.write_all(&offset.to_le_bytes())?;
+        w.write_all(&size.to_le_bytes())?;
+        offset += size;
+    }
+
+    for mip in mips {
+        w.write_all(mip.as_raw())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mesh_roundtrip() {
+        let vertices: Vec<[f32; 3]> = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let indices = vec![0, 1, 2];
+
+        let mut bytes = Vec::new();
+        write_mesh(&mut bytes, &vertices, &indices).unwrap();
+        let mesh = MeshData::parse(&bytes).unwrap();
+
+        assert_eq!(mesh.vertex_stride, 12);
+        assert_eq!(mesh.vertex_count, 3);
+        assert_eq!(mesh.index_format, IndexFormat::U16);
+        assert_eq!(mesh.vertices, as_bytes(&vertices));
+        assert_eq!(mesh.indices, &[0, 0, 1, 0, 2, 0]);
+
+        bytes[4] = 0xff;
+        assert!(MeshData::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn texture_roundtrip() {
+        let mips = vec![
+            image::RgbaImage::new(4, 2),
+            image::RgbaImage::new(2, 1),
+            image::RgbaImage::new(1, 1),
+        ];
+
+        let mut bytes = Vec::new();
+        write_texture(&mut bytes, TextureFormat::Rgba8Srgb, &mips).unwrap();
+        let texture = TextureData::parse(&bytes).unwrap();
+
+        assert_eq!((texture.width, texture.height), (4, 2));
+        assert_eq!(texture.mips.len(), 3);
+        assert_eq!(
+            texture.mips[1],
+            MipLevel {
+                width: 2,
+                height: 1,
+                offset: 32,
+                size: 8
+            }
+        );
+        assert_eq!(texture.data.len(), 44);
+    }
+
+    #[test]
+    fn corrupt_texture_is_an_error() {
+        let mips = vec![image::RgbaImage::new(2, 2), image::RgbaImage::new(1, 1)];
+        let mut bytes = Vec::new();
+        write_texture(&mut bytes, TextureFormat::Rgba8Srgb, &mips).unwrap();
+        assert!(TextureData::parse(&bytes).is_ok());
+        let rejected = |offset: usize, value: u32| {
+            let mut corrupt = bytes.clone();
+            corrupt[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            TextureData::parse(&corrupt).is_err()
+        };
+
+        assert!(TextureData::parse(&bytes[..TEXTURE_HEADER_SIZE - 1]).is_err());
+        assert!(TextureData::parse(&bytes[..TEXTURE_HEADER_SIZE + 4]).is_err());
+        assert!(TextureData::parse(&bytes[..bytes.len() - 1]).is_err());
+
+        // Width, mip counts outside 1..=2 for 2x2, then the mip sizes
+        assert!(rejected(12, 0));
+        assert!(rejected(20, 0));
+        assert!(rejected(20, 3));
+        assert!(rejected(20, 33));
+        assert!(rejected(TEXTURE_HEADER_SIZE + 4, 12));
+        assert!(rejected(TEXTURE_HEADER_SIZE + 12, 2));
+    }
+
+    #[test]
+    fn corrupt_mesh_is_an_error() {
+        let vertices: Vec<[f32; 3]> = vec![[0.0; 3]; 3];
+        let mesh = |indices: &[u32]| {
+            let mut bytes = Vec::new();
+            write_mesh(&mut bytes, &vertices, indices).unwrap();
+            bytes
+        };
+        let bytes = mesh(&[0, 1, 2]);
+        assert!(MeshData::parse(&bytes).is_ok());
+
+        assert!(MeshData::parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(MeshData::parse(&mesh(&[])).is_err());
+        assert!(MeshData::parse(&mesh(&[0, 1])).is_err());
+        assert!(MeshData::parse(&mesh(&[0, 1, 3])).is_err());
+
+        // No vertices, and more than the file holds
+        let mut corrupt = bytes.clone();
+        corrupt[12..16].copy_from_slice(&0u32.to_le_bytes());
+        assert!(MeshData::parse(&corrupt).is_err());
+        corrupt[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(MeshData::parse(&corrupt).is_err());
+    }
+}
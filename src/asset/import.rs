@@ -0,0 +1,169 @@
+// Source asset importers, used by enegine-bake and as the fallback for assets that haven't
+// been baked yet
+
+use std::io::Cursor;
+
+use glam::{Mat4, Vec2, Vec3};
+
+use super::mesh::{Mesh, NormalMode};
+use super::vfs::Vfs;
+use crate::render::renderer::Vertex;
+
+pub fn extension(path: &str) -> Option<String> {
+    let name = path.rsplit('/').next()?;
+    name.rfind('.').map(|i| name[i + 1..].to_ascii_lowercase())
+}
+
+pub fn import_mesh(vfs: &Vfs, path: &str) -> Result<Mesh<Vertex>, &'static str> {
+    let (mut mesh, has_normals) = match extension(path).as_deref() {
+        Some("obj") => import_obj(vfs, path)?,
+        Some("gltf") | Some("glb") => import_gltf(vfs, path)?,
+        _ => return Err("Unsupported mesh format"),
+    };
+
+    mesh.weld();
+    if !has_normals {
+        mesh.generate_normals(NormalMode::Smooth);
+    }
+    if let Err(e) = mesh.generate_tangents() {
+        warn!("Tangent generation failed: {}", e);
+    }
+    mesh.optimize();
+
+    Ok(mesh)
+}
+
+fn import_obj(vfs: &Vfs, path: &str) -> Result<(Mesh<Vertex>, bool), &'static str> {
+    let data =
+        obj::ObjData::load_buf(Cursor::new(vfs.read(path)?)).map_err(|_| "Failed to parse OBJ")?;
+    let has_normals = !data.normal.is_empty();
+    let mut vertices = Vec::with_capacity(data.position.len());
+
+    for o in &data.objects {
+        for g in &o.groups {
+            for poly in &g.polys {
+                // Fan triangulate anything that isn't a triangle
+                for i in 1..poly.0.len().saturating_sub(1) {
+                    for index in &[&poly.0[0], &poly.0[i], &poly.0[i + 1]] {
+                        let vert = data.position[index.0];
+                        let tex = index.1.map_or([0.0, 0.0], |t| data.texture[t]);
+                        let normal = index.2.map_or([0.0, 0.0, 0.0], |n| data.normal[n]);
+                        vertices.push(Vertex {
+                            position: Vec3::new(vert[0], vert[1], vert[2]),
+                            color: Vec3::zero(),
+                            tex_coord: Vec2::new(tex[0], 1.0 - tex[1]),
+                            normal: Vec3::new(normal[0], normal[1], normal[2]),
+                            tangent: [0.0; 4],
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok((Mesh::from_triangles(vertices), has_normals))
+}
+
+// Every triangle primitive of the default scene, flattened into one mesh with node transforms
+// applied
+fn import_gltf(vfs: &Vfs, path: &str) -> Result<(Mesh<Vertex>, bool), &'static str> {
+    let gltf = gltf::Gltf::from_slice(&vfs.read(path)?).map_err(|_| "Failed to parse glTF")?;
+    let dir = path.rfind('/').map_or("", |i| &path[..i + 1]);
+
+    let mut buffers = Vec::new();
+    for buffer in gltf.document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone().ok_or("glTF binary chunk missing")?,
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                return Err("Embedded glTF buffers aren't supported, use .glb");
+            }
+            gltf::buffer::Source::Uri(uri) => vfs.read(&format!("{}{}", dir, uri))?,
+        };
+        buffers.push(data);
+    }
+
+    let document = &gltf.document;
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or("glTF has no scenes")?;
+
+    let mut mesh = Mesh::new(Vec::new(), Vec::new());
+    let mut has_normals = true;
+    let mut nodes: Vec<(gltf::Node, Mat4)> = scene.nodes().map(|n| (n, Mat4::identity())).collect();
+
+    while let Some((node, parent)) = nodes.pop() {
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        let normal_transform = transform.inverse().transpose();
+        nodes.extend(node.children().map(|n| (n, transform)));
+
+        let gltf_mesh = match node.mesh() {
+            Some(m) => m,
+            None => continue,
+        };
+
+        for primitive in gltf_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                warn!("Skipping non triangle list glTF primitive");
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| Some(buffers[buffer.index()].as_slice()));
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(p) => p.collect(),
+                None => continue,
+            };
+            let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|n| n.collect());
+            let tex_coords: Option<Vec<[f32; 2]>> =
+                reader.read_tex_coords(0).map(|t| t.into_f32().collect());
+            has_normals &= normals.is_some();
+
+            let base = mesh.vertices.len() as u32;
+            for (i, p) in positions.iter().enumerate() {
+                let normal = normals.as_ref().map_or(Vec3::zero(), |n| {
+                    normal_transform.transform_vector3(n[i].into()).normalize()
+                });
+                let tex = tex_coords.as_ref().map_or([0.0; 2], |t| t[i]);
+                mesh.vertices.push(Vertex {
+                    position: transform.transform_point3(Vec3::from(*p)),
+                    color: Vec3::zero(),
+                    tex_coord: Vec2::from(tex),
+                    normal,
+                    tangent: [0.0; 4],
+                });
+            }
+
+            match reader.read_indices() {
+                Some(indices) => mesh.indices.extend(indices.into_u32().map(|i| base + i)),
+                None => mesh.indices.extend(base..base + positions.len() as u32),
+            }
+        }
+    }
+
+    Ok((mesh, has_normals))
+}
+
+// Full mip chain, largest first
+pub fn import_texture(vfs: &Vfs, path: &str) -> Result<Vec<image::RgbaImage>, &'static str> {
+    let image = image::load_from_memory(&vfs.read(path)?).map_err(|_| "Failed to decode image")?;
+    Ok(generate_mips(image.to_rgba8()))
+}
+
+pub fn generate_mips(image: image::RgbaImage) -> Vec<image::RgbaImage> {
+    let (width, height) = image.dimensions();
+    let mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+
+    let mut mips = Vec::with_capacity(mip_levels as usize);
+    mips.push(image);
+    for level in 1..mip_levels {
+        let prev = &mips[level as usize - 1];
+        let mip = image::imageops::resize(
+            prev,
+            (width >> level).max(1),
+            (height >> level).max(1),
+            image::imageops::FilterType::Triangle,
+        );
+        mips.push(mip);
+    }
+    mips
+}
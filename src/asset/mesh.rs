@@ -0,0 +1,703 @@
+use std::collections::HashMap;
+
+use glam::{Vec2, Vec3};
+
+use crate::render::renderer::Vertex;
+
+// Vertex cache size assumed by the optimizers, roughly what current hardware does
+const VERTEX_CACHE_SIZE: usize = 32;
+const OVERDRAW_CACHE_SIZE: usize = 16;
+
+// Forsyth scoring constants
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRI_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Attribute access for any vertex layout the mesh tools can work on.
+/// Layouts without normals, texture coordinates or tangents keep the defaults.
+pub trait MeshVertex: Copy {
+    fn position(&self) -> Vec3;
+
+    fn normal(&self) -> Option<Vec3> {
+        None
+    }
+
+    fn set_normal(&mut self, _normal: Vec3) {}
+
+    fn tex_coord(&self) -> Option<Vec2> {
+        None
+    }
+
+    // xyz is the tangent, w the bitangent sign
+    fn set_tangent(&mut self, _tangent: [f32; 4]) {}
+}
+
+impl MeshVertex for Vertex {
+    fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    fn normal(&self) -> Option<Vec3> {
+        Some(self.normal)
+    }
+
+    fn set_normal(&mut self, normal: Vec3) {
+        self.normal = normal;
+    }
+
+    fn tex_coord(&self) -> Option<Vec2> {
+        Some(self.tex_coord)
+    }
+
+    fn set_tangent(&mut self, tangent: [f32; 4]) {
+        self.tangent = tangent;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalMode {
+    // Averaged over every face sharing a position, weighted by area
+    Smooth,
+    // One normal per face, vertices get split
+    Flat,
+}
+
+/// Indexed triangle list
+#[derive(Clone, Debug)]
+pub struct Mesh<V> {
+    pub vertices: Vec<V>,
+    pub indices: Vec<u32>,
+}
+
+impl<V: MeshVertex> Mesh<V> {
+    pub fn new(vertices: Vec<V>, indices: Vec<u32>) -> Self {
+        Mesh { vertices, indices }
+    }
+
+    // Every three vertices form a triangle
+    pub fn from_triangles(vertices: Vec<V>) -> Self {
+        let indices = (0..vertices.len() as u32).collect();
+        Mesh { vertices, indices }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn face_normal(&self, tri: &[u32]) -> Vec3 {
+        let a = self.vertices[tri[0] as usize].position();
+        let b = self.vertices[tri[1] as usize].position();
+        let c = self.vertices[tri[2] as usize].position();
+        // Not normalized, length is twice the triangle area
+        (b - a).cross(c - a)
+    }
+
+    pub fn generate_normals(&mut self, mode: NormalMode) {
+        match mode {
+            NormalMode::Smooth => {
+                // Accumulate per position, not per vertex, so uv seams don't show up in the shading
+                let mut accum: HashMap<[u32; 3], Vec3> = HashMap::new();
+                for tri in self.indices.chunks_exact(3) {
+                    let normal = self.face_normal(tri);
+                    for &i in tri {
+                        *accum
+                            .entry(position_key(self.vertices[i as usize].position()))
+                            .or_insert_with(Vec3::zero) += normal;
+                    }
+                }
+
+                for v in self.vertices.iter_mut() {
+                    if let Some(&n) = accum.get(&position_key(v.position())) {
+                        v.set_normal(safe_normalize(n));
+                    }
+                }
+            }
+            NormalMode::Flat => {
+                let mut vertices = Vec::with_capacity(self.indices.len());
+                for tri in self.indices.chunks_exact(3) {
+                    let normal = safe_normalize(self.face_normal(tri));
+                    for &i in tri {
+                        let mut v = self.vertices[i as usize];
+                        v.set_normal(normal);
+                        vertices.push(v);
+                    }
+                }
+                self.indices = (0..vertices.len() as u32).collect();
+                self.vertices = vertices;
+            }
+        }
+    }
+
+    // MikkTSpace tangents, needs normals and texture coordinates.
+    // Vertices that end up with different tangents on different faces are split.
+    pub fn generate_tangents(&mut self) -> Result<(), &'static str> {
+        let supported = self
+            .vertices
+            .first()
+            .is_some_and(|v| v.normal().is_some() && v.tex_coord().is_some());
+        if !supported {
+            return Err("Vertex layout has no normals or texture coordinates");
+        }
+
+        let mut geometry = TangentGeometry {
+            mesh: self,
+            tangents: vec![[0.0; 4]; self.indices.len()],
+        };
+        if !mikktspace::generate_tangents(&mut geometry) {
+            return Err("Failed to generate tangents");
+        }
+        let tangents = geometry.tangents;
+
+        let mut assigned: Vec<Option<[f32; 4]>> = vec![None; self.vertices.len()];
+        let mut splits: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+        for (corner, tangent) in tangents.into_iter().enumerate() {
+            let index = self.indices[corner];
+            match assigned[index as usize] {
+                None => {
+                    assigned[index as usize] = Some(tangent);
+                    self.vertices[index as usize].set_tangent(tangent);
+                }
+                Some(existing) if existing == tangent => {}
+                Some(_) => {
+                    let key = (index, tangent_key(tangent));
+                    let vertices = &mut self.vertices;
+                    let split = *splits.entry(key).or_insert_with(|| {
+                        let mut v = vertices[index as usize];
+                        v.set_tangent(tangent);
+                        vertices.push(v);
+                        (vertices.len() - 1) as u32
+                    });
+                    self.indices[corner] = split;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Reorder triangles for the post-transform vertex cache (Forsyth)
+    pub fn optimize_vertex_cache(&mut self) {
+        self.indices = optimize_vertex_cache(&self.indices, self.vertices.len());
+    }
+
+    // Reorder triangle clusters so outward facing ones get drawn first. Expects the indices
+    // to already be cache optimized, threshold is how much worse the cache hit rate may get
+    // (1.05 allows 5%).
+    pub fn optimize_overdraw(&mut self, threshold: f32) {
+        let positions: Vec<Vec3> = self.vertices.iter().map(|v| v.position()).collect();
+        self.indices = optimize_overdraw(&self.indices, &positions, threshold);
+    }
+
+    // Reorder vertices by first use and drop unreferenced ones
+    pub fn optimize_vertex_fetch(&mut self) {
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        for index in self.indices.iter_mut() {
+            let old = *index as usize;
+            if remap[old] == u32::MAX {
+                remap[old] = vertices.len() as u32;
+                vertices.push(self.vertices[old]);
+            }
+            *index = remap[old];
+        }
+        self.vertices = vertices;
+    }
+
+    // Cache, overdraw and fetch optimization in the order they need to run
+    pub fn optimize(&mut self) {
+        self.optimize_vertex_cache();
+        self.optimize_overdraw(1.05);
+        self.optimize_vertex_fetch();
+    }
+
+    // Average cache misses per triangle for a FIFO cache of the given size
+    pub fn acmr(&self, cache_size: usize) -> f32 {
+        if self.indices.is_empty() {
+            return 0.0;
+        }
+        let mut cache_time = vec![0usize; self.vertices.len()];
+        let mut time = cache_size + 1;
+        let mut misses = 0;
+        for &i in self.indices.iter() {
+            if time - cache_time[i as usize] > cache_size {
+                cache_time[i as usize] = time;
+                time += 1;
+                misses += 1;
+            }
+        }
+        misses as f32 / self.triangle_count() as f32
+    }
+}
+
+impl<V: MeshVertex + PartialEq> Mesh<V> {
+    // Merge vertices that are identical in every attribute
+    pub fn weld(&mut self) {
+        let mut buckets: HashMap<[u32; 3], Vec<u32>> = HashMap::new();
+        let mut vertices: Vec<V> = Vec::with_capacity(self.vertices.len());
+        let mut remap = Vec::with_capacity(self.vertices.len());
+
+        for v in self.vertices.iter() {
+            let bucket = buckets.entry(position_key(v.position())).or_default();
+            let index = match bucket.iter().find(|&&i| vertices[i as usize] == *v) {
+                Some(&i) => i,
+                None => {
+                    vertices.push(*v);
+                    let i = (vertices.len() - 1) as u32;
+                    bucket.push(i);
+                    i
+                }
+            };
+            remap.push(index);
+        }
+
+        for index in self.indices.iter_mut() {
+            *index = remap[*index as usize];
+        }
+        self.vertices = vertices;
+    }
+}
+
+struct TangentGeometry<'a, V> {
+    mesh: &'a Mesh<V>,
+    tangents: Vec<[f32; 4]>,
+}
+
+impl<'a, V: MeshVertex> TangentGeometry<'a, V> {
+    fn vertex(&self, face: usize, vert: usize) -> &V {
+        &self.mesh.vertices[self.mesh.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl<'a, V: MeshVertex> mikktspace::Geometry for TangentGeometry<'a, V> {
+    fn num_faces(&self) -> usize {
+        self.mesh.triangle_count()
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position().into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert)
+            .normal()
+            .unwrap_or_else(Vec3::zero)
+            .into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert)
+            .tex_coord()
+            .unwrap_or_else(Vec2::zero)
+            .into()
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = tangent;
+    }
+}
+
+// Bit pattern key, + 0.0 folds -0.0 into 0.0
+fn position_key(p: Vec3) -> [u32; 3] {
+    [
+        (p.x() + 0.0).to_bits(),
+        (p.y() + 0.0).to_bits(),
+        (p.z() + 0.0).to_bits(),
+    ]
+}
+
+fn tangent_key(t: [f32; 4]) -> [u32; 4] {
+    [
+        t[0].to_bits(),
+        t[1].to_bits(),
+        t[2].to_bits(),
+        t[3].to_bits(),
+    ]
+}
+
+fn safe_normalize(v: Vec3) -> Vec3 {
+    if v.length_squared() > 0.0 {
+        v.normalize()
+    } else {
+        Vec3::unit_z()
+    }
+}
+
+fn vertex_score(cache_pos: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_pos {
+        Some(pos) if pos < 3 => LAST_TRI_SCORE,
+        Some(pos) => {
+            let scale = 1.0 / (VERTEX_CACHE_SIZE - 3) as f32;
+            (1.0 - (pos - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+
+    cache_score + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let tri_count = indices.len() / 3;
+
+    // Triangles using each vertex, the first remaining[v] entries are still to be emitted
+    let mut remaining = vec![0u32; vertex_count];
+    for &i in indices {
+        remaining[i as usize] += 1;
+    }
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for v in 0..vertex_count {
+        offsets[v + 1] = offsets[v] + remaining[v] as usize;
+    }
+    let mut adjacency = vec![0usize; indices.len()];
+    let mut fill = offsets.clone();
+    for (t, tri) in indices.chunks_exact(3).enumerate() {
+        for &v in tri {
+            adjacency[fill[v as usize]] = t;
+            fill[v as usize] += 1;
+        }
+    }
+
+    let mut cache_pos: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = (0..vertex_count)
+        .map(|v| vertex_score(None, remaining[v]))
+        .collect();
+    let mut tri_scores: Vec<f32> = indices
+        .chunks_exact(3)
+        .map(|tri| tri.iter().map(|&v| vertex_scores[v as usize]).sum())
+        .collect();
+    let mut emitted = vec![false; tri_count];
+
+    let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+    let mut result = Vec::with_capacity(tri_count * 3);
+    let mut best_tri = None;
+    let mut scan = 0;
+
+    while result.len() < tri_count * 3 {
+        let tri = match best_tri {
+            Some(t) => t,
+            None => {
+                // Nothing adjacent to the cache left, start over at the next unused triangle
+                while emitted[scan] {
+                    scan += 1;
+                }
+                scan
+            }
+        };
+        emitted[tri] = true;
+
+        let verts = &indices[tri * 3..tri * 3 + 3];
+        result.extend_from_slice(verts);
+
+        for &v in verts {
+            let v = v as usize;
+            let start = offsets[v];
+            let count = remaining[v] as usize;
+            let list = &mut adjacency[start..start + count];
+            if let Some(pos) = list.iter().position(|&t| t == tri) {
+                list.swap(pos, count - 1);
+                remaining[v] -= 1;
+            }
+        }
+
+        let mut new_cache: Vec<u32> = verts.to_vec();
+        new_cache.extend(cache.iter().filter(|v| !verts.contains(v)));
+        for (pos, &v) in new_cache.iter().enumerate() {
+            cache_pos[v as usize] = if pos < VERTEX_CACHE_SIZE {
+                Some(pos)
+            } else {
+                None
+            };
+        }
+
+        // Rescore everything that moved in or out of the cache
+        for &v in new_cache.iter() {
+            let v = v as usize;
+            let score = vertex_score(cache_pos[v], remaining[v]);
+            let delta = score - vertex_scores[v];
+            vertex_scores[v] = score;
+            for &t in &adjacency[offsets[v]..offsets[v] + remaining[v] as usize] {
+                tri_scores[t] += delta;
+            }
+        }
+
+        new_cache.truncate(VERTEX_CACHE_SIZE);
+        cache = new_cache;
+
+        best_tri = None;
+        let mut best_score = f32::MIN;
+        for &v in cache.iter() {
+            let v = v as usize;
+            for &t in &adjacency[offsets[v]..offsets[v] + remaining[v] as usize] {
+                if tri_scores[t] > best_score {
+                    best_score = tri_scores[t];
+                    best_tri = Some(t);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+// Cache misses per triangle of a FIFO cache that starts out empty at each cluster
+fn simulate_cache_misses(
+    indices: &[u32],
+    cache_time: &mut [usize],
+    time: &mut usize,
+    tri: usize,
+) -> usize {
+    let mut misses = 0;
+    for &v in &indices[tri * 3..tri * 3 + 3] {
+        if *time - cache_time[v as usize] > OVERDRAW_CACHE_SIZE {
+            cache_time[v as usize] = *time;
+            *time += 1;
+            misses += 1;
+        }
+    }
+    misses
+}
+
+pub fn optimize_overdraw(indices: &[u32], positions: &[Vec3], threshold: f32) -> Vec<u32> {
+    let tri_count = indices.len() / 3;
+    if tri_count == 0 {
+        return Vec::new();
+    }
+
+    // Hard boundaries: triangles where the cache restarts completely
+    let mut cache_time = vec![0usize; positions.len()];
+    let mut time = OVERDRAW_CACHE_SIZE + 1;
+    let mut hard = Vec::new();
+    for t in 0..tri_count {
+        let misses = simulate_cache_misses(indices, &mut cache_time, &mut time, t);
+        if t == 0 || misses == 3 {
+            hard.push(t);
+        }
+    }
+    hard.push(tri_count);
+
+    // Soft boundaries: split hard clusters further as long as the local hit rate stays close
+    // to the cluster's
+    let mut clusters = Vec::new();
+    for bounds in hard.windows(2) {
+        let (start, end) = (bounds[0], bounds[1]);
+
+        time += OVERDRAW_CACHE_SIZE + 1;
+        let cluster_misses: usize = (start..end)
+            .map(|t| simulate_cache_misses(indices, &mut cache_time, &mut time, t))
+            .sum();
+        let cluster_threshold = threshold * cluster_misses as f32 / (end - start) as f32;
+
+        time += OVERDRAW_CACHE_SIZE + 1;
+        let mut cluster_start = start;
+        let mut running_misses = 0;
+        clusters.push(start);
+        for t in start..end {
+            running_misses += simulate_cache_misses(indices, &mut cache_time, &mut time, t);
+            let running = running_misses as f32 / (t - cluster_start + 1) as f32;
+            if t + 1 < end && running <= cluster_threshold {
+                clusters.push(t + 1);
+                cluster_start = t + 1;
+                running_misses = 0;
+                time += OVERDRAW_CACHE_SIZE + 1;
+            }
+        }
+    }
+    clusters.push(tri_count);
+
+    let mesh_centroid = indices
+        .iter()
+        .fold(Vec3::zero(), |acc, &i| acc + positions[i as usize])
+        / indices.len() as f32;
+
+    // Outward facing clusters far from the center are the likely occluders, draw them first
+    let mut sorted: Vec<(f32, usize, usize)> = clusters
+        .windows(2)
+        .map(|bounds| {
+            let (start, end) = (bounds[0], bounds[1]);
+            let mut centroid = Vec3::zero();
+            let mut normal = Vec3::zero();
+            for tri in indices[start * 3..end * 3].chunks_exact(3) {
+                let a = positions[tri[0] as usize];
+                let b = positions[tri[1] as usize];
+                let c = positions[tri[2] as usize];
+                centroid += a + b + c;
+                normal += (b - a).cross(c - a);
+            }
+            centroid /= ((end - start) * 3) as f32;
+            let normal = if normal.length_squared() > 0.0 {
+                normal.normalize()
+            } else {
+                normal
+            };
+            ((centroid - mesh_centroid).dot(normal), start, end)
+        })
+        .collect();
+    sorted.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut result = Vec::with_capacity(indices.len());
+    for (_, start, end) in sorted {
+        result.extend_from_slice(&indices[start * 3..end * 3]);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct TestVertex {
+        position: Vec3,
+        normal: Vec3,
+    }
+
+    impl MeshVertex for TestVertex {
+        fn position(&self) -> Vec3 {
+            self.position
+        }
+
+        fn normal(&self) -> Option<Vec3> {
+            Some(self.normal)
+        }
+
+        fn set_normal(&mut self, normal: Vec3) {
+            self.normal = normal;
+        }
+    }
+
+    fn grid(size: u32) -> Mesh<TestVertex> {
+        let mut vertices = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                vertices.push(TestVertex {
+                    position: Vec3::new(x as f32, y as f32, 0.0),
+                    normal: Vec3::zero(),
+                });
+            }
+        }
+        let mut indices = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let i = y * (size + 1) + x;
+                indices.extend_from_slice(&[i, i + 1, i + size + 2, i, i + size + 2, i + size + 1]);
+            }
+        }
+        Mesh::new(vertices, indices)
+    }
+
+    fn sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]> {
+        let mut tris: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|t| {
+                // Rotate so the smallest index comes first, keeps the winding
+                let r = (0..3).min_by_key(|&i| t[i]).unwrap();
+                [t[r], t[(r + 1) % 3], t[(r + 2) % 3]]
+            })
+            .collect();
+        tris.sort();
+        tris
+    }
+
+    #[test]
+    fn smooth_and_flat_normals() {
+        let mut mesh = grid(2);
+        mesh.generate_normals(NormalMode::Smooth);
+        assert_eq!(mesh.vertices.len(), 9);
+        assert!(mesh.vertices.iter().all(|v| v.normal == Vec3::unit_z()));
+
+        mesh.generate_normals(NormalMode::Flat);
+        assert_eq!(mesh.vertices.len(), mesh.indices.len());
+        assert!(mesh.vertices.iter().all(|v| v.normal == Vec3::unit_z()));
+    }
+
+    #[test]
+    fn weld_merges_identical_vertices() {
+        let triangles = |m: &Mesh<TestVertex>| {
+            let mut tris: Vec<Vec<[u32; 3]>> = m
+                .indices
+                .chunks_exact(3)
+                .map(|t| {
+                    t.iter()
+                        .map(|&i| position_key(m.vertices[i as usize].position))
+                        .collect()
+                })
+                .collect();
+            tris.sort();
+            tris
+        };
+
+        let original = grid(4);
+        let mut mesh = Mesh::from_triangles(
+            original
+                .indices
+                .iter()
+                .map(|&i| original.vertices[i as usize])
+                .collect(),
+        );
+        assert_eq!(mesh.vertices.len(), 96);
+
+        mesh.weld();
+        assert_eq!(mesh.vertices.len(), 25);
+        assert_eq!(triangles(&mesh), triangles(&original));
+
+        mesh.optimize_vertex_fetch();
+        assert_eq!(mesh.indices[0], 0);
+        assert_eq!(triangles(&mesh), triangles(&original));
+    }
+
+    #[test]
+    fn tangents_split_mirrored_uvs() {
+        // Two quads along x with u = |x|, so the left one is mirrored and x = 0 is a uv seam
+        let mut vertices = Vec::new();
+        for &y in &[0.0, 1.0] {
+            for &x in &[-1.0f32, 0.0, 1.0] {
+                vertices.push(Vertex {
+                    position: Vec3::new(x, y, 0.0),
+                    color: Vec3::one(),
+                    tex_coord: Vec2::new(x.abs(), y),
+                    normal: Vec3::unit_z(),
+                    tangent: [0.0; 4],
+                });
+            }
+        }
+        let indices = vec![0, 1, 4, 0, 4, 3, 1, 2, 5, 1, 5, 4];
+        let mut mesh = Mesh::new(vertices, indices);
+        mesh.generate_tangents().unwrap();
+
+        // Each seam vertex gets a copy, one per side
+        assert_eq!(mesh.vertices.len(), 8);
+        for (tri, &(x, w)) in
+            mesh.indices
+                .chunks_exact(3)
+                .zip(&[(-1.0, -1.0), (-1.0, -1.0), (1.0, 1.0), (1.0, 1.0)])
+        {
+            for &i in tri {
+                let t = mesh.vertices[i as usize].tangent;
+                assert!((Vec3::new(t[0], t[1], t[2]) - Vec3::new(x, 0.0, 0.0)).length() < 1e-4);
+                assert_eq!(t[3], w);
+            }
+        }
+    }
+
+    #[test]
+    fn optimizers_keep_triangles() {
+        let mut mesh = grid(32);
+        let before = sorted_triangles(&mesh.indices);
+        let acmr_before = mesh.acmr(VERTEX_CACHE_SIZE);
+
+        mesh.optimize_vertex_cache();
+        assert_eq!(sorted_triangles(&mesh.indices), before);
+        assert!(mesh.acmr(VERTEX_CACHE_SIZE) < acmr_before);
+
+        mesh.optimize_overdraw(1.05);
+        assert_eq!(sorted_triangles(&mesh.indices), before);
+    }
+}
@@ -0,0 +1,60 @@
+pub mod format;
+pub mod import;
+pub mod mesh;
+pub mod vfs;
+pub mod watch;
+
+use vfs::Vfs;
+
+// "models/viking_room.obj" -> "models/viking_room.emesh"
+pub fn baked_path(source: &str, extension: &str) -> String {
+    let name_start = source.rfind('/').map_or(0, |i| i + 1);
+    let stem_end = source[name_start..]
+        .rfind('.')
+        .map_or(source.len(), |i| name_start + i);
+    format!("{}.{}", &source[..stem_end], extension)
+}
+
+pub fn bake_mesh(vfs: &Vfs, source: &str) -> Result<Vec<u8>, &'static str> {
+    let mesh = import::import_mesh(vfs, source)?;
+    let mut bytes = Vec::new();
+    format::write_mesh(&mut bytes, &mesh.vertices, &mesh.indices)
+        .map_err(|_| "Failed to write baked mesh")?;
+    Ok(bytes)
+}
+
+pub fn bake_texture(vfs: &Vfs, source: &str) -> Result<Vec<u8>, &'static str> {
+    let mips = import::import_texture(vfs, source)?;
+    let mut bytes = Vec::new();
+    format::write_texture(&mut bytes, format::TextureFormat::Rgba8Srgb, &mips)
+        .map_err(|_| "Failed to write baked texture")?;
+    Ok(bytes)
+}
+
+// Baked output next to the source if it's up to date, so unbaked assets still work (slowly).
+// Shipping packs can leave the sources out entirely.
+fn load_baked(
+    vfs: &Vfs,
+    source: &str,
+    extension: &str,
+    bake: fn(&Vfs, &str) -> Result<Vec<u8>, &'static str>,
+) -> Result<Vec<u8>, &'static str> {
+    let baked = baked_path(source, extension);
+
+    match (vfs.modified(&baked), vfs.modified(source)) {
+        (Some(baked_time), Some(source_time)) if baked_time >= source_time => vfs.read(&baked),
+        (Some(_), None) => vfs.read(&baked),
+        _ => {
+            warn!("{} is not baked, run enegine-bake on it", source);
+            bake(vfs, source)
+        }
+    }
+}
+
+pub fn load_baked_mesh(vfs: &Vfs, source: &str) -> Result<Vec<u8>, &'static str> {
+    load_baked(vfs, source, format::MESH_EXTENSION, bake_mesh)
+}
+
+pub fn load_baked_texture(vfs: &Vfs, source: &str) -> Result<Vec<u8>, &'static str> {
+    load_baked(vfs, source, format::TEXTURE_EXTENSION, bake_texture)
+}
@@ -0,0 +1,508 @@
+// Virtual file system. Asset paths like "textures/viking_room.png" are resolved through a list
+// of mounted directories and packs, the most recently mounted one wins.
+//
+// Pack (.epak):
+//   magic "EPAK", version, entry count, index offset (u64), file data,
+//   index: per entry path length (u16), path, offset (u64), stored size (u64), size (u64),
+//   compression (u8, 0 = none, 1 = deflate)
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use miniz_oxide::inflate;
+
+pub const PACK_MAGIC: [u8; 4] = *b"EPAK";
+pub const PACK_VERSION: u32 = 1;
+pub const PACK_EXTENSION: &str = "epak";
+
+const PACK_HEADER_SIZE: u64 = 20;
+// Path length, offset, stored size, size and compression of an entry with an empty path
+const PACK_ENTRY_MIN_SIZE: usize = 27;
+// Deflate can't do better than about 1032:1, bigger sizes are corrupt
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+pub trait Mount: Send + Sync {
+    // None if the file isn't in this mount
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>>;
+
+    fn exists(&self, path: &str) -> bool;
+
+    fn modified(&self, path: &str) -> Option<SystemTime>;
+
+    // Where the file lives on disk, if it's a plain file
+    fn real_path(&self, _path: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+pub struct DirMount {
+    root: PathBuf,
+}
+
+impl DirMount {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        DirMount {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Mount for DirMount {
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        let real = self.root.join(path);
+        if real.is_file() {
+            Some(fs::read(real))
+        } else {
+            None
+        }
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.root.join(path).is_file()
+    }
+
+    fn modified(&self, path: &str) -> Option<SystemTime> {
+        fs::metadata(self.root.join(path))
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    fn real_path(&self, path: &str) -> Option<PathBuf> {
+        let real = self.root.join(path);
+        if real.is_file() {
+            Some(real)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Deflate,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PackEntry {
+    offset: u64,
+    stored_size: u64,
+    size: u64,
+    compression: Compression,
+}
+
+pub struct PackMount {
+    file: Mutex<File>,
+    modified: Option<SystemTime>,
+    entries: HashMap<String, PackEntry>,
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(r: &mut impl Read) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl PackMount {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = File::open(path.as_ref())?;
+        let metadata = file.metadata()?;
+        let modified = metadata.modified().ok();
+
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)?;
+        if magic != PACK_MAGIC {
+            return Err(invalid_data("Not an asset pack"));
+        }
+        if read_u32(&mut file)? != PACK_VERSION {
+            return Err(invalid_data("Asset pack version mismatch"));
+        }
+        let entry_count = read_u32(&mut file)?;
+        let index_offset = read_u64(&mut file)?;
+        if index_offset < PACK_HEADER_SIZE || index_offset > metadata.len() {
+            return Err(invalid_data("Pack index out of bounds"));
+        }
+
+        file.seek(SeekFrom::Start(index_offset))?;
+        let mut index = Vec::new();
+        file.read_to_end(&mut index)?;
+        let mut index = io::Cursor::new(index);
+
+        // The count is only a hint, it can't be more than the index has room for
+        let mut entries = HashMap::with_capacity(
+            (entry_count as usize).min(index.get_ref().len() / PACK_ENTRY_MIN_SIZE),
+        );
+        for _ in 0..entry_count {
+            let path_len = read_u16(&mut index)? as usize;
+            let mut path = vec![0u8; path_len];
+            index.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| invalid_data("Invalid pack path"))?;
+
+            let offset = read_u64(&mut index)?;
+            let stored_size = read_u64(&mut index)?;
+            let size = read_u64(&mut index)?;
+            let compression = match read_u8(&mut index)? {
+                0 => Compression::None,
+                1 => Compression::Deflate,
+                _ => return Err(invalid_data("Unknown pack compression")),
+            };
+
+            // Data has to sit between the header and the index, so a corrupt entry can't make
+            // read_entry allocate more than the file holds
+            let in_bounds = offset >= PACK_HEADER_SIZE
+                && offset
+                    .checked_add(stored_size)
+                    .is_some_and(|end| end <= index_offset);
+            if !in_bounds {
+                return Err(invalid_data("Pack entry out of bounds"));
+            }
+            let max_size = match compression {
+                Compression::None => stored_size,
+                Compression::Deflate => stored_size.saturating_mul(MAX_DEFLATE_RATIO),
+            };
+            if size > max_size || (compression == Compression::None && size != stored_size) {
+                return Err(invalid_data("Pack entry size mismatch"));
+            }
+
+            entries.insert(
+                path,
+                PackEntry {
+                    offset,
+                    stored_size,
+                    size,
+                    compression,
+                },
+            );
+        }
+
+        Ok(PackMount {
+            file: Mutex::new(file),
+            modified,
+            entries,
+        })
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|p| p.as_str())
+    }
+
+    fn read_entry(&self, entry: &PackEntry) -> io::Result<Vec<u8>> {
+        let mut stored = vec![0u8; entry.stored_size as usize];
+        {
+            let mut file = self.file.lock().unwrap();
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut stored)?;
+        }
+
+        let data = match entry.compression {
+            Compression::None => stored,
+            // Into exactly the recorded size, anything that doesn't fill it or needs more is corrupt
+            Compression::Deflate => {
+                let mut data = vec![0u8; entry.size as usize];
+                let (status, _, written) = inflate::core::decompress(
+                    &mut inflate::core::DecompressorOxide::new(),
+                    &stored,
+                    &mut data,
+                    0,
+                    inflate::core::inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+                );
+                if status != inflate::TINFLStatus::Done || written != data.len() {
+                    return Err(invalid_data("Corrupt compressed pack entry"));
+                }
+                data
+            }
+        };
+        if data.len() as u64 != entry.size {
+            return Err(invalid_data("Pack entry size mismatch"));
+        }
+        Ok(data)
+    }
+}
+
+impl Mount for PackMount {
+    fn read(&self, path: &str) -> Option<io::Result<Vec<u8>>> {
+        self.entries.get(path).map(|entry| self.read_entry(entry))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    fn modified(&self, path: &str) -> Option<SystemTime> {
+        if self.exists(path) {
+            self.modified
+        } else {
+            None
+        }
+    }
+}
+
+// Forward slashes, no leading slash, no "." or ".." components
+pub fn normalize_path(path: &str) -> Result<String, &'static str> {
+    let mut parts = Vec::new();
+    for part in path.split(&['/', '\\'][..]) {
+        match part {
+            "" | "." => {}
+            ".." => return Err("Asset paths can't leave their mount"),
+            _ => parts.push(part),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+#[derive(Default)]
+pub struct Vfs {
+    mounts: Vec<(String, Box<dyn Mount>)>,
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Vfs { mounts: Vec::new() }
+    }
+
+    // mount_point is prepended to every path in the mount, "" mounts at the root. Invalid ones
+    // are an error rather than falling back to the root, where they'd shadow every other mount.
+    pub fn mount<M: Mount + 'static>(&mut self, mount_point: &str, mount: M) -> io::Result<()> {
+        let mount_point = normalize_path(mount_point).map_err(invalid_data)?;
+        self.mounts.push((mount_point, Box::new(mount)));
+        Ok(())
+    }
+
+    pub fn mount_dir<P: AsRef<Path>>(&mut self, mount_point: &str, dir: P) -> io::Result<()> {
+        self.mount(mount_point, DirMount::new(dir))
+    }
+
+    pub fn mount_pack<P: AsRef<Path>>(&mut self, mount_point: &str, pack: P) -> io::Result<()> {
+        normalize_path(mount_point).map_err(invalid_data)?;
+        let pack = PackMount::open(pack)?;
+        self.mount(mount_point, pack)
+    }
+
+    // Mounts the path could live in, most recent first, with the path relative to the mount
+    fn resolve<'a>(&'a self, path: &str) -> impl Iterator<Item = (&'a dyn Mount, String)> {
+        let path = normalize_path(path).unwrap_or_default();
+        self.mounts
+            .iter()
+            .rev()
+            .filter_map(move |(mount_point, mount)| {
+                let relative = if mount_point.is_empty() {
+                    path.as_str()
+                } else {
+                    path.strip_prefix(mount_point.as_str())?.strip_prefix('/')?
+                };
+                Some((mount.as_ref(), relative.to_string()))
+            })
+    }
+
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, &'static str> {
+        normalize_path(path)?;
+        for (mount, relative) in self.resolve(path) {
+            if let Some(data) = mount.read(&relative) {
+                return data.map_err(|_| "Failed to read asset");
+            }
+        }
+        Err("Asset not found in any mount")
+    }
+
+    pub fn read_to_string(&self, path: &str) -> Result<String, &'static str> {
+        String::from_utf8(self.read(path)?).map_err(|_| "Asset is not valid UTF-8")
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.resolve(path)
+            .any(|(mount, relative)| mount.exists(&relative))
+    }
+
+    pub fn modified(&self, path: &str) -> Option<SystemTime> {
+        self.resolve(path)
+            .find(|(mount, relative)| mount.exists(relative))
+            .and_then(|(mount, relative)| mount.modified(&relative))
+    }
+
+    pub fn real_path(&self, path: &str) -> Option<PathBuf> {
+        self.resolve(path)
+            .find(|(mount, relative)| mount.exists(relative))
+            .and_then(|(mount, relative)| mount.real_path(&relative))
+    }
+}
+
+// Every file under dir, as paths relative to it
+pub fn collect_files(dir: &Path) -> io::Result<Vec<String>> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<String>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(root, &path, files)?;
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                let relative = relative.to_string_lossy();
+                if let Ok(normalized) = normalize_path(&relative) {
+                    files.push(normalized);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(dir, dir, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+// Packs (virtual path, data) pairs. Entries are only stored compressed when that saves space.
+pub fn write_pack<W: Write + Seek>(
+    w: &mut W,
+    files: &[(String, Vec<u8>)],
+    compression: Compression,
+) -> io::Result<()> {
+    w.write_all(&PACK_MAGIC)?;
+    w.write_all(&PACK_VERSION.to_le_bytes())?;
+    w.write_all(&(files.len() as u32).to_le_bytes())?;
+    // Index offset, patched once the data is written
+    w.write_all(&0u64.to_le_bytes())?;
+
+    let mut offset = PACK_HEADER_SIZE;
+    let mut index = Vec::new();
+    for (path, data) in files {
+        let compressed = match compression {
+            Compression::Deflate => Some(miniz_oxide::deflate::compress_to_vec(data, 6))
+                .filter(|c| c.len() < data.len()),
+            Compression::None => None,
+        };
+        let (stored, entry_compression) = match compressed {
+            Some(ref c) => (c.as_slice(), 1u8),
+            None => (data.as_slice(), 0u8),
+        };
+        w.write_all(stored)?;
+
+        let path = normalize_path(path).map_err(invalid_data)?;
+        index.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        index.extend_from_slice(path.as_bytes());
+        index.extend_from_slice(&offset.to_le_bytes());
+        index.extend_from_slice(&(stored.len() as u64).to_le_bytes());
+        index.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        index.push(entry_compression);
+
+        offset += stored.len() as u64;
+    }
+
+    w.write_all(&index)?;
+    w.seek(SeekFrom::Start(PACK_HEADER_SIZE - 8))?;
+    w.write_all(&offset.to_le_bytes())?;
+    w.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_path("/textures\\a/./b.png").unwrap(),
+            "textures/a/b.png"
+        );
+        assert!(normalize_path("textures/../../secret").is_err());
+    }
+
+    #[test]
+    fn pack_mounts_override_dirs() {
+        let dir = std::env::temp_dir().join(format!("enegine-vfs-{}", std::process::id()));
+        fs::create_dir_all(dir.join("textures")).unwrap();
+        fs::write(dir.join("textures/a.txt"), b"from dir").unwrap();
+        fs::write(dir.join("textures/b.txt"), b"only in dir").unwrap();
+
+        let pack_path = dir.join("test.epak");
+        let repeated = vec![b'x'; 4096];
+        let mut pack = File::create(&pack_path).unwrap();
+        write_pack(
+            &mut pack,
+            &[
+                ("a.txt".to_string(), b"from pack".to_vec()),
+                ("big.txt".to_string(), repeated.clone()),
+            ],
+            Compression::Deflate,
+        )
+        .unwrap();
+        drop(pack);
+
+        let mut vfs = Vfs::new();
+        vfs.mount_dir("", &dir).unwrap();
+        assert!(vfs.mount_pack("../textures", &pack_path).is_err());
+        vfs.mount_pack("textures", &pack_path).unwrap();
+
+        assert_eq!(vfs.read("textures/a.txt").unwrap(), b"from pack");
+        assert_eq!(vfs.read("/textures/b.txt").unwrap(), b"only in dir");
+        assert_eq!(vfs.read("textures/big.txt").unwrap(), repeated);
+        assert!(vfs.real_path("textures/a.txt").is_none());
+        assert!(vfs.real_path("textures/b.txt").is_some());
+        assert!(vfs.read("textures/missing.txt").is_err());
+        assert!(fs::metadata(&pack_path).unwrap().len() < 4096);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_pack_entries_are_errors() {
+        let dir = std::env::temp_dir().join(format!("enegine-vfs-corrupt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pack_path = dir.join("test.epak");
+
+        let mut pack = io::Cursor::new(Vec::new());
+        write_pack(
+            &mut pack,
+            &[("a.txt".to_string(), vec![b'x'; 4096])],
+            Compression::Deflate,
+        )
+        .unwrap();
+        let bytes = pack.into_inner();
+        // The only entry's stored size and size, after its path length, path and offset
+        let index = read_u64(&mut &bytes[12..20]).unwrap() as usize;
+        let stored_size = index + 2 + 5 + 8;
+        let size = stored_size + 8;
+
+        let open = |offset: usize, value: u64| {
+            let mut corrupt = bytes.clone();
+            corrupt[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            fs::write(&pack_path, &corrupt).unwrap();
+            PackMount::open(&pack_path)
+        };
+        assert!(open(stored_size, u64::MAX).is_err());
+        assert!(open(12, u64::MAX).is_err());
+        // Inflates to more, or less, than the entry says
+        let pack = open(size, 16).unwrap();
+        assert!(pack.read("a.txt").unwrap().is_err());
+        let pack = open(size, 4097).unwrap();
+        assert!(pack.read("a.txt").unwrap().is_err());
+        assert!(open(size, u64::MAX).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
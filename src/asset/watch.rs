@@ -0,0 +1,147 @@
+// Polls watched assets for changes and rebakes them on a background thread. Polling goes
+// through the VFS so it works the same for plain directories and packs.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use super::format;
+use super::vfs::Vfs;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssetKind {
+    Mesh,
+    Texture,
+    Shader,
+}
+
+impl AssetKind {
+    fn baked_extension(self) -> Option<&'static str> {
+        match self {
+            AssetKind::Mesh => Some(format::MESH_EXTENSION),
+            AssetKind::Texture => Some(format::TEXTURE_EXTENSION),
+            AssetKind::Shader => None,
+        }
+    }
+
+    fn load(self, vfs: &Vfs, path: &str) -> Result<Vec<u8>, &'static str> {
+        match self {
+            AssetKind::Mesh => super::load_baked_mesh(vfs, path),
+            AssetKind::Texture => super::load_baked_texture(vfs, path),
+            // Shaders are recompiled by whoever owns the variants, they just need to know
+            AssetKind::Shader => vfs.read(path),
+        }
+    }
+}
+
+pub struct Reloaded {
+    pub path: String,
+    pub kind: AssetKind,
+    // Baked bytes, same as load_baked_mesh/load_baked_texture would return. Source for shaders.
+    pub result: Result<Vec<u8>, &'static str>,
+}
+
+struct Watched {
+    kind: AssetKind,
+    modified: Option<SystemTime>,
+}
+
+pub struct AssetWatcher {
+    watched: Arc<Mutex<HashMap<String, Watched>>>,
+    vfs: Arc<Vfs>,
+    receiver: Receiver<Reloaded>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Newest of the source and its baked version, either one changing means a reload
+fn modified(vfs: &Vfs, path: &str, kind: AssetKind) -> Option<SystemTime> {
+    let baked = kind
+        .baked_extension()
+        .and_then(|extension| vfs.modified(&super::baked_path(path, extension)));
+    vfs.modified(path).max(baked)
+}
+
+impl AssetWatcher {
+    pub fn new(vfs: Arc<Vfs>, interval: Duration) -> Self {
+        let watched: Arc<Mutex<HashMap<String, Watched>>> = Arc::new(Mutex::new(HashMap::new()));
+        let running = Arc::new(AtomicBool::new(true));
+        let (sender, receiver) = mpsc::channel();
+
+        let thread = {
+            let watched = watched.clone();
+            let running = running.clone();
+            let vfs = vfs.clone();
+            thread::Builder::new()
+                .name("asset watcher".to_string())
+                .spawn(move || {
+                    while running.load(Ordering::Relaxed) {
+                        thread::sleep(interval);
+
+                        let changed: Vec<(String, AssetKind)> = {
+                            let mut watched = watched.lock().unwrap();
+                            watched
+                                .iter_mut()
+                                .filter_map(|(path, w)| {
+                                    let current = modified(&vfs, path, w.kind);
+                                    if current != w.modified {
+                                        w.modified = current;
+                                        Some((path.clone(), w.kind))
+                                    } else {
+                                        None
+                                    }
+                                })
+                                .collect()
+                        };
+
+                        // Importing is the slow part, keep it off the render thread
+                        for (path, kind) in changed {
+                            info!("Reloading {}", path);
+                            let result = kind.load(&vfs, &path);
+                            if sender.send(Reloaded { path, kind, result }).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                })
+                .unwrap()
+        };
+
+        AssetWatcher {
+            watched,
+            vfs,
+            receiver,
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn watch(&self, path: &str, kind: AssetKind) {
+        let modified = modified(&self.vfs, path, kind);
+        self.watched
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), Watched { kind, modified });
+    }
+
+    pub fn unwatch(&self, path: &str) {
+        self.watched.lock().unwrap().remove(path);
+    }
+
+    // Reloads finished since the last call, never blocks
+    pub fn poll(&self) -> Vec<Reloaded> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for AssetWatcher {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
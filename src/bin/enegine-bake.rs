@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+use enegine::asset::{
+    self, format, import,
+    vfs::{self, Compression, Vfs},
+};
+
+const USAGE: &str = "Usage: enegine-bake [-o OUTPUT] INPUT...
+       enegine-bake --pack DIR -o OUTPUT [-z]
+Bakes OBJ/glTF meshes and PNG/JPG textures for the runtime.
+Output goes next to the input with a .emesh/.etex extension unless -o is given.
+--pack puts every file under DIR into an .epak asset pack, -z compresses its entries.";
+
+fn bake(input: &Path, output: Option<&Path>) -> Result<PathBuf, &'static str> {
+    let name = input
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Invalid input path")?;
+    let mut vfs = Vfs::new();
+    vfs.mount_dir("", input.parent().unwrap_or_else(|| Path::new(".")))
+        .map_err(|_| "Invalid input path")?;
+
+    let (bytes, baked_extension) = match import::extension(name).as_deref() {
+        Some("obj") | Some("gltf") | Some("glb") => {
+            (asset::bake_mesh(&vfs, name)?, format::MESH_EXTENSION)
+        }
+        Some("png") | Some("jpg") | Some("jpeg") => {
+            (asset::bake_texture(&vfs, name)?, format::TEXTURE_EXTENSION)
+        }
+        _ => return Err("Unsupported source format"),
+    };
+
+    let output = output.map_or_else(|| input.with_extension(baked_extension), Path::to_path_buf);
+    fs::write(&output, bytes).map_err(|_| "Failed to write baked asset")?;
+    Ok(output)
+}
+
+fn pack(dir: &Path, output: &Path, compression: Compression) -> Result<usize, &'static str> {
+    let paths = vfs::collect_files(dir).map_err(|_| "Failed to list pack directory")?;
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let data = fs::read(dir.join(&path)).map_err(|_| "Failed to read pack input")?;
+        files.push((path, data));
+    }
+
+    let mut out = fs::File::create(output).map_err(|_| "Failed to create pack")?;
+    vfs::write_pack(&mut out, &files, compression).map_err(|_| "Failed to write pack")?;
+    Ok(files.len())
+}
+
+fn main() {
+    env_logger::init();
+
+    let mut output = None;
+    let mut pack_dir = None;
+    let mut compression = Compression::None;
+    let mut inputs = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next().map(PathBuf::from),
+            "--pack" => pack_dir = args.next().map(PathBuf::from),
+            "-z" => compression = Compression::Deflate,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if let Some(dir) = pack_dir {
+        let output = match output {
+            Some(o) if inputs.is_empty() => o,
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        };
+        match pack(&dir, &output, compression) {
+            Ok(count) => println!("{} files -> {}", count, output.display()),
+            Err(e) => {
+                eprintln!("{}: {}", dir.display(), e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if inputs.is_empty() || (output.is_some() && inputs.len() > 1) {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let mut failed = false;
+    for input in inputs.iter() {
+        match bake(input, output.as_deref()) {
+            Ok(baked) => println!("{} -> {}", input.display(), baked.display()),
+            Err(e) => {
+                eprintln!("{}: {}", input.display(), e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
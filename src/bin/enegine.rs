@@ -1,13 +1,51 @@
 #[macro_use]
 extern crate log;
 
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use enegine::asset::vfs::Vfs;
 use enegine::render::renderer;
 
+use glam::Mat4;
 use winit::{event_loop::EventLoop, window};
 
+fn mount_assets() -> Vfs {
+    let mut vfs = Vfs::new();
+
+    // Straight from the source tree while developing
+    if cfg!(debug_assertions) {
+        vfs.mount_dir("", concat!(env!("CARGO_MANIFEST_DIR"), "/src/bin"))
+            .unwrap();
+    }
+
+    // Shipping layout, assets/ and assets.epak next to the executable
+    if let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+    {
+        let pack = exe_dir.join("assets.epak");
+        if pack.is_file() {
+            if let Err(e) = vfs.mount_pack("", &pack) {
+                error!("Failed to mount {}: {}", pack.display(), e);
+            }
+        }
+        let dir = exe_dir.join("assets");
+        if dir.is_dir() {
+            if let Err(e) = vfs.mount_dir("", &dir) {
+                error!("Failed to mount {}: {}", dir.display(), e);
+            }
+        }
+    }
+
+    vfs
+}
+
 fn main() {
     env_logger::init();
 
+    let vfs = Arc::new(mount_assets());
 
     let event_loop = EventLoop::new();
     let window = window::WindowBuilder::new()
@@ -15,7 +53,10 @@ fn main() {
         .build(&event_loop)
         .unwrap();
 
-    let mut renderer = renderer::Renderer::new(&window).unwrap();
+    let mut renderer = renderer::Renderer::new(&window, vfs).unwrap();
+    if cfg!(debug_assertions) {
+        renderer.watch_assets(Duration::from_millis(500));
+    }
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = winit::event_loop::ControlFlow::Poll;
@@ -46,8 +87,13 @@ fn main() {
             },
             winit::event::Event::MainEventsCleared => window.request_redraw(),
             winit::event::Event::RedrawRequested(_) => {
-                // TODO: Render
-                renderer.render();
+                renderer.begin_frame();
+                renderer.draw(
+                    renderer.model(),
+                    renderer.default_material(),
+                    Mat4::identity(),
+                );
+                renderer.end_frame();
             }
             _ => {}
         }
@@ -0,0 +1,12 @@
+// The bindless texture set, mirrors BindlessTextures in bindless.rs. Needs
+// GL_EXT_nonuniform_qualifier enabled by the including shader.
+
+layout(set = 1, binding = 0) uniform texture2D textures[];
+layout(set = 1, binding = 1) uniform sampler samplers[];
+
+// Sampler 0 is the renderer's default one
+vec4 sample_texture(uint texture_index, uint sampler_index, vec2 uv) {
+    return texture(
+        sampler2D(textures[nonuniformEXT(texture_index)], samplers[nonuniformEXT(sampler_index)]),
+        uv);
+}
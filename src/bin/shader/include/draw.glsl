@@ -0,0 +1,5 @@
+// Per batch data pushed with every instanced draw, mirrors DrawPushConstants in renderer.rs
+layout(push_constant) uniform DrawConstants {
+    uint material_index;
+    uint texture_index;
+} draw;
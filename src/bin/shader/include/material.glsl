@@ -0,0 +1,12 @@
+// Shared by the material fragment shaders
+
+const float ALPHA_CUTOFF = 0.5;
+
+// Only does anything in ALPHA_TEST variants
+void alpha_test(vec4 color) {
+#ifdef ALPHA_TEST
+    if (color.a < ALPHA_CUTOFF) {
+        discard;
+    }
+#endif
+}
@@ -1,13 +1,30 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : enable
+#ifdef BINDLESS
+#extension GL_EXT_nonuniform_qualifier : enable
+#endif
+
+#include <material.glsl>
+#ifdef BINDLESS
+#include <bindless.glsl>
+#include <draw.glsl>
+#endif
 
 layout(location = 0) in vec3 vertex_color;
 layout(location = 1) in vec2 tex_coord;
 
 layout(location = 0) out vec4 frag_color;
 
+#ifndef BINDLESS
 layout(binding = 1) uniform sampler2D tex_sampler;
+#endif
 
 void main() {
+#ifdef BINDLESS
+    frag_color = sample_texture(draw.texture_index, 0, tex_coord);
+#else
     frag_color = texture(tex_sampler, tex_coord);
+#endif
+    alpha_test(frag_color);
 }
@@ -1,8 +1,8 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : enable
 
 layout(binding = 0) uniform UniformBufferObject {
-    mat4 model;
     mat4 view;
     mat4 proj;
 } ubo;
@@ -10,6 +10,8 @@ layout(binding = 0) uniform UniformBufferObject {
 layout(location = 0) in vec3 in_position;
 layout(location = 1) in vec3 in_color;
 layout(location = 2) in vec2 in_tex_coord;
+// Per instance, see InstanceData in renderer.rs
+layout(location = 5) in mat4 in_model;
 
 layout(location = 0) out vec3 out_color;
 layout(location = 1) out vec2 out_tex_coord;
@@ -21,6 +23,6 @@ out gl_PerVertex {
 void main() {
     out_color = in_color;
     out_tex_coord = in_tex_coord;
-    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(in_position, 1.0);
+    gl_Position = ubo.proj * ubo.view * in_model * vec4(in_position, 1.0);
     gl_Position.y = -gl_Position.y;
 }
@@ -3,6 +3,12 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
+// So #[derive(VertexLayout)] can name this crate from inside it too
+extern crate self as enegine;
+
+// The ash types in the public API, and what derives name so users don't need the same ash version
+pub use ash;
+
 macro_rules! to_cstr {
     ($s:literal) => {{
         #[allow(unused_unsafe)]
@@ -12,19 +18,9 @@ macro_rules! to_cstr {
     }};
 }
 
-macro_rules! offset_of {
-    ($base:path, $field:ident) => {{
-        #[allow(unused_unsafe)]
-        unsafe {
-            let b: $base = std::mem::zeroed();
-            (&b.$field as *const _ as isize) - (&b as *const _ as isize)
-        }
-    }};
-}
-
+pub mod asset;
 pub mod render;
 
-
 #[cfg(test)]
 mod tests {
     #[test]
@@ -0,0 +1,255 @@
+// Barriers described by how a resource was used before and how it's used next, instead of raw
+// stage/access/layout triples that are easy to get subtly wrong.
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Access {
+    // Contents don't matter, e.g. the first use of a fresh image
+    Nothing,
+    // A swapchain image that was just acquired. Its contents don't matter either, but the
+    // acquire semaphore is waited on at COLOR_ATTACHMENT_OUTPUT so that's where barriers start.
+    Acquire,
+    TransferRead,
+    TransferWrite,
+    VertexBuffer,
+    IndexBuffer,
+    IndirectBuffer,
+    // Uniform buffers and sampled images
+    VertexShaderRead,
+    FragmentShaderRead,
+    ComputeShaderRead,
+    // Storage buffers and storage images
+    ComputeStorageRead,
+    ComputeStorageWrite,
+    ColorAttachmentWrite,
+    DepthAttachmentWrite,
+    Present,
+}
+
+impl Access {
+    pub fn stage(self) -> vk::PipelineStageFlags {
+        match self {
+            Access::Nothing => vk::PipelineStageFlags::TOP_OF_PIPE,
+            Access::Acquire => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Access::TransferRead | Access::TransferWrite => vk::PipelineStageFlags::TRANSFER,
+            Access::VertexBuffer | Access::IndexBuffer => vk::PipelineStageFlags::VERTEX_INPUT,
+            Access::IndirectBuffer => vk::PipelineStageFlags::DRAW_INDIRECT,
+            Access::VertexShaderRead => vk::PipelineStageFlags::VERTEX_SHADER,
+            Access::FragmentShaderRead => vk::PipelineStageFlags::FRAGMENT_SHADER,
+            Access::ComputeShaderRead
+            | Access::ComputeStorageRead
+            | Access::ComputeStorageWrite => vk::PipelineStageFlags::COMPUTE_SHADER,
+            Access::ColorAttachmentWrite => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Access::DepthAttachmentWrite => {
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            Access::Present => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        }
+    }
+
+    pub fn access(self) -> vk::AccessFlags {
+        match self {
+            Access::Nothing | Access::Acquire | Access::Present => vk::AccessFlags::empty(),
+            Access::TransferRead => vk::AccessFlags::TRANSFER_READ,
+            Access::TransferWrite => vk::AccessFlags::TRANSFER_WRITE,
+            Access::VertexBuffer => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            Access::IndexBuffer => vk::AccessFlags::INDEX_READ,
+            Access::IndirectBuffer => vk::AccessFlags::INDIRECT_COMMAND_READ,
+            Access::VertexShaderRead | Access::FragmentShaderRead | Access::ComputeShaderRead => {
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::UNIFORM_READ
+            }
+            Access::ComputeStorageRead => vk::AccessFlags::SHADER_READ,
+            Access::ComputeStorageWrite => vk::AccessFlags::SHADER_WRITE,
+            Access::ColorAttachmentWrite => {
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            }
+            Access::DepthAttachmentWrite => {
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+        }
+    }
+
+    // Layout an image has to be in for this access, buffers ignore it
+    pub fn image_layout(self) -> vk::ImageLayout {
+        match self {
+            Access::Nothing | Access::Acquire => vk::ImageLayout::UNDEFINED,
+            Access::TransferRead => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Access::TransferWrite => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Access::VertexShaderRead | Access::FragmentShaderRead | Access::ComputeShaderRead => {
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            }
+            Access::ComputeStorageRead | Access::ComputeStorageWrite => vk::ImageLayout::GENERAL,
+            Access::ColorAttachmentWrite => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Access::DepthAttachmentWrite => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Access::Present => vk::ImageLayout::PRESENT_SRC_KHR,
+            Access::VertexBuffer | Access::IndexBuffer | Access::IndirectBuffer => {
+                vk::ImageLayout::UNDEFINED
+            }
+        }
+    }
+
+    pub fn is_write(self) -> bool {
+        matches!(
+            self,
+            Access::TransferWrite
+                | Access::ComputeStorageWrite
+                | Access::ColorAttachmentWrite
+                | Access::DepthAttachmentWrite
+        )
+    }
+
+    // Only writes have to be made available, after a read an execution dependency is enough
+    fn src_access(self) -> vk::AccessFlags {
+        if self.is_write() {
+            self.access()
+        } else {
+            vk::AccessFlags::empty()
+        }
+    }
+}
+
+// Depth/stencil formats get both aspects where they have both
+pub fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32 => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+// Covers every buffer and image at once, cheaper than a pile of buffer barriers
+pub fn memory_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    from: Access,
+    to: Access,
+) {
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(from.src_access())
+        .dst_access_mask(to.access())
+        .build();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            from.stage(),
+            to.stage(),
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[],
+            &[],
+        );
+    }
+}
+
+pub fn buffer_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    from: Access,
+    to: Access,
+) {
+    let barrier = vk::BufferMemoryBarrier::builder()
+        .src_access_mask(from.src_access())
+        .dst_access_mask(to.access())
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE)
+        .build();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            from.stage(),
+            to.stage(),
+            vk::DependencyFlags::empty(),
+            &[],
+            &[barrier],
+            &[],
+        );
+    }
+}
+
+// Also transitions the layout. Going from Nothing throws the old contents away.
+pub fn image_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    from: Access,
+    to: Access,
+) {
+    cmd_image_barrier(
+        device,
+        command_buffer,
+        image,
+        range,
+        from,
+        to,
+        from.image_layout(),
+    );
+}
+
+// Still waits for `from` to finish, but throws the contents away instead of transitioning them.
+// For memory that something else used before, e.g. aliased transient attachments.
+pub fn image_discard_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    from: Access,
+    to: Access,
+) {
+    cmd_image_barrier(
+        device,
+        command_buffer,
+        image,
+        range,
+        from,
+        to,
+        vk::ImageLayout::UNDEFINED,
+    );
+}
+
+fn cmd_image_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    range: vk::ImageSubresourceRange,
+    from: Access,
+    to: Access,
+    old_layout: vk::ImageLayout,
+) {
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .src_access_mask(from.src_access())
+        .dst_access_mask(to.access())
+        .old_layout(old_layout)
+        .new_layout(to.image_layout())
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(range)
+        .build();
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            from.stage(),
+            to.stage(),
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+    }
+}
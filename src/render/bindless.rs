@@ -0,0 +1,397 @@
+// One descriptor set holding every loaded texture, built on VK_EXT_descriptor_indexing. Textures
+// are registered once when they're loaded and shaders pick them by index, from push constants or
+// a material buffer, so materials don't need descriptor sets of their own. Shaders see it as set
+// 1, see shader/include/bindless.glsl.
+
+use std::ffi::CStr;
+use std::os::raw::c_void;
+use std::ptr;
+
+use ash::version::{DeviceV1_0, EntryV1_0, InstanceV1_0};
+use ash::vk;
+
+use super::reflect::ResourceLayout;
+
+pub const SET: u32 = 1;
+pub const TEXTURE_BINDING: u32 = 0;
+pub const SAMPLER_BINDING: u32 = 1;
+
+// Plenty for now, clamped to what the device allows
+const MAX_TEXTURES: u32 = 16 * 1024;
+const MAX_SAMPLERS: u32 = 32;
+
+// VK_EXT_descriptor_indexing and what it depends on before Vulkan 1.2. They also need
+// VK_KHR_get_physical_device_properties2 on the instance.
+pub fn extension_names() -> [&'static CStr; 2] {
+    [
+        vk::ExtDescriptorIndexingFn::name(),
+        vk::KhrMaintenance3Fn::name(),
+    ]
+}
+
+// Chained into DeviceCreateInfo, just the features the set and its shaders use
+pub fn features() -> vk::PhysicalDeviceDescriptorIndexingFeatures {
+    vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+        .shader_sampled_image_array_non_uniform_indexing(true)
+        .descriptor_binding_sampled_image_update_after_bind(true)
+        .descriptor_binding_update_unused_while_pending(true)
+        .descriptor_binding_partially_bound(true)
+        .runtime_descriptor_array(true)
+        .build()
+}
+
+// How many textures and samplers the set can hold, None if the device lacks any of the features.
+// The instance needs VK_KHR_get_physical_device_properties2.
+pub fn query_capacity(
+    entry: &ash::Entry,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Option<(u32, u32)> {
+    let fns = vk::KhrGetPhysicalDeviceProperties2Fn::load(|name| unsafe {
+        entry
+            .get_instance_proc_addr(instance.handle(), name.as_ptr())
+            .map_or(ptr::null(), |f| f as *const c_void)
+    });
+
+    let mut indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    // ash has no push_next for this one
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut indexing as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { fns.get_physical_device_features2_khr(physical_device, &mut features) };
+    let supported = indexing.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+        && indexing.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+        && indexing.descriptor_binding_update_unused_while_pending == vk::TRUE
+        && indexing.descriptor_binding_partially_bound == vk::TRUE
+        && indexing.runtime_descriptor_array == vk::TRUE;
+    if !supported {
+        return None;
+    }
+
+    let mut limits = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder()
+        .push_next(&mut limits)
+        .build();
+    unsafe { fns.get_physical_device_properties2_khr(physical_device, &mut properties) };
+    let textures = MAX_TEXTURES
+        .min(limits.max_descriptor_set_update_after_bind_sampled_images)
+        .min(limits.max_per_stage_descriptor_update_after_bind_sampled_images);
+    let samplers = MAX_SAMPLERS
+        .min(limits.max_descriptor_set_update_after_bind_samplers)
+        .min(limits.max_per_stage_descriptor_update_after_bind_samplers);
+    // Both arrays count against the per stage total
+    let textures = textures.min(
+        limits
+            .max_per_stage_update_after_bind_resources
+            .saturating_sub(samplers),
+    );
+    if textures == 0 || samplers == 0 {
+        return None;
+    }
+    Some((textures, samplers))
+}
+
+// Shaders may only use the two arrays in the bindless set, anything else there has no descriptor
+pub fn check_layout(layout: &ResourceLayout) -> Result<(), &'static str> {
+    for binding in layout.bindings.iter().filter(|b| b.set == SET) {
+        let expected = match binding.binding {
+            TEXTURE_BINDING => vk::DescriptorType::SAMPLED_IMAGE,
+            SAMPLER_BINDING => vk::DescriptorType::SAMPLER,
+            _ => vk::DescriptorType::default(),
+        };
+        if binding.descriptor_type != expected {
+            error!(
+                "Set {} binding {} is {:?}, the bindless set has textures at {} and samplers at {}",
+                SET, binding.binding, binding.descriptor_type, TEXTURE_BINDING, SAMPLER_BINDING
+            );
+            return Err("Shader doesn't match the bindless descriptor set");
+        }
+    }
+    Ok(())
+}
+
+// Which array elements are in use. Released ones only come back once every frame that might
+// still read them is done.
+struct Slots {
+    capacity: u32,
+    next: u32,
+    free: Vec<u32>,
+    // Slot and the last frame that may use it
+    retired: Vec<(u32, u64)>,
+}
+
+impl Slots {
+    fn new(capacity: u32) -> Self {
+        Slots {
+            capacity,
+            next: 0,
+            free: Vec::new(),
+            retired: Vec::new(),
+        }
+    }
+
+    fn allocate(&mut self) -> Option<u32> {
+        if let Some(slot) = self.free.pop() {
+            return Some(slot);
+        }
+        if self.next < self.capacity {
+            self.next += 1;
+            Some(self.next - 1)
+        } else {
+            None
+        }
+    }
+
+    fn release(&mut self, slot: u32, frame: u64) {
+        self.retired.push((slot, frame));
+    }
+
+    fn collect(&mut self, completed_frame: u64) {
+        let free = &mut self.free;
+        self.retired.retain(|&(slot, frame)| {
+            if frame <= completed_frame {
+                free.push(slot);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+pub struct BindlessTextures {
+    layout: vk::DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: vk::DescriptorSet,
+    textures: Slots,
+    samplers: Slots,
+}
+
+impl BindlessTextures {
+    // Only for devices created with the extensions and `features`, sized by `query_capacity`
+    pub fn new(
+        device: &ash::Device,
+        (max_textures, max_samplers): (u32, u32),
+    ) -> Result<Self, &'static str> {
+        let stages = vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE;
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(TEXTURE_BINDING)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(max_textures)
+                .stage_flags(stages)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(SAMPLER_BINDING)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(max_samplers)
+                .stage_flags(stages)
+                .build(),
+        ];
+        // Unwritten elements are fine as long as nothing reads them, and elements can be written
+        // while frames using the others are in flight
+        let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+            2];
+        let mut flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(&binding_flags);
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .bindings(&bindings)
+            .push_next(&mut flags_info);
+        let layout = match unsafe { device.create_descriptor_set_layout(&layout_info, None) } {
+            Ok(layout) => layout,
+            Err(e) => {
+                error!("Failed to create bindless set layout: {}", e);
+                return Err("Failed to create bindless set layout");
+            }
+        };
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: max_textures,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: max_samplers,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND)
+            .pool_sizes(&pool_sizes)
+            .max_sets(1);
+        let pool = match unsafe { device.create_descriptor_pool(&pool_info, None) } {
+            Ok(pool) => pool,
+            Err(e) => {
+                error!("Failed to create bindless descriptor pool: {}", e);
+                unsafe { device.destroy_descriptor_set_layout(layout, None) };
+                return Err("Failed to create bindless descriptor pool");
+            }
+        };
+
+        let layouts = [layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(&layouts);
+        let set = match unsafe { device.allocate_descriptor_sets(&alloc_info) } {
+            Ok(sets) => sets[0],
+            Err(e) => {
+                error!("Failed to allocate bindless descriptor set: {}", e);
+                unsafe {
+                    device.destroy_descriptor_pool(pool, None);
+                    device.destroy_descriptor_set_layout(layout, None);
+                }
+                return Err("Failed to allocate bindless descriptor set");
+            }
+        };
+
+        Ok(BindlessTextures {
+            layout,
+            pool,
+            set,
+            textures: Slots::new(max_textures),
+            samplers: Slots::new(max_samplers),
+        })
+    }
+
+    pub fn layout(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+
+    pub fn set(&self) -> vk::DescriptorSet {
+        self.set
+    }
+
+    // Index shaders read the texture at, the view has to be in SHADER_READ_ONLY_OPTIMAL
+    pub fn register_texture(
+        &mut self,
+        device: &ash::Device,
+        view: vk::ImageView,
+    ) -> Result<u32, &'static str> {
+        let index = self
+            .textures
+            .allocate()
+            .ok_or("Bindless texture array is full")?;
+        self.update_texture(device, index, view);
+        Ok(index)
+    }
+
+    // Points an index at another view, e.g. a reloaded texture. Nothing in flight may be using the
+    // index, e.g. after FrameSync::wait_all.
+    pub fn update_texture(&self, device: &ash::Device, index: u32, view: vk::ImageView) {
+        let image_info = [vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        self.write(device, TEXTURE_BINDING, index, &image_info);
+    }
+
+    // `frame` is the last frame that may sample it, the index is reused once that's done
+    pub fn release_texture(&mut self, index: u32, frame: u64) {
+        self.textures.release(index, frame);
+    }
+
+    pub fn register_sampler(
+        &mut self,
+        device: &ash::Device,
+        sampler: vk::Sampler,
+    ) -> Result<u32, &'static str> {
+        let index = self
+            .samplers
+            .allocate()
+            .ok_or("Bindless sampler array is full")?;
+        let image_info = [vk::DescriptorImageInfo {
+            sampler,
+            image_view: vk::ImageView::null(),
+            image_layout: vk::ImageLayout::UNDEFINED,
+        }];
+        self.write(device, SAMPLER_BINDING, index, &image_info);
+        Ok(index)
+    }
+
+    pub fn release_sampler(&mut self, index: u32, frame: u64) {
+        self.samplers.release(index, frame);
+    }
+
+    // Makes released indices whose frames are done available again
+    pub fn collect(&mut self, completed_frame: u64) {
+        self.textures.collect(completed_frame);
+        self.samplers.collect(completed_frame);
+    }
+
+    fn write(
+        &self,
+        device: &ash::Device,
+        binding: u32,
+        index: u32,
+        image_info: &[vk::DescriptorImageInfo],
+    ) {
+        let descriptor_type = if binding == TEXTURE_BINDING {
+            vk::DescriptorType::SAMPLED_IMAGE
+        } else {
+            vk::DescriptorType::SAMPLER
+        };
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(binding)
+            .dst_array_element(index)
+            .descriptor_type(descriptor_type)
+            .image_info(image_info)
+            .build();
+        unsafe { device.update_descriptor_sets(&[write], &[]) };
+    }
+
+    // The caller makes sure the GPU is done with the set
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            device.destroy_descriptor_pool(self.pool, None);
+            device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::reflect::DescriptorBinding;
+
+    #[test]
+    fn released_slots_wait_for_their_frame() {
+        let mut slots = Slots::new(2);
+        assert_eq!(slots.allocate(), Some(0));
+        assert_eq!(slots.allocate(), Some(1));
+        assert_eq!(slots.allocate(), None);
+
+        slots.release(0, 5);
+        slots.collect(4);
+        assert_eq!(slots.allocate(), None);
+        slots.collect(5);
+        assert_eq!(slots.allocate(), Some(0));
+    }
+
+    #[test]
+    fn layout_has_to_match_the_set() {
+        let binding = |binding, descriptor_type| DescriptorBinding {
+            set: SET,
+            binding,
+            descriptor_type,
+            count: 0,
+            stages: vk::ShaderStageFlags::FRAGMENT,
+        };
+        let mut layout = ResourceLayout {
+            bindings: vec![
+                binding(TEXTURE_BINDING, vk::DescriptorType::SAMPLED_IMAGE),
+                binding(SAMPLER_BINDING, vk::DescriptorType::SAMPLER),
+            ],
+            push_constants: None,
+        };
+        assert!(check_layout(&layout).is_ok());
+
+        layout.bindings[1].descriptor_type = vk::DescriptorType::COMBINED_IMAGE_SAMPLER;
+        assert!(check_layout(&layout).is_err());
+    }
+}
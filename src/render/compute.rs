@@ -0,0 +1,191 @@
+// Compute pipelines, with their layouts built from reflecting the shader the same way the
+// graphics pipelines are. Barriers between dispatches and draws are in barrier.rs.
+
+use std::ffi::CString;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use super::descriptor::DescriptorAllocator;
+use super::pipeline::{self, PipelineCache};
+use super::reflect::{self, ResourceLayout};
+use super::shader;
+
+pub struct ComputePipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    // One per set the shader uses, in set order. Owned by the DescriptorAllocator's layout cache.
+    pub set_layouts: Vec<vk::DescriptorSetLayout>,
+    resource_layout: ResourceLayout,
+}
+
+impl ComputePipeline {
+    pub fn new(
+        device: &ash::Device,
+        cache: &PipelineCache,
+        descriptors: &mut DescriptorAllocator,
+        spirv: &[u32],
+        limits: &vk::PhysicalDeviceLimits,
+    ) -> Result<Self, &'static str> {
+        let reflection = reflect::reflect(spirv)?;
+        if reflection.stage != vk::ShaderStageFlags::COMPUTE {
+            return Err("Not a compute shader");
+        }
+        let resource_layout = ResourceLayout::merge(&[&reflection])?;
+        resource_layout.check_push_constants(limits)?;
+
+        let set_layouts = (0..resource_layout.set_count())
+            .map(|set| descriptors.set_layout(device, &resource_layout.set_layout_bindings(set)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let push_constant_ranges = resource_layout.push_constant_ranges();
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let layout = unsafe { device.create_pipeline_layout(&layout_info, None) }
+            .map_err(|_| "Failed to create pipeline layout")?;
+
+        // The module is only needed while creating the pipeline
+        let entry_point = CString::new(reflection.entry_point.as_str())
+            .map_err(|_| "Shader entry point contains a nul");
+        let pipeline = entry_point.and_then(|entry_point| {
+            let module = shader::create_module(device, spirv)?;
+            let stage = vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(module)
+                .name(&entry_point)
+                .build();
+            let pipeline_info = [vk::ComputePipelineCreateInfo::builder()
+                .stage(stage)
+                .layout(layout)
+                .build()];
+            let pipelines = unsafe {
+                let pipelines =
+                    device.create_compute_pipelines(cache.vk_cache(), &pipeline_info, None);
+                device.destroy_shader_module(module, None);
+                pipelines
+            };
+            pipelines
+                .map(|p| p[0])
+                .map_err(|_| "Failed to create compute pipeline")
+        });
+
+        match pipeline {
+            Ok(pipeline) => Ok(ComputePipeline {
+                pipeline,
+                layout,
+                set_layouts,
+                resource_layout,
+            }),
+            Err(e) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                Err(e)
+            }
+        }
+    }
+
+    // `count` descriptor sets for `set`, from the allocator's persistent pools
+    pub fn allocate_descriptor_sets(
+        &self,
+        device: &ash::Device,
+        descriptors: &mut DescriptorAllocator,
+        set: u32,
+        count: u32,
+    ) -> Result<Vec<vk::DescriptorSet>, &'static str> {
+        let set_layout = *self
+            .set_layouts
+            .get(set as usize)
+            .ok_or("Compute shader doesn't use that descriptor set")?;
+        (0..count)
+            .map(|_| descriptors.allocate(device, set_layout))
+            .collect()
+    }
+
+    // Binds the pipeline and `sets` starting at set 0
+    pub fn cmd_bind(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        sets: &[vk::DescriptorSet],
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            if !sets.is_empty() {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.layout,
+                    0,
+                    sets,
+                    &[],
+                );
+            }
+        }
+    }
+
+    // `data` mirrors the shader's push constant block
+    pub fn cmd_push_constants<T: Copy>(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        data: &T,
+    ) {
+        for range in self.resource_layout.push_constant_ranges() {
+            pipeline::cmd_push_constants(device, command_buffer, self.layout, &range, data);
+        }
+    }
+
+    pub fn cmd_dispatch(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        sets: &[vk::DescriptorSet],
+        group_count: [u32; 3],
+    ) {
+        self.cmd_bind(device, command_buffer, sets);
+        unsafe {
+            device.cmd_dispatch(
+                command_buffer,
+                group_count[0],
+                group_count[1],
+                group_count[2],
+            );
+        }
+    }
+
+    // Group counts come from a VkDispatchIndirectCommand in `buffer`, e.g. written by an earlier
+    // dispatch. That needs a ComputeStorageWrite -> IndirectBuffer barrier in between.
+    pub fn cmd_dispatch_indirect(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        sets: &[vk::DescriptorSet],
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+    ) {
+        self.cmd_bind(device, command_buffer, sets);
+        unsafe { device.cmd_dispatch_indirect(command_buffer, buffer, offset) };
+    }
+
+    // The caller makes sure the GPU is done with it. Its descriptor sets and set layouts stay with
+    // the DescriptorAllocator.
+    pub fn destroy(self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+// Workgroups needed to cover `size` invocations with `local_size` sized groups
+pub fn group_count(size: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    [
+        size[0].div_ceil(local_size[0]),
+        size[1].div_ceil(local_size[1]),
+        size[2].div_ceil(local_size[2]),
+    ]
+}
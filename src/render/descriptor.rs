@@ -0,0 +1,438 @@
+// Writing descriptor sets without spelling out a vk::WriteDescriptorSet for every binding, and
+// allocating them from pools that grow as needed.
+
+use std::collections::HashMap;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+// The first pool in a group has room for this many sets, every one after it twice the last
+const INITIAL_POOL_SETS: u32 = 32;
+const MAX_POOL_SETS: u32 = 4096;
+
+// Descriptors of each type a pool makes room for per set. Rough averages, sets that don't fit
+// just move on to the next, bigger pool.
+const POOL_RATIOS: [(vk::DescriptorType, u32); 9] = [
+    (vk::DescriptorType::UNIFORM_BUFFER, 2),
+    (vk::DescriptorType::STORAGE_BUFFER, 2),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1),
+    (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 1),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4),
+    (vk::DescriptorType::SAMPLED_IMAGE, 4),
+    (vk::DescriptorType::STORAGE_IMAGE, 1),
+    (vk::DescriptorType::SAMPLER, 1),
+    (vk::DescriptorType::INPUT_ATTACHMENT, 1),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptorResource {
+    UniformBuffer {
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    },
+    StorageBuffer {
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    },
+    // Read in SHADER_READ_ONLY_OPTIMAL
+    CombinedImageSampler {
+        view: vk::ImageView,
+        sampler: vk::Sampler,
+    },
+    SampledImage {
+        view: vk::ImageView,
+    },
+    // Read and written in GENERAL
+    StorageImage {
+        view: vk::ImageView,
+    },
+    Sampler {
+        sampler: vk::Sampler,
+    },
+}
+
+impl DescriptorResource {
+    pub fn descriptor_type(&self) -> vk::DescriptorType {
+        match self {
+            DescriptorResource::UniformBuffer { .. } => vk::DescriptorType::UNIFORM_BUFFER,
+            DescriptorResource::StorageBuffer { .. } => vk::DescriptorType::STORAGE_BUFFER,
+            DescriptorResource::CombinedImageSampler { .. } => {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+            DescriptorResource::SampledImage { .. } => vk::DescriptorType::SAMPLED_IMAGE,
+            DescriptorResource::StorageImage { .. } => vk::DescriptorType::STORAGE_IMAGE,
+            DescriptorResource::Sampler { .. } => vk::DescriptorType::SAMPLER,
+        }
+    }
+
+    fn buffer_info(&self) -> Option<vk::DescriptorBufferInfo> {
+        match *self {
+            DescriptorResource::UniformBuffer {
+                buffer,
+                offset,
+                range,
+            }
+            | DescriptorResource::StorageBuffer {
+                buffer,
+                offset,
+                range,
+            } => Some(vk::DescriptorBufferInfo {
+                buffer,
+                offset,
+                range,
+            }),
+            _ => None,
+        }
+    }
+
+    fn image_info(&self) -> Option<vk::DescriptorImageInfo> {
+        let (view, sampler, layout) = match *self {
+            DescriptorResource::CombinedImageSampler { view, sampler } => {
+                (view, sampler, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            }
+            DescriptorResource::SampledImage { view } => (
+                view,
+                vk::Sampler::null(),
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            DescriptorResource::StorageImage { view } => {
+                (view, vk::Sampler::null(), vk::ImageLayout::GENERAL)
+            }
+            DescriptorResource::Sampler { sampler } => {
+                (vk::ImageView::null(), sampler, vk::ImageLayout::UNDEFINED)
+            }
+            _ => return None,
+        };
+        Some(vk::DescriptorImageInfo {
+            sampler,
+            image_view: view,
+            image_layout: layout,
+        })
+    }
+}
+
+// One descriptor per (binding, resource), always array element 0
+pub fn write_descriptor_set(
+    device: &ash::Device,
+    set: vk::DescriptorSet,
+    writes: &[(u32, DescriptorResource)],
+) {
+    // The writes point into these, so they have to be complete before building any
+    let buffer_infos: Vec<[vk::DescriptorBufferInfo; 1]> = writes
+        .iter()
+        .map(|(_, r)| [r.buffer_info().unwrap_or_default()])
+        .collect();
+    let image_infos: Vec<[vk::DescriptorImageInfo; 1]> = writes
+        .iter()
+        .map(|(_, r)| [r.image_info().unwrap_or_default()])
+        .collect();
+
+    let descriptor_writes: Vec<vk::WriteDescriptorSet> = writes
+        .iter()
+        .enumerate()
+        .map(|(i, (binding, resource))| {
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(*binding)
+                .dst_array_element(0)
+                .descriptor_type(resource.descriptor_type());
+            if resource.buffer_info().is_some() {
+                write.buffer_info(&buffer_infos[i]).build()
+            } else {
+                write.image_info(&image_infos[i]).build()
+            }
+        })
+        .collect();
+
+    unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+}
+
+// Pool sizes for `sets` copies of a set with `bindings`, one entry per descriptor type
+pub fn pool_sizes(
+    bindings: &[vk::DescriptorSetLayoutBinding],
+    sets: u32,
+) -> Vec<vk::DescriptorPoolSize> {
+    let mut sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+    for binding in bindings {
+        let count = binding.descriptor_count * sets;
+        match sizes.iter_mut().find(|s| s.ty == binding.descriptor_type) {
+            Some(size) => size.descriptor_count += count,
+            None => sizes.push(vk::DescriptorPoolSize {
+                ty: binding.descriptor_type,
+                descriptor_count: count,
+            }),
+        }
+    }
+    sizes
+}
+
+// Everything about a binding that matters to its layout, in binding order
+type LayoutKey = Vec<(u32, vk::DescriptorType, u32, vk::ShaderStageFlags)>;
+
+fn layout_key(bindings: &[vk::DescriptorSetLayoutBinding]) -> LayoutKey {
+    let mut key: LayoutKey = bindings
+        .iter()
+        .map(|b| {
+            (
+                b.binding,
+                b.descriptor_type,
+                b.descriptor_count,
+                b.stage_flags,
+            )
+        })
+        .collect();
+    key.sort_by_key(|&(binding, ..)| binding);
+    key
+}
+
+// Size of the pool after `pools` others in the same group
+fn pool_max_sets(pools: usize) -> u32 {
+    INITIAL_POOL_SETS
+        .checked_shl(pools as u32)
+        .unwrap_or(MAX_POOL_SETS)
+        .min(MAX_POOL_SETS)
+}
+
+// Pools sets are allocated from one after the other. Once one is full the next is used, and
+// only when there's no next one is another created.
+#[derive(Default)]
+struct PoolGroup {
+    pools: Vec<vk::DescriptorPool>,
+    // Pools before this one are full
+    current: usize,
+}
+
+impl PoolGroup {
+    fn allocate(
+        &mut self,
+        device: &ash::Device,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, &'static str> {
+        loop {
+            let created = self.current == self.pools.len();
+            if created {
+                self.pools
+                    .push(create_pool(device, pool_max_sets(self.pools.len()))?);
+            }
+
+            let set_layouts = [layout];
+            let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.pools[self.current])
+                .set_layouts(&set_layouts);
+            match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+                Ok(sets) => return Ok(sets[0]),
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                    // Bigger pools can't help once an empty one of the largest size is too small
+                    if created && pool_max_sets(self.current) == MAX_POOL_SETS {
+                        return Err("Descriptor set doesn't fit in an empty pool");
+                    }
+                    self.current += 1;
+                }
+                Err(e) => {
+                    error!("Failed to allocate descriptor set: {}", e);
+                    return Err("Failed to allocate descriptor set");
+                }
+            }
+        }
+    }
+
+    // Frees every set allocated from the group at once
+    fn reset(&mut self, device: &ash::Device) {
+        for &pool in &self.pools {
+            unsafe {
+                device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+                    .unwrap();
+            }
+        }
+        self.current = 0;
+    }
+
+    fn destroy(&mut self, device: &ash::Device) {
+        for pool in self.pools.drain(..) {
+            unsafe { device.destroy_descriptor_pool(pool, None) };
+        }
+        self.current = 0;
+    }
+}
+
+fn create_pool(device: &ash::Device, max_sets: u32) -> Result<vk::DescriptorPool, &'static str> {
+    let pool_sizes: Vec<vk::DescriptorPoolSize> = POOL_RATIOS
+        .iter()
+        .map(|&(ty, ratio)| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: ratio * max_sets,
+        })
+        .collect();
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(max_sets);
+    unsafe { device.create_descriptor_pool(&pool_info, None) }.map_err(|e| {
+        error!("Failed to create descriptor pool: {}", e);
+        "Failed to create descriptor pool"
+    })
+}
+
+// Descriptor sets for the whole renderer. Long lived ones come from persistent pools and stay
+// until the allocator is destroyed, transient ones from the pools of a frame in flight that are
+// reset as a whole when the frame comes around again. Set layouts are cached by their bindings
+// and owned by the allocator too.
+pub struct DescriptorAllocator {
+    layouts: HashMap<LayoutKey, vk::DescriptorSetLayout>,
+    persistent: PoolGroup,
+    // One per frame in flight, indexed by FrameSync::frame_index
+    frames: Vec<PoolGroup>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(frames_in_flight: usize) -> Self {
+        DescriptorAllocator {
+            layouts: HashMap::new(),
+            persistent: PoolGroup::default(),
+            frames: (0..frames_in_flight.max(1))
+                .map(|_| PoolGroup::default())
+                .collect(),
+        }
+    }
+
+    // The same bindings in any order get the same layout. Immutable samplers aren't part of the
+    // key, so they aren't supported.
+    pub fn set_layout(
+        &mut self,
+        device: &ash::Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> Result<vk::DescriptorSetLayout, &'static str> {
+        if bindings.iter().any(|b| !b.p_immutable_samplers.is_null()) {
+            return Err("Cached set layouts can't have immutable samplers");
+        }
+        let key = layout_key(bindings);
+        if let Some(&layout) = self.layouts.get(&key) {
+            return Ok(layout);
+        }
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+        let layout = match unsafe { device.create_descriptor_set_layout(&layout_info, None) } {
+            Ok(layout) => layout,
+            Err(e) => {
+                error!("Failed to create descriptor set layout: {}", e);
+                return Err("Failed to create descriptor set layout");
+            }
+        };
+        self.layouts.insert(key, layout);
+        Ok(layout)
+    }
+
+    // Lives as long as the allocator
+    pub fn allocate(
+        &mut self,
+        device: &ash::Device,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, &'static str> {
+        self.persistent.allocate(device, layout)
+    }
+
+    // Only valid until `begin_frame` is called for the same frame again
+    pub fn allocate_frame(
+        &mut self,
+        device: &ash::Device,
+        frame_index: usize,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, &'static str> {
+        self.frames[frame_index].allocate(device, layout)
+    }
+
+    // Once the frame that last used `frame_index` is done, its sets are free to go
+    pub fn begin_frame(&mut self, device: &ash::Device, frame_index: usize) {
+        self.frames[frame_index].reset(device);
+    }
+
+    // Only with nothing in flight, every frame's transient sets are gone after
+    pub fn set_frames_in_flight(&mut self, device: &ash::Device, frames_in_flight: usize) {
+        for frame in &mut self.frames {
+            frame.reset(device);
+        }
+        for mut frame in self.frames.drain(frames_in_flight.max(1)..) {
+            frame.destroy(device);
+        }
+        while self.frames.len() < frames_in_flight {
+            self.frames.push(PoolGroup::default());
+        }
+    }
+
+    // The caller makes sure the GPU is done with every set
+    pub fn destroy(&mut self, device: &ash::Device) {
+        self.persistent.destroy(device);
+        for frame in &mut self.frames {
+            frame.destroy(device);
+        }
+        for (_, layout) in self.layouts.drain() {
+            unsafe { device.destroy_descriptor_set_layout(layout, None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_sizes_per_type() {
+        let binding = |binding, descriptor_type, descriptor_count| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(descriptor_count)
+                .build()
+        };
+        let bindings = [
+            binding(0, vk::DescriptorType::STORAGE_BUFFER, 1),
+            binding(1, vk::DescriptorType::STORAGE_IMAGE, 1),
+            binding(2, vk::DescriptorType::STORAGE_BUFFER, 2),
+        ];
+
+        let sizes: Vec<(vk::DescriptorType, u32)> = pool_sizes(&bindings, 3)
+            .iter()
+            .map(|s| (s.ty, s.descriptor_count))
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![
+                (vk::DescriptorType::STORAGE_BUFFER, 9),
+                (vk::DescriptorType::STORAGE_IMAGE, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn layout_keys_ignore_binding_order() {
+        let binding = |binding, descriptor_type| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        };
+        let ubo = binding(0, vk::DescriptorType::UNIFORM_BUFFER);
+        let texture = binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER);
+        assert_eq!(layout_key(&[ubo, texture]), layout_key(&[texture, ubo]));
+
+        let mut vertex_ubo = ubo;
+        vertex_ubo.stage_flags = vk::ShaderStageFlags::VERTEX;
+        assert_ne!(
+            layout_key(&[ubo, texture]),
+            layout_key(&[vertex_ubo, texture])
+        );
+    }
+
+    #[test]
+    fn pools_double_up_to_the_max() {
+        assert_eq!(pool_max_sets(0), INITIAL_POOL_SETS);
+        assert_eq!(pool_max_sets(1), INITIAL_POOL_SETS * 2);
+        assert_eq!(pool_max_sets(7), MAX_POOL_SETS);
+        assert_eq!(pool_max_sets(40), MAX_POOL_SETS);
+    }
+}
@@ -0,0 +1,209 @@
+// What the application wants drawn this frame, submitted between Renderer::begin_frame and
+// Renderer::end_frame. Draws are sorted by a key so state changes are rare, and runs of the same
+// mesh and material become one instanced draw.
+
+use glam::Mat4;
+
+use super::renderer::{MaterialHandle, MeshHandle};
+
+// Bits per field of a sort key, most significant first. Pass, pipeline and material are what
+// state changes cost the most, depth sorts front to back within them and the mesh keeps draws at
+// about the same depth together so they can share an instanced draw.
+const PASS_BITS: u32 = 4;
+const PIPELINE_BITS: u32 = 12;
+const MATERIAL_BITS: u32 = 16;
+const DEPTH_BITS: u32 = 16;
+const MESH_BITS: u32 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortKey {
+    pub pass: u32,
+    pub pipeline: u32,
+    pub material: u32,
+    // 0 at the camera to 1 at the far plane, clamped
+    pub depth: f32,
+    pub mesh: u32,
+}
+
+impl SortKey {
+    // Fields are masked to their bits, wrapping ones just sort less well
+    pub fn pack(&self) -> u64 {
+        let field = |value: u32, bits: u32| u64::from(value) & ((1 << bits) - 1);
+        let depth = (self.depth.clamp(0.0, 1.0) * ((1 << DEPTH_BITS) - 1) as f32) as u32;
+        let mut key = field(self.pass, PASS_BITS);
+        key = key << PIPELINE_BITS | field(self.pipeline, PIPELINE_BITS);
+        key = key << MATERIAL_BITS | field(self.material, MATERIAL_BITS);
+        key = key << DEPTH_BITS | field(depth, DEPTH_BITS);
+        key << MESH_BITS | field(self.mesh, MESH_BITS)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Draw {
+    pub mesh: MeshHandle,
+    pub material: MaterialHandle,
+    pub transform: Mat4,
+}
+
+// Instances `first_instance..first_instance + instance_count` of one mesh with one material
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Batch {
+    pub mesh: MeshHandle,
+    pub material: MaterialHandle,
+    pub first_instance: u32,
+    pub instance_count: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DrawList {
+    draws: Vec<(u64, Draw)>,
+}
+
+impl DrawList {
+    pub fn new() -> Self {
+        DrawList { draws: Vec::new() }
+    }
+
+    pub fn push(&mut self, key: &SortKey, draw: Draw) {
+        self.draws.push((key.pack(), draw));
+    }
+
+    // Keeps the allocation, so one list can be reused across frames
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.draws.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    // Sorts by key and merges consecutive draws of the same mesh and material. Transforms are
+    // written in instance order, replacing what `transforms` and `batches` held.
+    pub fn build_batches(&mut self, transforms: &mut Vec<Mat4>, batches: &mut Vec<Batch>) {
+        // Stable, so equal keys stay in submission order
+        self.draws.sort_by_key(|&(key, _)| key);
+
+        transforms.clear();
+        batches.clear();
+        for (_, draw) in &self.draws {
+            match batches.last_mut() {
+                Some(batch) if batch.mesh == draw.mesh && batch.material == draw.material => {
+                    batch.instance_count += 1;
+                }
+                _ => batches.push(Batch {
+                    mesh: draw.mesh,
+                    material: draw.material,
+                    first_instance: transforms.len() as u32,
+                    instance_count: 1,
+                }),
+            }
+            transforms.push(draw.transform);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    fn key(material: u32, depth: f32, mesh: u32) -> SortKey {
+        SortKey {
+            pass: 0,
+            pipeline: 0,
+            material,
+            depth,
+            mesh,
+        }
+    }
+
+    #[test]
+    fn keys_order_by_field() {
+        assert!(key(0, 0.9, 5).pack() < key(1, 0.1, 0).pack());
+        assert!(key(1, 0.1, 5).pack() < key(1, 0.2, 0).pack());
+        assert!(key(1, 0.1, 0).pack() < key(1, 0.1, 1).pack());
+        // Out of range depths clamp instead of spilling into the material
+        assert_eq!(key(1, 2.0, 0).pack(), key(1, 1.0, 0).pack());
+        let pass = SortKey {
+            pass: 1,
+            ..key(0, 0.0, 0)
+        };
+        assert!(key(u32::MAX, 1.0, u32::MAX).pack() < pass.pack());
+    }
+
+    #[test]
+    fn same_mesh_and_material_batch() {
+        let mesh = |index| MeshHandle {
+            index,
+            generation: 0,
+        };
+        let (a, b) = (mesh(0), mesh(1));
+        let (m0, m1) = (MaterialHandle(0), MaterialHandle(1));
+        let at = |x| Mat4::from_translation(Vec3::new(x, 0.0, 0.0));
+
+        let mut list = DrawList::new();
+        list.push(
+            &key(1, 0.5, 0),
+            Draw {
+                mesh: a,
+                material: m1,
+                transform: at(1.0),
+            },
+        );
+        list.push(
+            &key(0, 0.5, 0),
+            Draw {
+                mesh: a,
+                material: m0,
+                transform: at(2.0),
+            },
+        );
+        list.push(
+            &key(0, 0.5, 1),
+            Draw {
+                mesh: b,
+                material: m0,
+                transform: at(3.0),
+            },
+        );
+        list.push(
+            &key(0, 0.5, 0),
+            Draw {
+                mesh: a,
+                material: m0,
+                transform: at(4.0),
+            },
+        );
+
+        let (mut transforms, mut batches) = (Vec::new(), Vec::new());
+        list.build_batches(&mut transforms, &mut batches);
+        assert_eq!(
+            batches,
+            vec![
+                Batch {
+                    mesh: a,
+                    material: m0,
+                    first_instance: 0,
+                    instance_count: 2
+                },
+                Batch {
+                    mesh: b,
+                    material: m0,
+                    first_instance: 2,
+                    instance_count: 1
+                },
+                Batch {
+                    mesh: a,
+                    material: m1,
+                    first_instance: 3,
+                    instance_count: 1
+                },
+            ]
+        );
+        assert_eq!(transforms, vec![at(2.0), at(4.0), at(3.0), at(1.0)]);
+    }
+}
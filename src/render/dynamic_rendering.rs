@@ -0,0 +1,290 @@
+// VK_KHR_dynamic_rendering, rendering straight into image views without render pass or
+// framebuffer objects. Like synchronization2 it's newer than ash, so the structs are declared here.
+
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+
+use ash::version::InstanceV1_0;
+use ash::vk;
+
+use super::barrier;
+use super::pipeline::AttachmentFormats;
+
+const RENDERING_INFO: vk::StructureType = vk::StructureType::from_raw(1_000_044_000);
+const RENDERING_ATTACHMENT_INFO: vk::StructureType = vk::StructureType::from_raw(1_000_044_001);
+const PIPELINE_RENDERING_CREATE_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_002);
+const PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_003);
+const COMMAND_BUFFER_INHERITANCE_RENDERING_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_004);
+
+// VK_RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS_BIT
+const RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS: u32 = 0x1;
+
+// The extension and everything it depends on before Vulkan 1.2. They also need
+// VK_KHR_get_physical_device_properties2 on the instance.
+pub fn extension_names() -> [&'static CStr; 5] {
+    [
+        to_cstr!("VK_KHR_dynamic_rendering"),
+        to_cstr!("VK_KHR_depth_stencil_resolve"),
+        to_cstr!("VK_KHR_create_renderpass2"),
+        to_cstr!("VK_KHR_multiview"),
+        to_cstr!("VK_KHR_maintenance2"),
+    ]
+}
+
+// Chained into DeviceCreateInfo to turn the feature on
+#[repr(C)]
+pub struct DynamicRenderingFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    dynamic_rendering: vk::Bool32,
+}
+
+impl Default for DynamicRenderingFeatures {
+    fn default() -> Self {
+        DynamicRenderingFeatures {
+            s_type: PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES,
+            p_next: ptr::null_mut(),
+            dynamic_rendering: vk::TRUE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for DynamicRenderingFeatures {}
+
+#[repr(C)]
+struct RenderingAttachmentInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    image_view: vk::ImageView,
+    image_layout: vk::ImageLayout,
+    resolve_mode: vk::ResolveModeFlags,
+    resolve_image_view: vk::ImageView,
+    resolve_image_layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    clear_value: vk::ClearValue,
+}
+
+#[repr(C)]
+struct RenderingInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: u32,
+    render_area: vk::Rect2D,
+    layer_count: u32,
+    view_mask: u32,
+    color_attachment_count: u32,
+    p_color_attachments: *const RenderingAttachmentInfo,
+    p_depth_attachment: *const RenderingAttachmentInfo,
+    p_stencil_attachment: *const RenderingAttachmentInfo,
+}
+
+// Takes the place of the render pass when creating a pipeline. Points into the formats it was
+// made from, so those have to outlive it.
+#[repr(C)]
+pub struct PipelineRenderingCreateInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    view_mask: u32,
+    color_attachment_count: u32,
+    p_color_attachment_formats: *const vk::Format,
+    depth_attachment_format: vk::Format,
+    stencil_attachment_format: vk::Format,
+}
+
+impl PipelineRenderingCreateInfo {
+    pub fn new(formats: &AttachmentFormats) -> Self {
+        let (depth, stencil) = depth_stencil_formats(formats);
+        PipelineRenderingCreateInfo {
+            s_type: PIPELINE_RENDERING_CREATE_INFO,
+            p_next: ptr::null(),
+            view_mask: 0,
+            color_attachment_count: formats.color.len() as u32,
+            p_color_attachment_formats: formats.color.as_ptr(),
+            depth_attachment_format: depth,
+            stencil_attachment_format: stencil,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsGraphicsPipelineCreateInfo for PipelineRenderingCreateInfo {}
+
+// Secondary command buffers continuing dynamic rendering inherit the formats instead of a render
+// pass. Points into `formats` as well.
+#[repr(C)]
+pub struct InheritanceRenderingInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: u32,
+    view_mask: u32,
+    color_attachment_count: u32,
+    p_color_attachment_formats: *const vk::Format,
+    depth_attachment_format: vk::Format,
+    stencil_attachment_format: vk::Format,
+    rasterization_samples: vk::SampleCountFlags,
+}
+
+impl InheritanceRenderingInfo {
+    pub fn new(formats: &AttachmentFormats) -> Self {
+        let (depth, stencil) = depth_stencil_formats(formats);
+        InheritanceRenderingInfo {
+            s_type: COMMAND_BUFFER_INHERITANCE_RENDERING_INFO,
+            p_next: ptr::null(),
+            flags: RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS,
+            view_mask: 0,
+            color_attachment_count: formats.color.len() as u32,
+            p_color_attachment_formats: formats.color.as_ptr(),
+            depth_attachment_format: depth,
+            stencil_attachment_format: stencil,
+            rasterization_samples: formats.samples,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsCommandBufferInheritanceInfo for InheritanceRenderingInfo {}
+
+// A combined depth/stencil format fills both slots
+fn depth_stencil_formats(formats: &AttachmentFormats) -> (vk::Format, vk::Format) {
+    match formats.depth {
+        Some(format) => {
+            let aspects = barrier::aspect_mask(format);
+            let pick = |aspect| {
+                if aspects.contains(aspect) {
+                    format
+                } else {
+                    vk::Format::UNDEFINED
+                }
+            };
+            (
+                pick(vk::ImageAspectFlags::DEPTH),
+                pick(vk::ImageAspectFlags::STENCIL),
+            )
+        }
+        None => (vk::Format::UNDEFINED, vk::Format::UNDEFINED),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Attachment {
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    pub layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+}
+
+impl Attachment {
+    fn info(&self) -> RenderingAttachmentInfo {
+        RenderingAttachmentInfo {
+            s_type: RENDERING_ATTACHMENT_INFO,
+            p_next: ptr::null(),
+            image_view: self.view,
+            image_layout: self.layout,
+            resolve_mode: vk::ResolveModeFlags::NONE,
+            resolve_image_view: vk::ImageView::null(),
+            resolve_image_layout: vk::ImageLayout::UNDEFINED,
+            load_op: self.load_op,
+            store_op: self.store_op,
+            clear_value: self.clear_value,
+        }
+    }
+}
+
+type CmdBeginRendering = unsafe extern "system" fn(vk::CommandBuffer, *const RenderingInfo);
+type CmdEndRendering = unsafe extern "system" fn(vk::CommandBuffer);
+
+#[derive(Clone, Copy)]
+pub struct DynamicRendering {
+    cmd_begin_rendering: CmdBeginRendering,
+    cmd_end_rendering: CmdEndRendering,
+}
+
+impl DynamicRendering {
+    // Only valid for devices created with the extensions and DynamicRenderingFeatures
+    pub fn load(instance: &ash::Instance, device: &ash::Device) -> Option<Self> {
+        let load =
+            |name: &CStr| unsafe { instance.get_device_proc_addr(device.handle(), name.as_ptr()) };
+        let begin = load(to_cstr!("vkCmdBeginRenderingKHR"))?;
+        let end = load(to_cstr!("vkCmdEndRenderingKHR"))?;
+        Some(DynamicRendering {
+            cmd_begin_rendering: unsafe {
+                mem::transmute::<unsafe extern "system" fn() -> c_void, CmdBeginRendering>(begin)
+            },
+            cmd_end_rendering: unsafe {
+                mem::transmute::<unsafe extern "system" fn() -> c_void, CmdEndRendering>(end)
+            },
+        })
+    }
+
+    // `secondary` if everything inside comes from secondary command buffers
+    pub fn cmd_begin_rendering(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        render_area: vk::Rect2D,
+        colors: &[Attachment],
+        depth: Option<&Attachment>,
+        secondary: bool,
+    ) {
+        let color_infos: Vec<_> = colors.iter().map(Attachment::info).collect();
+        let depth_info = depth.map(Attachment::info);
+        let has = |aspect| depth.is_some_and(|d| barrier::aspect_mask(d.format).contains(aspect));
+        let pointer = |present: bool| match (&depth_info, present) {
+            (Some(info), true) => info as *const RenderingAttachmentInfo,
+            _ => ptr::null(),
+        };
+
+        let rendering_info = RenderingInfo {
+            s_type: RENDERING_INFO,
+            p_next: ptr::null(),
+            flags: if secondary {
+                RENDERING_CONTENTS_SECONDARY_COMMAND_BUFFERS
+            } else {
+                0
+            },
+            render_area,
+            layer_count: 1,
+            view_mask: 0,
+            color_attachment_count: color_infos.len() as u32,
+            p_color_attachments: color_infos.as_ptr(),
+            p_depth_attachment: pointer(has(vk::ImageAspectFlags::DEPTH)),
+            p_stencil_attachment: pointer(has(vk::ImageAspectFlags::STENCIL)),
+        };
+        unsafe { (self.cmd_begin_rendering)(command_buffer, &rendering_info) };
+    }
+
+    pub fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+        unsafe { (self.cmd_end_rendering)(command_buffer) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combined_depth_stencil_fills_both() {
+        let formats = |depth| AttachmentFormats {
+            color: vec![vk::Format::B8G8R8A8_SRGB],
+            depth,
+            samples: vk::SampleCountFlags::TYPE_1,
+        };
+        assert_eq!(
+            depth_stencil_formats(&formats(Some(vk::Format::D32_SFLOAT))),
+            (vk::Format::D32_SFLOAT, vk::Format::UNDEFINED)
+        );
+        assert_eq!(
+            depth_stencil_formats(&formats(Some(vk::Format::D24_UNORM_S8_UINT))),
+            (vk::Format::D24_UNORM_S8_UINT, vk::Format::D24_UNORM_S8_UINT)
+        );
+        assert_eq!(
+            depth_stencil_formats(&formats(None)),
+            (vk::Format::UNDEFINED, vk::Format::UNDEFINED)
+        );
+    }
+}
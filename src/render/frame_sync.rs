@@ -0,0 +1,293 @@
+// Frame pacing around a frame counter that only goes up. With VK_KHR_timeline_semaphore the
+// counter is a timeline semaphore's value: each frame's submission signals its number, and later
+// submissions can wait on any earlier frame on the GPU. Without it every frame in flight gets a
+// fence, and waiting on an earlier frame happens on the CPU instead.
+//
+// Only frame submissions take part. One-off uploads and compute work (Renderer::run_commands and
+// friends) wait on their own fence before returning, so nothing later has to wait on them.
+
+use std::os::raw::c_void;
+use std::ptr;
+
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk;
+
+enum Mode {
+    Timeline {
+        semaphore: vk::Semaphore,
+        fns: vk::KhrTimelineSemaphoreFn,
+    },
+    Fences {
+        fences: Vec<vk::Fence>,
+        // Frame each fence was last submitted with, 0 for never
+        frames: Vec<u64>,
+    },
+}
+
+pub struct FrameSync {
+    frames_in_flight: usize,
+    // The frame being recorded. Starts at 1 so that 0 is always complete.
+    frame: u64,
+    mode: Mode,
+}
+
+pub fn extension_name() -> &'static std::ffi::CStr {
+    vk::KhrTimelineSemaphoreFn::name()
+}
+
+impl FrameSync {
+    // `timeline` only if the device was created with the extension and the feature enabled
+    pub fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        timeline: bool,
+        frames_in_flight: usize,
+    ) -> Result<Self, &'static str> {
+        let mode = if timeline {
+            let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            let semaphore_info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+            let semaphore = match unsafe { device.create_semaphore(&semaphore_info, None) } {
+                Ok(semaphore) => semaphore,
+                Err(e) => {
+                    error!("Failed to create timeline semaphore: {}", e);
+                    return Err("Failed to create timeline semaphore");
+                }
+            };
+            let fns = vk::KhrTimelineSemaphoreFn::load(|name| unsafe {
+                instance
+                    .get_device_proc_addr(device.handle(), name.as_ptr())
+                    .map_or(ptr::null(), |f| f as *const c_void)
+            });
+            Mode::Timeline { semaphore, fns }
+        } else {
+            let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+            let mut fences = Vec::with_capacity(frames_in_flight);
+            for _ in 0..frames_in_flight {
+                match unsafe { device.create_fence(&fence_info, None) } {
+                    Ok(fence) => fences.push(fence),
+                    Err(e) => {
+                        error!("Failed to create frame fence: {}", e);
+                        for fence in fences {
+                            unsafe { device.destroy_fence(fence, None) };
+                        }
+                        return Err("Failed to create frame fence");
+                    }
+                }
+            }
+            Mode::Fences {
+                fences,
+                frames: vec![0; frames_in_flight],
+            }
+        };
+
+        Ok(FrameSync {
+            frames_in_flight,
+            frame: 1,
+            mode,
+        })
+    }
+
+    pub fn is_timeline(&self) -> bool {
+        matches!(self.mode, Mode::Timeline { .. })
+    }
+
+    // Number of the frame being recorded
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Which of the per frame resources the frame being recorded uses
+    pub fn frame_index(&self) -> usize {
+        (self.frame % self.frames_in_flight as u64) as usize
+    }
+
+    // Only with nothing in flight, e.g. after vkDeviceWaitIdle
+    pub fn set_frames_in_flight(
+        &mut self,
+        device: &ash::Device,
+        frames_in_flight: usize,
+    ) -> Result<(), &'static str> {
+        if let Mode::Fences { fences, frames } = &mut self.mode {
+            let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+            while fences.len() < frames_in_flight {
+                match unsafe { device.create_fence(&fence_info, None) } {
+                    Ok(fence) => fences.push(fence),
+                    Err(e) => {
+                        error!("Failed to create frame fence: {}", e);
+                        return Err("Failed to create frame fence");
+                    }
+                }
+            }
+            for fence in fences.drain(frames_in_flight..) {
+                unsafe { device.destroy_fence(fence, None) };
+            }
+            // Slots are frame numbers modulo the count, so the old ones mean nothing now
+            *frames = vec![0; frames_in_flight];
+        }
+        self.frames_in_flight = frames_in_flight;
+        Ok(())
+    }
+
+    // Newest frame the GPU has finished
+    pub fn completed_frame(&self, device: &ash::Device) -> u64 {
+        match &self.mode {
+            Mode::Timeline { semaphore, fns } => {
+                let mut value = 0;
+                let result = unsafe {
+                    fns.get_semaphore_counter_value_khr(device.handle(), *semaphore, &mut value)
+                };
+                if result != vk::Result::SUCCESS {
+                    error!("Failed to read timeline semaphore: {}", result);
+                }
+                value
+            }
+            // Frames finish in order, so anything older than the oldest unsignalled fence is done
+            Mode::Fences { fences, frames } => {
+                let mut completed = self.frame - 1;
+                for (fence, &frame) in fences.iter().zip(frames) {
+                    let signalled = unsafe { device.get_fence_status(*fence) }.unwrap_or(false);
+                    if !signalled && frame != 0 {
+                        completed = completed.min(frame - 1);
+                    }
+                }
+                completed
+            }
+        }
+    }
+
+    pub fn is_frame_complete(&self, device: &ash::Device, frame: u64) -> bool {
+        frame <= self.completed_frame(device)
+    }
+
+    pub fn wait_for_frame(&self, device: &ash::Device, frame: u64) {
+        // Never submitted, waiting would hang
+        let frame = frame.min(self.frame - 1);
+        if frame == 0 {
+            return;
+        }
+
+        match &self.mode {
+            Mode::Timeline { semaphore, fns } => {
+                let semaphores = [*semaphore];
+                let values = [frame];
+                let wait_info = vk::SemaphoreWaitInfo::builder()
+                    .semaphores(&semaphores)
+                    .values(&values);
+                let result =
+                    unsafe { fns.wait_semaphores_khr(device.handle(), &*wait_info, u64::MAX) };
+                if result != vk::Result::SUCCESS {
+                    error!("Failed to wait for frame {}: {}", frame, result);
+                }
+            }
+            Mode::Fences { fences, frames } => {
+                // Frames older than the ones in flight are done already
+                if frame + (self.frames_in_flight as u64) < self.frame {
+                    return;
+                }
+                let slot = (frame % self.frames_in_flight as u64) as usize;
+                if frames[slot] == frame {
+                    unsafe {
+                        device
+                            .wait_for_fences(&fences[slot..=slot], true, u64::MAX)
+                            .unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    // Everything submitted so far, e.g. before destroying resources frames might still use
+    pub fn wait_all(&self, device: &ash::Device) {
+        self.wait_for_frame(device, self.frame - 1);
+    }
+
+    // Blocks until the frame that last used this frame's slot is done with it
+    pub fn begin_frame(&self, device: &ash::Device) {
+        if self.frame > self.frames_in_flight as u64 {
+            self.wait_for_frame(device, self.frame - self.frames_in_flight as u64);
+        }
+    }
+
+    // The frame's submission, signals its number and moves on to the next frame. `wait_frames`
+    // are earlier frames this work depends on and the stage that has to wait for each.
+    pub fn submit(
+        &mut self,
+        device: &ash::Device,
+        queue: vk::Queue,
+        command_buffers: &[vk::CommandBuffer],
+        waits: &[(vk::Semaphore, vk::PipelineStageFlags)],
+        signals: &[vk::Semaphore],
+        wait_frames: &[(u64, vk::PipelineStageFlags)],
+    ) -> Result<(), &'static str> {
+        if !self.is_timeline() {
+            for &(frame, _) in wait_frames {
+                self.wait_for_frame(device, frame);
+            }
+        }
+
+        let mut wait_semaphores: Vec<_> = waits.iter().map(|&(s, _)| s).collect();
+        let mut wait_stages: Vec<_> = waits.iter().map(|&(_, stage)| stage).collect();
+        let mut signal_semaphores = signals.to_vec();
+
+        let result = match &mut self.mode {
+            Mode::Timeline { semaphore, .. } => {
+                // Binary semaphores ignore their values, they only have to line up
+                let mut wait_values = vec![0; waits.len()];
+                for &(frame, stage) in wait_frames {
+                    wait_semaphores.push(*semaphore);
+                    wait_stages.push(stage);
+                    wait_values.push(frame);
+                }
+                let mut signal_values = vec![0; signals.len()];
+                signal_semaphores.push(*semaphore);
+                signal_values.push(self.frame);
+
+                let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+                    .wait_semaphore_values(&wait_values)
+                    .signal_semaphore_values(&signal_values);
+                let submit_info = vk::SubmitInfo::builder()
+                    .wait_semaphores(&wait_semaphores)
+                    .wait_dst_stage_mask(&wait_stages)
+                    .command_buffers(command_buffers)
+                    .signal_semaphores(&signal_semaphores)
+                    .push_next(&mut timeline_info);
+                unsafe { device.queue_submit(queue, &[submit_info.build()], vk::Fence::null()) }
+            }
+            Mode::Fences { fences, frames } => {
+                let slot = (self.frame % self.frames_in_flight as u64) as usize;
+                let submit_info = vk::SubmitInfo::builder()
+                    .wait_semaphores(&wait_semaphores)
+                    .wait_dst_stage_mask(&wait_stages)
+                    .command_buffers(command_buffers)
+                    .signal_semaphores(&signal_semaphores);
+                // Only reset once something is submitted that signals it again
+                unsafe {
+                    device.reset_fences(&fences[slot..=slot]).unwrap();
+                    frames[slot] = self.frame;
+                    device.queue_submit(queue, &[submit_info.build()], fences[slot])
+                }
+            }
+        };
+
+        self.frame += 1;
+        result.map_err(|e| {
+            error!("Failed to submit frame: {}", e);
+            "Failed to submit frame"
+        })
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        match &mut self.mode {
+            Mode::Timeline { semaphore, .. } => unsafe {
+                device.destroy_semaphore(*semaphore, None)
+            },
+            Mode::Fences { fences, .. } => {
+                for fence in fences.drain(..) {
+                    unsafe { device.destroy_fence(fence, None) };
+                }
+            }
+        }
+    }
+}
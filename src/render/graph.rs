@@ -0,0 +1,1232 @@
+// A frame graph. Passes declare the images and buffers they read and write, and the graph works
+// out the rest: the order to run them in, which ones can be dropped because nothing uses what
+// they produce, render passes and framebuffers for their attachments, and the barriers between
+// them.
+//
+// Resources are either imported, like the swapchain image or a buffer the application owns, or
+// transient. Transients only hold anything during a frame, so ones that are never alive at the
+// same time share memory.
+//
+// Passes and resources are declared once. `compile` creates everything and has to be called again
+// when the extent changes. Imported resources are set before every `execute`.
+
+use std::collections::HashMap;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use super::barrier::{self, Access};
+use super::dynamic_rendering::{Attachment, DynamicRendering};
+use super::pipeline::AttachmentFormats;
+use super::renderer::find_memorytype_index;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PassId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageSize {
+    // Scale of the graph's extent, usually the swapchain's
+    Relative(f32),
+    Absolute(vk::Extent2D),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub size: ImageSize,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl ImageDesc {
+    // Single sampled and as big as the graph
+    pub fn new(format: vk::Format) -> Self {
+        ImageDesc {
+            format,
+            size: ImageSize::Relative(1.0),
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ResourceKind {
+    Image(ImageDesc),
+    Buffer(vk::DeviceSize),
+}
+
+struct Resource {
+    name: String,
+    kind: ResourceKind,
+    // Access before and after the frame for imported resources. Transients start out empty.
+    imported: Option<(Access, Access)>,
+    image: vk::Image,
+    view: vk::ImageView,
+    buffer: vk::Buffer,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Use {
+    resource: usize,
+    access: Access,
+}
+
+struct Pass {
+    name: String,
+    // One per resource
+    uses: Vec<Use>,
+    colors: Vec<(usize, Option<[f32; 4]>)>,
+    depth: Option<(usize, Option<f32>)>,
+    // Recorded into secondary command buffers, see PassBuilder::secondary
+    secondary: bool,
+}
+
+impl Pass {
+    fn access(&self, resource: usize) -> Option<Access> {
+        self.uses
+            .iter()
+            .find(|u| u.resource == resource)
+            .map(|u| u.access)
+    }
+
+    fn attachments(&self) -> impl Iterator<Item = usize> + '_ {
+        self.colors
+            .iter()
+            .map(|&(r, _)| r)
+            .chain(self.depth.map(|(r, _)| r))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Barrier {
+    resource: usize,
+    from: Access,
+    to: Access,
+    // The memory was someone else's, so there's nothing to keep
+    discard: bool,
+}
+
+// A pass as it runs, in execution order
+struct PassInstance {
+    pass: usize,
+    // Null for passes without attachments, e.g. compute, and with dynamic rendering
+    render_pass: vk::RenderPass,
+    extent: vk::Extent2D,
+    attachments: Vec<AttachmentOps>,
+    barriers: Vec<Barrier>,
+}
+
+// How a graphics pass loads and stores one of its attachments
+#[derive(Clone, Copy)]
+struct AttachmentOps {
+    resource: usize,
+    format: vk::Format,
+    layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    clear_value: vk::ClearValue,
+}
+
+// Handed to the record callback for every pass. Graphics passes are already inside their render
+// pass, with the viewport and scissor covering it unless the pass is secondary.
+pub struct PassContext<'a> {
+    pub device: &'a ash::Device,
+    pub command_buffer: vk::CommandBuffer,
+    pub extent: vk::Extent2D,
+    // Null outside graphics passes and with dynamic rendering, secondary command buffers inherit
+    // these
+    pub render_pass: vk::RenderPass,
+    pub framebuffer: vk::Framebuffer,
+    pass: PassId,
+    graph: &'a FrameGraph,
+}
+
+impl<'a> PassContext<'a> {
+    // Secondary command buffers inherit the attachment formats instead of a render pass then
+    pub fn dynamic_rendering(&self) -> Option<AttachmentFormats> {
+        if self.graph.dynamic_rendering.is_some()
+            && self.graph.passes[self.pass.0].attachments().count() > 0
+        {
+            Some(self.graph.attachment_formats(self.pass))
+        } else {
+            None
+        }
+    }
+
+    pub fn render_area(&self) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        }
+    }
+
+    // Viewport and scissor over the whole pass, secondary command buffers have to set their own
+    pub fn set_viewport(&self, command_buffer: vk::CommandBuffer) {
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        unsafe {
+            self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.device
+                .cmd_set_scissor(command_buffer, 0, &[self.render_area()]);
+        }
+    }
+
+    pub fn image(&self, id: ResourceId) -> vk::Image {
+        self.graph.resources[id.0].image
+    }
+
+    pub fn image_view(&self, id: ResourceId) -> vk::ImageView {
+        self.graph.resources[id.0].view
+    }
+
+    pub fn buffer(&self, id: ResourceId) -> vk::Buffer {
+        self.graph.resources[id.0].buffer
+    }
+}
+
+pub struct PassBuilder<'a> {
+    graph: &'a mut FrameGraph,
+    pass: usize,
+}
+
+impl<'a> PassBuilder<'a> {
+    // Rendered to, cleared to `clear` first or drawn on top of what's there
+    pub fn color(self, id: ResourceId, clear: Option<[f32; 4]>) -> Self {
+        self.graph.passes[self.pass].colors.push((id.0, clear));
+        self.access(id, Access::ColorAttachmentWrite)
+    }
+
+    pub fn depth(self, id: ResourceId, clear: Option<f32>) -> Self {
+        self.graph.passes[self.pass].depth = Some((id.0, clear));
+        self.access(id, Access::DepthAttachmentWrite)
+    }
+
+    pub fn read(self, id: ResourceId, access: Access) -> Self {
+        debug_assert!(!access.is_write());
+        self.access(id, access)
+    }
+
+    pub fn write(self, id: ResourceId, access: Access) -> Self {
+        debug_assert!(access.is_write());
+        self.access(id, access)
+    }
+
+    // Only vkCmdExecuteCommands inside the render pass, so the callback can record the pass on
+    // several threads. Secondary buffers continue context.render_pass and context.framebuffer.
+    pub fn secondary(self) -> Self {
+        self.graph.passes[self.pass].secondary = true;
+        self
+    }
+
+    pub fn id(self) -> PassId {
+        PassId(self.pass)
+    }
+
+    // A pass uses each resource one way, the last one given wins
+    fn access(self, id: ResourceId, access: Access) -> Self {
+        let uses = &mut self.graph.passes[self.pass].uses;
+        match uses.iter_mut().find(|u| u.resource == id.0) {
+            Some(u) => u.access = access,
+            None => uses.push(Use {
+                resource: id.0,
+                access,
+            }),
+        }
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct FrameGraph {
+    resources: Vec<Resource>,
+    passes: Vec<Pass>,
+
+    extent: vk::Extent2D,
+    instances: Vec<PassInstance>,
+    // Imported resources back to what they were imported as
+    final_barriers: Vec<Barrier>,
+    transients: Vec<usize>,
+    memory: Vec<vk::DeviceMemory>,
+    // Imported attachments can change every frame, so by views as well. Cleared on compile.
+    framebuffers: HashMap<(vk::RenderPass, Vec<vk::ImageView>), vk::Framebuffer>,
+    // Graphics passes begin rendering on their views directly, no render passes or framebuffers
+    dynamic_rendering: Option<DynamicRendering>,
+}
+
+impl FrameGraph {
+    pub fn new() -> Self {
+        FrameGraph::default()
+    }
+
+    // Takes effect on the next `compile`
+    pub fn set_dynamic_rendering(&mut self, dynamic_rendering: Option<DynamicRendering>) {
+        self.dynamic_rendering = dynamic_rendering;
+    }
+
+    // `initial` is how it's been used before the frame, `final_access` how it's used after, e.g.
+    // Acquire and Present for the swapchain image. Always the size of the graph.
+    pub fn import_image(
+        &mut self,
+        name: &str,
+        format: vk::Format,
+        initial: Access,
+        final_access: Access,
+    ) -> ResourceId {
+        self.add_resource(
+            name,
+            ResourceKind::Image(ImageDesc::new(format)),
+            Some((initial, final_access)),
+        )
+    }
+
+    pub fn import_buffer(
+        &mut self,
+        name: &str,
+        size: vk::DeviceSize,
+        initial: Access,
+        final_access: Access,
+    ) -> ResourceId {
+        self.add_resource(
+            name,
+            ResourceKind::Buffer(size),
+            Some((initial, final_access)),
+        )
+    }
+
+    // Usage flags come from how the passes use it
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Image(desc), None)
+    }
+
+    pub fn create_buffer(&mut self, name: &str, size: vk::DeviceSize) -> ResourceId {
+        self.add_resource(name, ResourceKind::Buffer(size), None)
+    }
+
+    fn add_resource(
+        &mut self,
+        name: &str,
+        kind: ResourceKind,
+        imported: Option<(Access, Access)>,
+    ) -> ResourceId {
+        self.resources.push(Resource {
+            name: name.to_string(),
+            kind,
+            imported,
+            image: vk::Image::null(),
+            view: vk::ImageView::null(),
+            buffer: vk::Buffer::null(),
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, name: &str) -> PassBuilder<'_> {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            uses: Vec::new(),
+            colors: Vec::new(),
+            depth: None,
+            secondary: false,
+        });
+        PassBuilder {
+            pass: self.passes.len() - 1,
+            graph: self,
+        }
+    }
+
+    // What a pipeline drawing in `pass` has to be compatible with
+    pub fn attachment_formats(&self, pass: PassId) -> AttachmentFormats {
+        let pass = &self.passes[pass.0];
+        let image = |r: usize| match self.resources[r].kind {
+            ResourceKind::Image(desc) => desc,
+            ResourceKind::Buffer(_) => panic!("{} is a buffer", self.resources[r].name),
+        };
+        AttachmentFormats {
+            color: pass.colors.iter().map(|&(r, _)| image(r).format).collect(),
+            depth: pass.depth.map(|(r, _)| image(r).format),
+            samples: pass
+                .attachments()
+                .next()
+                .map_or(vk::SampleCountFlags::TYPE_1, |r| image(r).samples),
+        }
+    }
+
+    pub fn set_image(
+        &mut self,
+        device: &ash::Device,
+        id: ResourceId,
+        image: vk::Image,
+        view: vk::ImageView,
+    ) -> Result<(), &'static str> {
+        let resource = &mut self.resources[id.0];
+        debug_assert!(resource.imported.is_some());
+        resource.image = image;
+        resource.view = view;
+        self.create_framebuffers(device)
+    }
+
+    pub fn set_buffer(&mut self, id: ResourceId, buffer: vk::Buffer) {
+        debug_assert!(self.resources[id.0].imported.is_some());
+        self.resources[id.0].buffer = buffer;
+    }
+
+    // Order, transients, render passes and barriers for `extent`. Everything from an earlier
+    // compile is destroyed first, so the GPU has to be done with it.
+    pub fn compile(
+        &mut self,
+        device: &ash::Device,
+        mem_properties: &vk::PhysicalDeviceMemoryProperties,
+        extent: vk::Extent2D,
+    ) -> Result<(), &'static str> {
+        self.destroy(device);
+        self.extent = extent;
+        let result = self.build(device, mem_properties);
+        if result.is_err() {
+            self.destroy(device);
+        }
+        result
+    }
+
+    fn build(
+        &mut self,
+        device: &ash::Device,
+        mem_properties: &vk::PhysicalDeviceMemoryProperties,
+    ) -> Result<(), &'static str> {
+        let order = self.schedule()?;
+        let uses_of = |r: usize| -> Vec<(usize, Access)> {
+            order
+                .iter()
+                .enumerate()
+                .filter_map(|(i, &p)| self.passes[p].access(r).map(|a| (i, a)))
+                .collect()
+        };
+        let uses: Vec<Vec<(usize, Access)>> = (0..self.resources.len()).map(uses_of).collect();
+
+        // Transients nothing runs with don't need creating
+        let transients: Vec<usize> = (0..self.resources.len())
+            .filter(|&r| self.resources[r].imported.is_none() && !uses[r].is_empty())
+            .collect();
+        let mut lifetimes = Vec::with_capacity(transients.len());
+        for &r in &transients {
+            let accesses = uses[r].iter().map(|&(_, a)| a);
+            let requirements = self.create_transient(device, r, accesses)?;
+            lifetimes.push(Lifetime {
+                first: uses[r][0].0,
+                last: uses[r][uses[r].len() - 1].0,
+                requirements,
+            });
+        }
+
+        // Memory, shared between transients that don't overlap
+        let (slot_of, slots) = alias_memory(&lifetimes);
+        for requirements in &slots {
+            let memory_type_index = find_memorytype_index(
+                requirements,
+                mem_properties,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .ok_or("No memory type for frame graph resources")?;
+            let alloc_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index);
+            let memory = unsafe { device.allocate_memory(&alloc_info, None) }
+                .map_err(|_| "Failed to allocate frame graph memory")?;
+            self.memory.push(memory);
+        }
+        for (i, &r) in transients.iter().enumerate() {
+            self.bind_transient(device, r, self.memory[slot_of[i]])?;
+        }
+
+        // Each transient's first use waits for whoever had the memory before it. That's the
+        // previous one in its slot, or the last one in the slot during the previous frame.
+        let mut discard_from = HashMap::new();
+        for slot in 0..slots.len() {
+            let mut occupants: Vec<usize> = (0..transients.len())
+                .filter(|&i| slot_of[i] == slot)
+                .collect();
+            occupants.sort_by_key(|&i| lifetimes[i].first);
+            for (k, &i) in occupants.iter().enumerate() {
+                let previous = transients[occupants[(k + occupants.len() - 1) % occupants.len()]];
+                let last_access = uses[previous][uses[previous].len() - 1].1;
+                discard_from.insert(transients[i], last_access);
+            }
+        }
+
+        let mut state: Vec<Option<Access>> = self
+            .resources
+            .iter()
+            .map(|r| r.imported.map(|(initial, _)| initial))
+            .collect();
+        for (position, &p) in order.iter().enumerate() {
+            let mut barriers = Vec::new();
+            for u in &self.passes[p].uses {
+                let barrier = match state[u.resource] {
+                    Some(from) => Barrier {
+                        resource: u.resource,
+                        from,
+                        to: u.access,
+                        discard: false,
+                    },
+                    None => Barrier {
+                        resource: u.resource,
+                        from: discard_from[&u.resource],
+                        to: u.access,
+                        discard: true,
+                    },
+                };
+                if barrier.discard || self.needs_barrier(&barrier) {
+                    barriers.push(barrier);
+                }
+                state[u.resource] = Some(u.access);
+            }
+
+            let (attachments, extent) = if self.passes[p].attachments().count() > 0 {
+                self.attachment_ops(p, position, &uses)
+            } else {
+                (Vec::new(), self.extent)
+            };
+            // Dynamic rendering begins with the attachments themselves
+            let render_pass = if attachments.is_empty() || self.dynamic_rendering.is_some() {
+                vk::RenderPass::null()
+            } else {
+                self.create_render_pass(device, p, &attachments)?
+            };
+            self.instances.push(PassInstance {
+                pass: p,
+                render_pass,
+                extent,
+                attachments,
+                barriers,
+            });
+        }
+
+        for (r, resource) in self.resources.iter().enumerate() {
+            if let (Some((_, final_access)), Some(from)) = (resource.imported, state[r]) {
+                let barrier = Barrier {
+                    resource: r,
+                    from,
+                    to: final_access,
+                    discard: false,
+                };
+                if self.needs_barrier(&barrier) {
+                    self.final_barriers.push(barrier);
+                }
+            }
+        }
+
+        self.create_framebuffers(device)
+    }
+
+    // Passes that end up in an imported resource, ordered so everything a pass reads was written
+    // before it runs. Otherwise passes run in the order they were added.
+    fn schedule(&self) -> Result<Vec<usize>, &'static str> {
+        let count = self.passes.len();
+        let mut edges: Vec<Vec<usize>> = vec![Vec::new(); count];
+
+        for r in 0..self.resources.len() {
+            let writers: Vec<usize> = (0..count)
+                .filter(|&p| matches!(self.passes[p].access(r), Some(a) if a.is_write()))
+                .collect();
+            if writers.is_empty() {
+                continue;
+            }
+
+            // A read sees the last write added before it, or the first one if it came earlier
+            let mut readers: Vec<Vec<usize>> = vec![Vec::new(); writers.len()];
+            for p in 0..count {
+                if matches!(self.passes[p].access(r), Some(a) if !a.is_write()) {
+                    let version = writers.iter().rposition(|&w| w < p).unwrap_or(0);
+                    edges[writers[version]].push(p);
+                    readers[version].push(p);
+                }
+            }
+            // The next write waits for the previous one and everything reading it
+            for version in 1..writers.len() {
+                edges[writers[version - 1]].push(writers[version]);
+                for &reader in &readers[version - 1] {
+                    edges[reader].push(writers[version]);
+                }
+            }
+        }
+
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); count];
+        for (p, successors) in edges.iter().enumerate() {
+            for &s in successors {
+                predecessors[s].push(p);
+            }
+        }
+
+        let mut kept = vec![false; count];
+        let mut stack: Vec<usize> = (0..count)
+            .filter(|&p| {
+                self.passes[p]
+                    .uses
+                    .iter()
+                    .any(|u| u.access.is_write() && self.resources[u.resource].imported.is_some())
+            })
+            .collect();
+        while let Some(p) = stack.pop() {
+            if !kept[p] {
+                kept[p] = true;
+                stack.extend(&predecessors[p]);
+            }
+        }
+        for (p, pass) in self.passes.iter().enumerate() {
+            if !kept[p] {
+                debug!("Frame graph pass {} isn't used, skipping it", pass.name);
+            }
+        }
+
+        let mut in_degree = vec![0; count];
+        for p in (0..count).filter(|&p| kept[p]) {
+            for &s in &edges[p] {
+                in_degree[s] += 1;
+            }
+        }
+        let mut ready: Vec<usize> = (0..count)
+            .filter(|&p| kept[p] && in_degree[p] == 0)
+            .collect();
+        let mut order = Vec::new();
+        while let Some(i) = (0..ready.len()).min_by_key(|&i| ready[i]) {
+            let p = ready.swap_remove(i);
+            order.push(p);
+            for &s in &edges[p] {
+                in_degree[s] -= 1;
+                if in_degree[s] == 0 {
+                    ready.push(s);
+                }
+            }
+        }
+
+        if order.len() != kept.iter().filter(|&&k| k).count() {
+            return Err("Frame graph passes depend on each other in a cycle");
+        }
+        Ok(order)
+    }
+
+    fn needs_barrier(&self, barrier: &Barrier) -> bool {
+        let is_image = matches!(
+            self.resources[barrier.resource].kind,
+            ResourceKind::Image(_)
+        );
+        let layout_changes = is_image && barrier.from.image_layout() != barrier.to.image_layout();
+        // Reads after reads don't need anything
+        layout_changes || barrier.from.is_write() || barrier.to.is_write()
+    }
+
+    fn image_extent(&self, desc: &ImageDesc) -> vk::Extent2D {
+        match desc.size {
+            ImageSize::Relative(scale) => vk::Extent2D {
+                width: ((self.extent.width as f32 * scale) as u32).max(1),
+                height: ((self.extent.height as f32 * scale) as u32).max(1),
+            },
+            ImageSize::Absolute(extent) => extent,
+        }
+    }
+
+    // Creates the image or buffer without memory, returns what memory it needs
+    fn create_transient(
+        &mut self,
+        device: &ash::Device,
+        r: usize,
+        accesses: impl Iterator<Item = Access>,
+    ) -> Result<vk::MemoryRequirements, &'static str> {
+        match self.resources[r].kind {
+            ResourceKind::Image(desc) => {
+                let usage = accesses.fold(vk::ImageUsageFlags::empty(), |usage, a| {
+                    usage | image_usage(a)
+                });
+                let extent = self.image_extent(&desc);
+                let image_info = vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .format(desc.format)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .usage(usage)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .samples(desc.samples);
+                let image = unsafe { device.create_image(&image_info, None) }
+                    .map_err(|_| "Failed to create frame graph image")?;
+                self.resources[r].image = image;
+                self.transients.push(r);
+                Ok(unsafe { device.get_image_memory_requirements(image) })
+            }
+            ResourceKind::Buffer(size) => {
+                let usage = accesses.fold(vk::BufferUsageFlags::empty(), |usage, a| {
+                    usage | buffer_usage(a)
+                });
+                let buffer_info = vk::BufferCreateInfo::builder()
+                    .size(size)
+                    .usage(usage)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE);
+                let buffer = unsafe { device.create_buffer(&buffer_info, None) }
+                    .map_err(|_| "Failed to create frame graph buffer")?;
+                self.resources[r].buffer = buffer;
+                self.transients.push(r);
+                Ok(unsafe { device.get_buffer_memory_requirements(buffer) })
+            }
+        }
+    }
+
+    fn bind_transient(
+        &mut self,
+        device: &ash::Device,
+        r: usize,
+        memory: vk::DeviceMemory,
+    ) -> Result<(), &'static str> {
+        let resource = &mut self.resources[r];
+        match resource.kind {
+            ResourceKind::Image(desc) => unsafe {
+                device
+                    .bind_image_memory(resource.image, memory, 0)
+                    .map_err(|_| "Failed to bind frame graph image memory")?;
+                let view_info = vk::ImageViewCreateInfo::builder()
+                    .image(resource.image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(desc.format)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: barrier::aspect_mask(desc.format),
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    });
+                resource.view = device
+                    .create_image_view(&view_info, None)
+                    .map_err(|_| "Failed to create frame graph image view")?;
+            },
+            ResourceKind::Buffer(_) => unsafe {
+                device
+                    .bind_buffer_memory(resource.buffer, memory, 0)
+                    .map_err(|_| "Failed to bind frame graph buffer memory")?;
+            },
+        }
+        Ok(())
+    }
+
+    // Attachments are already in the right layout when the pass begins, so all that's left is
+    // what to load and store. Colors first, then depth.
+    fn attachment_ops(
+        &self,
+        p: usize,
+        position: usize,
+        uses: &[Vec<(usize, Access)>],
+    ) -> (Vec<AttachmentOps>, vk::Extent2D) {
+        let pass = &self.passes[p];
+        let formats = self.attachment_formats(PassId(p));
+
+        let attachment = |r: usize, format: vk::Format, clear_value, cleared: bool, layout| {
+            let resource = &self.resources[r];
+            let written_before = match resource.imported {
+                Some((initial, _)) => {
+                    !matches!(initial, Access::Nothing | Access::Acquire) || uses[r][0].0 < position
+                }
+                None => uses[r][0].0 < position,
+            };
+            let used_after = resource.imported.is_some() || uses[r][uses[r].len() - 1].0 > position;
+            AttachmentOps {
+                resource: r,
+                format,
+                layout,
+                load_op: if cleared {
+                    vk::AttachmentLoadOp::CLEAR
+                } else if written_before {
+                    vk::AttachmentLoadOp::LOAD
+                } else {
+                    vk::AttachmentLoadOp::DONT_CARE
+                },
+                store_op: if used_after {
+                    vk::AttachmentStoreOp::STORE
+                } else {
+                    vk::AttachmentStoreOp::DONT_CARE
+                },
+                clear_value,
+            }
+        };
+
+        let mut attachments = Vec::new();
+        for (&(r, clear), &format) in pass.colors.iter().zip(&formats.color) {
+            let clear_value = vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: clear.unwrap_or_default(),
+                },
+            };
+            attachments.push(attachment(
+                r,
+                format,
+                clear_value,
+                clear.is_some(),
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ));
+        }
+        if let (Some((r, clear)), Some(format)) = (pass.depth, formats.depth) {
+            let clear_value = vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: clear.unwrap_or(1.0),
+                    stencil: 0,
+                },
+            };
+            attachments.push(attachment(
+                r,
+                format,
+                clear_value,
+                clear.is_some(),
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ));
+        }
+
+        let first = pass.attachments().next().unwrap();
+        let extent = match self.resources[first].kind {
+            ResourceKind::Image(desc) => self.image_extent(&desc),
+            ResourceKind::Buffer(_) => self.extent,
+        };
+        (attachments, extent)
+    }
+
+    fn create_render_pass(
+        &self,
+        device: &ash::Device,
+        p: usize,
+        ops: &[AttachmentOps],
+    ) -> Result<vk::RenderPass, &'static str> {
+        let pass = &self.passes[p];
+        let samples = self.attachment_formats(PassId(p)).samples;
+        let attachments: Vec<vk::AttachmentDescription> = ops
+            .iter()
+            .map(|a| {
+                vk::AttachmentDescription::builder()
+                    .format(a.format)
+                    .samples(samples)
+                    .load_op(a.load_op)
+                    .store_op(a.store_op)
+                    .stencil_load_op(a.load_op)
+                    .stencil_store_op(a.store_op)
+                    .initial_layout(a.layout)
+                    .final_layout(a.layout)
+                    .build()
+            })
+            .collect();
+
+        let color_refs: Vec<vk::AttachmentReference> = (0..pass.colors.len() as u32)
+            .map(|attachment| vk::AttachmentReference {
+                attachment,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            })
+            .collect();
+        let depth_ref = vk::AttachmentReference {
+            attachment: pass.colors.len() as u32,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let mut subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs);
+        if pass.depth.is_some() {
+            subpass = subpass.depth_stencil_attachment(&depth_ref);
+        }
+        let subpasses = [subpass.build()];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses);
+        unsafe { device.create_render_pass(&render_pass_info, None) }
+            .map_err(|_| "Failed to create frame graph render pass")
+    }
+
+    // For every graphics pass whose attachments are all set
+    fn create_framebuffers(&mut self, device: &ash::Device) -> Result<(), &'static str> {
+        for instance in &self.instances {
+            if instance.render_pass == vk::RenderPass::null() {
+                continue;
+            }
+            let views: Vec<vk::ImageView> = instance
+                .attachments
+                .iter()
+                .map(|a| self.resources[a.resource].view)
+                .collect();
+            let key = (instance.render_pass, views);
+            if key.1.contains(&vk::ImageView::null()) || self.framebuffers.contains_key(&key) {
+                continue;
+            }
+
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(instance.render_pass)
+                .attachments(&key.1)
+                .width(instance.extent.width)
+                .height(instance.extent.height)
+                .layers(1);
+            let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None) }
+                .map_err(|_| "Failed to create frame graph framebuffer")?;
+            self.framebuffers.insert(key, framebuffer);
+        }
+        Ok(())
+    }
+
+    // Records every pass with its barriers. `record` is called for each pass that survived
+    // compiling, in execution order.
+    pub fn execute<F: FnMut(PassId, &PassContext)>(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        mut record: F,
+    ) {
+        for instance in &self.instances {
+            for barrier in &instance.barriers {
+                self.cmd_barrier(device, command_buffer, barrier);
+            }
+
+            if instance.attachments.is_empty() {
+                let context = PassContext {
+                    device,
+                    command_buffer,
+                    extent: instance.extent,
+                    render_pass: vk::RenderPass::null(),
+                    framebuffer: vk::Framebuffer::null(),
+                    pass: PassId(instance.pass),
+                    graph: self,
+                };
+                record(PassId(instance.pass), &context);
+                continue;
+            }
+
+            // set_image has to have been called for every imported attachment
+            let views: Vec<vk::ImageView> = instance
+                .attachments
+                .iter()
+                .map(|a| self.resources[a.resource].view)
+                .collect();
+            let secondary = self.passes[instance.pass].secondary;
+
+            if let Some(dynamic_rendering) = &self.dynamic_rendering {
+                let context = PassContext {
+                    device,
+                    command_buffer,
+                    extent: instance.extent,
+                    render_pass: vk::RenderPass::null(),
+                    framebuffer: vk::Framebuffer::null(),
+                    pass: PassId(instance.pass),
+                    graph: self,
+                };
+                let attachments: Vec<Attachment> = instance
+                    .attachments
+                    .iter()
+                    .zip(views)
+                    .map(|(a, view)| Attachment {
+                        view,
+                        format: a.format,
+                        layout: a.layout,
+                        load_op: a.load_op,
+                        store_op: a.store_op,
+                        clear_value: a.clear_value,
+                    })
+                    .collect();
+                let color_count = self.passes[instance.pass].colors.len();
+                dynamic_rendering.cmd_begin_rendering(
+                    command_buffer,
+                    context.render_area(),
+                    &attachments[..color_count],
+                    attachments.get(color_count),
+                    secondary,
+                );
+                if !secondary {
+                    context.set_viewport(command_buffer);
+                }
+                record(PassId(instance.pass), &context);
+                dynamic_rendering.cmd_end_rendering(command_buffer);
+                continue;
+            }
+
+            let context = PassContext {
+                device,
+                command_buffer,
+                extent: instance.extent,
+                render_pass: instance.render_pass,
+                framebuffer: self.framebuffers[&(instance.render_pass, views)],
+                pass: PassId(instance.pass),
+                graph: self,
+            };
+            let clear_values: Vec<vk::ClearValue> =
+                instance.attachments.iter().map(|a| a.clear_value).collect();
+            let render_begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(context.render_pass)
+                .framebuffer(context.framebuffer)
+                .render_area(context.render_area())
+                .clear_values(&clear_values);
+            let contents = if secondary {
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+            } else {
+                vk::SubpassContents::INLINE
+            };
+            unsafe { device.cmd_begin_render_pass(command_buffer, &render_begin_info, contents) };
+            if !secondary {
+                context.set_viewport(command_buffer);
+            }
+            record(PassId(instance.pass), &context);
+            unsafe { device.cmd_end_render_pass(command_buffer) };
+        }
+
+        for barrier in &self.final_barriers {
+            self.cmd_barrier(device, command_buffer, barrier);
+        }
+    }
+
+    fn cmd_barrier(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        barrier: &Barrier,
+    ) {
+        let resource = &self.resources[barrier.resource];
+        match resource.kind {
+            ResourceKind::Image(desc) => {
+                let range = vk::ImageSubresourceRange {
+                    aspect_mask: barrier::aspect_mask(desc.format),
+                    base_mip_level: 0,
+                    level_count: vk::REMAINING_MIP_LEVELS,
+                    base_array_layer: 0,
+                    layer_count: vk::REMAINING_ARRAY_LAYERS,
+                };
+                if barrier.discard {
+                    barrier::image_discard_barrier(
+                        device,
+                        command_buffer,
+                        resource.image,
+                        range,
+                        barrier.from,
+                        barrier.to,
+                    );
+                } else {
+                    barrier::image_barrier(
+                        device,
+                        command_buffer,
+                        resource.image,
+                        range,
+                        barrier.from,
+                        barrier.to,
+                    );
+                }
+            }
+            ResourceKind::Buffer(_) => barrier::buffer_barrier(
+                device,
+                command_buffer,
+                resource.buffer,
+                barrier.from,
+                barrier.to,
+            ),
+        }
+    }
+
+    // Everything compile created. The caller makes sure the GPU is done with it.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            for (_, framebuffer) in self.framebuffers.drain() {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+            for instance in self.instances.drain(..) {
+                if instance.render_pass != vk::RenderPass::null() {
+                    device.destroy_render_pass(instance.render_pass, None);
+                }
+            }
+            for r in self.transients.drain(..) {
+                let resource = &mut self.resources[r];
+                if resource.view != vk::ImageView::null() {
+                    device.destroy_image_view(resource.view, None);
+                }
+                if resource.image != vk::Image::null() {
+                    device.destroy_image(resource.image, None);
+                }
+                if resource.buffer != vk::Buffer::null() {
+                    device.destroy_buffer(resource.buffer, None);
+                }
+                resource.view = vk::ImageView::null();
+                resource.image = vk::Image::null();
+                resource.buffer = vk::Buffer::null();
+            }
+            for memory in self.memory.drain(..) {
+                device.free_memory(memory, None);
+            }
+        }
+        self.final_barriers.clear();
+    }
+}
+
+struct Lifetime {
+    // Positions in execution order
+    first: usize,
+    last: usize,
+    requirements: vk::MemoryRequirements,
+}
+
+// Assigns every lifetime a slot of memory, returning the slot for each and what each slot needs.
+// Resources share a slot when they're never alive at the same time and have a memory type in
+// common.
+fn alias_memory(lifetimes: &[Lifetime]) -> (Vec<usize>, Vec<vk::MemoryRequirements>) {
+    let mut by_first: Vec<usize> = (0..lifetimes.len()).collect();
+    by_first.sort_by_key(|&i| lifetimes[i].first);
+
+    // Requirements and the last use of whoever is in it
+    let mut slots: Vec<(vk::MemoryRequirements, usize)> = Vec::new();
+    let mut slot_of = vec![0; lifetimes.len()];
+    for i in by_first {
+        let lifetime = &lifetimes[i];
+        let free = slots.iter().position(|(requirements, last)| {
+            *last < lifetime.first
+                && requirements.memory_type_bits & lifetime.requirements.memory_type_bits != 0
+        });
+        slot_of[i] = match free {
+            Some(slot) => {
+                let (requirements, last) = &mut slots[slot];
+                requirements.size = requirements.size.max(lifetime.requirements.size);
+                requirements.alignment =
+                    requirements.alignment.max(lifetime.requirements.alignment);
+                requirements.memory_type_bits &= lifetime.requirements.memory_type_bits;
+                *last = lifetime.last;
+                slot
+            }
+            None => {
+                slots.push((lifetime.requirements, lifetime.last));
+                slots.len() - 1
+            }
+        };
+    }
+
+    (slot_of, slots.into_iter().map(|(r, _)| r).collect())
+}
+
+fn image_usage(access: Access) -> vk::ImageUsageFlags {
+    match access {
+        Access::TransferRead => vk::ImageUsageFlags::TRANSFER_SRC,
+        Access::TransferWrite => vk::ImageUsageFlags::TRANSFER_DST,
+        Access::VertexShaderRead | Access::FragmentShaderRead | Access::ComputeShaderRead => {
+            vk::ImageUsageFlags::SAMPLED
+        }
+        Access::ComputeStorageRead | Access::ComputeStorageWrite => vk::ImageUsageFlags::STORAGE,
+        Access::ColorAttachmentWrite => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        Access::DepthAttachmentWrite => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        _ => vk::ImageUsageFlags::empty(),
+    }
+}
+
+fn buffer_usage(access: Access) -> vk::BufferUsageFlags {
+    match access {
+        Access::TransferRead => vk::BufferUsageFlags::TRANSFER_SRC,
+        Access::TransferWrite => vk::BufferUsageFlags::TRANSFER_DST,
+        Access::VertexBuffer => vk::BufferUsageFlags::VERTEX_BUFFER,
+        Access::IndexBuffer => vk::BufferUsageFlags::INDEX_BUFFER,
+        Access::IndirectBuffer => vk::BufferUsageFlags::INDIRECT_BUFFER,
+        Access::VertexShaderRead | Access::FragmentShaderRead | Access::ComputeShaderRead => {
+            vk::BufferUsageFlags::UNIFORM_BUFFER
+        }
+        Access::ComputeStorageRead | Access::ComputeStorageWrite => {
+            vk::BufferUsageFlags::STORAGE_BUFFER
+        }
+        _ => vk::BufferUsageFlags::empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_ordered_by_dependencies() {
+        let mut graph = FrameGraph::new();
+        let swapchain = graph.import_image(
+            "swapchain",
+            vk::Format::B8G8R8A8_SRGB,
+            Access::Acquire,
+            Access::Present,
+        );
+        let scene = graph.create_image("scene", ImageDesc::new(vk::Format::R16G16B16A16_SFLOAT));
+        let depth = graph.create_image("depth", ImageDesc::new(vk::Format::D32_SFLOAT));
+        let unused = graph.create_image("unused", ImageDesc::new(vk::Format::R8_UNORM));
+
+        // Added before what it reads is written
+        let post = graph
+            .add_pass("post")
+            .read(scene, Access::FragmentShaderRead)
+            .color(swapchain, None)
+            .id();
+        let debug = graph.add_pass("debug").color(unused, Some([0.0; 4])).id();
+        let main = graph
+            .add_pass("main")
+            .color(scene, Some([0.0; 4]))
+            .depth(depth, Some(1.0))
+            .id();
+
+        let order = graph.schedule().unwrap();
+        assert_eq!(order, vec![main.0, post.0]);
+        assert!(!order.contains(&debug.0));
+    }
+
+    #[test]
+    fn cycles_rejected() {
+        let mut graph = FrameGraph::new();
+        let output = graph.import_buffer("output", 64, Access::Nothing, Access::Nothing);
+        let a = graph.create_buffer("a", 64);
+        let b = graph.create_buffer("b", 64);
+        graph
+            .add_pass("first")
+            .read(a, Access::ComputeStorageRead)
+            .write(b, Access::ComputeStorageWrite)
+            .write(output, Access::ComputeStorageWrite);
+        graph
+            .add_pass("second")
+            .read(b, Access::ComputeStorageRead)
+            .write(a, Access::ComputeStorageWrite);
+        // "first" reads a before "second" writes it, but also needs b from it
+        assert!(graph.schedule().is_err());
+    }
+
+    #[test]
+    fn memory_aliased_between_disjoint_lifetimes() {
+        let requirements = |size, memory_type_bits| vk::MemoryRequirements {
+            size,
+            alignment: 256,
+            memory_type_bits,
+        };
+        let lifetimes = [
+            Lifetime {
+                first: 0,
+                last: 1,
+                requirements: requirements(1024, 0b11),
+            },
+            Lifetime {
+                first: 1,
+                last: 2,
+                requirements: requirements(512, 0b11),
+            },
+            // Fits where the first one was
+            Lifetime {
+                first: 2,
+                last: 3,
+                requirements: requirements(2048, 0b01),
+            },
+            // Can't live in the same memory type
+            Lifetime {
+                first: 3,
+                last: 3,
+                requirements: requirements(256, 0b100),
+            },
+        ];
+
+        let (slot_of, slots) = alias_memory(&lifetimes);
+        assert_eq!(slot_of, vec![0, 1, 0, 2]);
+        assert_eq!(slots.len(), 3);
+        assert_eq!(slots[0].size, 2048);
+        assert_eq!(slots[0].memory_type_bits, 0b01);
+    }
+}
@@ -1,3 +1,19 @@
+pub mod barrier;
+pub mod bindless;
+pub mod compute;
+pub mod descriptor;
+pub mod draw;
+pub mod dynamic_rendering;
+pub mod frame_sync;
+pub mod graph;
+pub mod pipeline;
+pub mod recorder;
+pub mod reflect;
 pub mod renderer;
+pub mod shader;
+pub mod shader_cache;
+pub mod sync2;
+pub mod tracker;
+pub mod vertex;
 
 mod window;
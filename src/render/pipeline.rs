@@ -0,0 +1,755 @@
+// Graphics pipelines described as plain data. A description hashes to the pipeline built from it,
+// so anything that wants a pipeline just asks the cache and gets the existing one if it matches.
+//
+// Viewport and scissor are always dynamic, so one pipeline works for any target size.
+//
+// Underneath is a VkPipelineCache that's saved to disk, so drivers can skip compiling pipelines
+// they've already seen in an earlier run.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use super::dynamic_rendering::PipelineRenderingCreateInfo;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderStage {
+    pub stage: vk::ShaderStageFlags,
+    pub module: vk::ShaderModule,
+    // Name of the entry point function, see ShaderReflection::entry_point
+    pub entry_point: String,
+}
+
+impl ShaderStage {
+    pub fn new(stage: vk::ShaderStageFlags, module: vk::ShaderModule, entry_point: &str) -> Self {
+        ShaderStage {
+            stage,
+            module,
+            entry_point: entry_point.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VertexBinding {
+    pub binding: u32,
+    pub stride: u32,
+    pub input_rate: vk::VertexInputRate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub binding: u32,
+    pub format: vk::Format,
+    pub offset: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    pub bindings: Vec<VertexBinding>,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+impl VertexLayout {
+    pub fn from_descriptions(
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        VertexLayout {
+            bindings: bindings
+                .iter()
+                .map(|b| VertexBinding {
+                    binding: b.binding,
+                    stride: b.stride,
+                    input_rate: b.input_rate,
+                })
+                .collect(),
+            attributes: attributes
+                .iter()
+                .map(|a| VertexAttribute {
+                    location: a.location,
+                    binding: a.binding,
+                    format: a.format,
+                    offset: a.offset,
+                })
+                .collect(),
+        }
+    }
+
+    // Another binding's attributes next to these, e.g. per instance data after the vertices
+    pub fn extend(&mut self, other: VertexLayout) {
+        self.bindings.extend(other.bindings);
+        self.attributes.extend(other.attributes);
+    }
+
+    pub fn binding_descriptions(&self) -> Vec<vk::VertexInputBindingDescription> {
+        self.bindings
+            .iter()
+            .map(|b| vk::VertexInputBindingDescription {
+                binding: b.binding,
+                stride: b.stride,
+                input_rate: b.input_rate,
+            })
+            .collect()
+    }
+
+    pub fn attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.attributes
+            .iter()
+            .map(|a| vk::VertexInputAttributeDescription {
+                location: a.location,
+                binding: a.binding,
+                format: a.format,
+                offset: a.offset,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RasterizerState {
+    pub polygon_mode: vk::PolygonMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub depth_clamp: bool,
+}
+
+impl Default for RasterizerState {
+    fn default() -> Self {
+        RasterizerState {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_clamp: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StencilFace {
+    pub fail_op: vk::StencilOp,
+    pub pass_op: vk::StencilOp,
+    pub depth_fail_op: vk::StencilOp,
+    pub compare_op: vk::CompareOp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StencilState {
+    pub front: StencilFace,
+    pub back: StencilFace,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+    pub reference: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DepthStencilState {
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare_op: vk::CompareOp,
+    pub stencil: Option<StencilState>,
+}
+
+impl Default for DepthStencilState {
+    fn default() -> Self {
+        DepthStencilState {
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::LESS,
+            stencil: None,
+        }
+    }
+}
+
+impl DepthStencilState {
+    pub fn disabled() -> Self {
+        DepthStencilState {
+            depth_test: false,
+            depth_write: false,
+            depth_compare_op: vk::CompareOp::ALWAYS,
+            stencil: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlendFactors {
+    pub src_color: vk::BlendFactor,
+    pub dst_color: vk::BlendFactor,
+    pub color_op: vk::BlendOp,
+    pub src_alpha: vk::BlendFactor,
+    pub dst_alpha: vk::BlendFactor,
+    pub alpha_op: vk::BlendOp,
+}
+
+// One per color attachment, `blend: None` writes the fragment as is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlendState {
+    pub blend: Option<BlendFactors>,
+    pub write_mask: vk::ColorComponentFlags,
+}
+
+impl BlendState {
+    pub fn opaque() -> Self {
+        BlendState {
+            blend: None,
+            write_mask: vk::ColorComponentFlags::all(),
+        }
+    }
+
+    // Straight (non premultiplied) alpha
+    pub fn alpha() -> Self {
+        BlendState {
+            blend: Some(BlendFactors {
+                src_color: vk::BlendFactor::SRC_ALPHA,
+                dst_color: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                color_op: vk::BlendOp::ADD,
+                src_alpha: vk::BlendFactor::ONE,
+                dst_alpha: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                alpha_op: vk::BlendOp::ADD,
+            }),
+            write_mask: vk::ColorComponentFlags::all(),
+        }
+    }
+
+    pub fn additive() -> Self {
+        BlendState {
+            blend: Some(BlendFactors {
+                src_color: vk::BlendFactor::ONE,
+                dst_color: vk::BlendFactor::ONE,
+                color_op: vk::BlendOp::ADD,
+                src_alpha: vk::BlendFactor::ONE,
+                dst_alpha: vk::BlendFactor::ONE,
+                alpha_op: vk::BlendOp::ADD,
+            }),
+            write_mask: vk::ColorComponentFlags::all(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AttachmentFormats {
+    pub color: Vec<vk::Format>,
+    pub depth: Option<vk::Format>,
+    pub samples: vk::SampleCountFlags,
+}
+
+// What the pipeline renders into. With formats the cache creates a compatible render pass.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RenderTarget {
+    RenderPass {
+        render_pass: vk::RenderPass,
+        subpass: u32,
+    },
+    Formats(AttachmentFormats),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GraphicsPipelineDesc {
+    pub stages: Vec<ShaderStage>,
+    pub vertex_layout: VertexLayout,
+    pub topology: vk::PrimitiveTopology,
+    pub rasterizer: RasterizerState,
+    pub depth_stencil: DepthStencilState,
+    pub blend: Vec<BlendState>,
+    pub layout: vk::PipelineLayout,
+    pub target: RenderTarget,
+}
+
+impl GraphicsPipelineDesc {
+    // Triangle list, back face culling, depth tested and one opaque color attachment
+    pub fn new(
+        vertex_shader: ShaderStage,
+        fragment_shader: ShaderStage,
+        vertex_layout: VertexLayout,
+        layout: vk::PipelineLayout,
+        target: RenderTarget,
+    ) -> Self {
+        GraphicsPipelineDesc {
+            stages: vec![vertex_shader, fragment_shader],
+            vertex_layout,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            rasterizer: RasterizerState::default(),
+            depth_stencil: DepthStencilState::default(),
+            blend: vec![BlendState::opaque()],
+            layout,
+            target,
+        }
+    }
+
+    pub fn uses_module(&self, module: vk::ShaderModule) -> bool {
+        self.stages.iter().any(|s| s.module == module)
+    }
+}
+
+// Header every VkPipelineCache blob starts with: length, version, vendor, device, cache UUID
+const CACHE_HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+// Data saved by a different driver or GPU is at best ignored and at worst crashes the driver, so
+// anything that doesn't match exactly is thrown away.
+pub fn cache_data_matches(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    if data.len() < CACHE_HEADER_SIZE {
+        return false;
+    }
+    let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    word(0) as usize >= CACHE_HEADER_SIZE
+        && word(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && word(8) == properties.vendor_id
+        && word(12) == properties.device_id
+        && data[16..CACHE_HEADER_SIZE] == properties.pipeline_cache_uuid
+}
+
+// Next to the executable, like the shader cache
+pub fn default_cache_path() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    Some(exe.parent()?.join("pipeline_cache.bin"))
+}
+
+pub struct PipelineCache {
+    pipelines: HashMap<GraphicsPipelineDesc, vk::Pipeline>,
+    // Compatible render passes for pipelines that only gave attachment formats
+    render_passes: HashMap<AttachmentFormats, vk::RenderPass>,
+    vk_cache: vk::PipelineCache,
+    // Where vk_cache is loaded from and saved to, None keeps it in memory only
+    path: Option<PathBuf>,
+    // Pipelines that only gave attachment formats are built against those instead of a render pass
+    dynamic_rendering: bool,
+}
+
+impl PipelineCache {
+    pub fn new(
+        device: &ash::Device,
+        properties: &vk::PhysicalDeviceProperties,
+        path: Option<PathBuf>,
+    ) -> Result<Self, &'static str> {
+        let data = match path.as_ref().map(fs::read) {
+            Some(Ok(data)) if cache_data_matches(&data, properties) => data,
+            Some(Ok(_)) => {
+                info!("Pipeline cache is from another driver or device, starting over");
+                Vec::new()
+            }
+            _ => Vec::new(),
+        };
+
+        let cache_info = vk::PipelineCacheCreateInfo::builder().initial_data(&data);
+        let vk_cache = unsafe {
+            device
+                .create_pipeline_cache(&cache_info, None)
+                .map_err(|_| "Failed to create pipeline cache")?
+        };
+
+        Ok(PipelineCache {
+            pipelines: HashMap::new(),
+            render_passes: HashMap::new(),
+            vk_cache,
+            path,
+            dynamic_rendering: false,
+        })
+    }
+
+    // Only for devices created with dynamic rendering, before any Formats pipeline is built
+    pub fn set_dynamic_rendering(&mut self, dynamic_rendering: bool) {
+        self.dynamic_rendering = dynamic_rendering;
+    }
+
+    // Writes the driver's cache to disk, through a temporary file so a crash can't leave a torn
+    // one behind
+    pub fn save(&self, device: &ash::Device) -> Result<(), &'static str> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let data = unsafe { device.get_pipeline_cache_data(self.vk_cache) }
+            .map_err(|_| "Failed to get pipeline cache data")?;
+
+        let write = || -> io::Result<()> {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, &data)?;
+            fs::rename(&tmp, path)
+        };
+        write().map_err(|e| {
+            error!("{}: {}", path.display(), e);
+            "Failed to write pipeline cache"
+        })
+    }
+
+    // For pipelines that aren't cached by description, like compute pipelines
+    pub fn vk_cache(&self) -> vk::PipelineCache {
+        self.vk_cache
+    }
+
+    // The pipeline for `desc`, built the first time it's asked for
+    pub fn graphics_pipeline(
+        &mut self,
+        device: &ash::Device,
+        desc: &GraphicsPipelineDesc,
+    ) -> Result<vk::Pipeline, &'static str> {
+        if let Some(&pipeline) = self.pipelines.get(desc) {
+            return Ok(pipeline);
+        }
+
+        let (render_pass, subpass, formats) = match desc.target {
+            RenderTarget::RenderPass {
+                render_pass,
+                subpass,
+            } => (render_pass, subpass, None),
+            RenderTarget::Formats(ref formats) if self.dynamic_rendering => {
+                (vk::RenderPass::null(), 0, Some(formats))
+            }
+            RenderTarget::Formats(ref formats) => {
+                (self.compatible_render_pass(device, formats)?, 0, None)
+            }
+        };
+
+        let pipeline =
+            create_graphics_pipeline(device, self.vk_cache, desc, render_pass, subpass, formats)?;
+        self.pipelines.insert(desc.clone(), pipeline);
+        Ok(pipeline)
+    }
+
+    // Only for pipelines already built, for when a `&mut` isn't at hand
+    pub fn get(&self, desc: &GraphicsPipelineDesc) -> Option<vk::Pipeline> {
+        self.pipelines.get(desc).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    // Destroys every pipeline `f` matches, e.g. the ones using a shader module about to go away.
+    // The caller makes sure the GPU is done with them.
+    pub fn evict<F: Fn(&GraphicsPipelineDesc) -> bool>(&mut self, device: &ash::Device, f: F) {
+        self.pipelines.retain(|desc, pipeline| {
+            if f(desc) {
+                unsafe { device.destroy_pipeline(*pipeline, None) };
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    // Saves the driver's cache first, failing to is only worth a warning
+    pub fn destroy(&mut self, device: &ash::Device) {
+        if let Err(e) = self.save(device) {
+            warn!("{}", e);
+        }
+
+        self.evict(device, |_| true);
+        for (_, render_pass) in self.render_passes.drain() {
+            unsafe { device.destroy_render_pass(render_pass, None) };
+        }
+        unsafe { device.destroy_pipeline_cache(self.vk_cache, None) };
+    }
+
+    // Compatibility only looks at formats and sample counts, load/store ops don't matter
+    fn compatible_render_pass(
+        &mut self,
+        device: &ash::Device,
+        formats: &AttachmentFormats,
+    ) -> Result<vk::RenderPass, &'static str> {
+        if let Some(&render_pass) = self.render_passes.get(formats) {
+            return Ok(render_pass);
+        }
+
+        let mut attachments: Vec<vk::AttachmentDescription> = formats
+            .color
+            .iter()
+            .map(|&format| {
+                vk::AttachmentDescription::builder()
+                    .format(format)
+                    .samples(formats.samples)
+                    .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .build()
+            })
+            .collect();
+        let color_refs: Vec<vk::AttachmentReference> = (0..formats.color.len() as u32)
+            .map(|attachment| vk::AttachmentReference {
+                attachment,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            })
+            .collect();
+        let depth_ref = vk::AttachmentReference {
+            attachment: attachments.len() as u32,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+
+        let mut subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs);
+        if let Some(format) = formats.depth {
+            attachments.push(
+                vk::AttachmentDescription::builder()
+                    .format(format)
+                    .samples(formats.samples)
+                    .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .build(),
+            );
+            subpass = subpass.depth_stencil_attachment(&depth_ref);
+        }
+        let subpasses = [subpass.build()];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(&subpasses);
+        let render_pass = unsafe {
+            device
+                .create_render_pass(&render_pass_info, None)
+                .map_err(|_| "Failed to create render pass")?
+        };
+
+        self.render_passes.insert(formats.clone(), render_pass);
+        Ok(render_pass)
+    }
+}
+
+// Pushes the part of `data` that `range` covers. `data` mirrors the shader's whole push constant
+// block, starting at offset 0.
+pub fn cmd_push_constants<T: Copy>(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    layout: vk::PipelineLayout,
+    range: &vk::PushConstantRange,
+    data: &T,
+) {
+    let start = range.offset as usize;
+    let end = start + range.size as usize;
+    assert!(
+        end <= mem::size_of::<T>(),
+        "Push constant range outside of the data"
+    );
+    unsafe {
+        let bytes = std::slice::from_raw_parts(data as *const T as *const u8, mem::size_of::<T>());
+        device.cmd_push_constants(
+            command_buffer,
+            layout,
+            range.stage_flags,
+            range.offset,
+            &bytes[start..end],
+        );
+    }
+}
+
+fn create_graphics_pipeline(
+    device: &ash::Device,
+    vk_cache: vk::PipelineCache,
+    desc: &GraphicsPipelineDesc,
+    render_pass: vk::RenderPass,
+    subpass: u32,
+    // Set for dynamic rendering, with a null render pass
+    formats: Option<&AttachmentFormats>,
+) -> Result<vk::Pipeline, &'static str> {
+    let entry_points = desc
+        .stages
+        .iter()
+        .map(|s| CString::new(s.entry_point.as_str()))
+        .collect::<Result<Vec<CString>, _>>()
+        .map_err(|_| "Shader entry point contains a nul")?;
+    let stages: Vec<vk::PipelineShaderStageCreateInfo> = desc
+        .stages
+        .iter()
+        .zip(&entry_points)
+        .map(|(s, entry_point)| {
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(s.stage)
+                .module(s.module)
+                .name(entry_point)
+                .build()
+        })
+        .collect();
+
+    let vertex_bindings = desc.vertex_layout.binding_descriptions();
+    let vertex_attributes = desc.vertex_layout.attribute_descriptions();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(&vertex_bindings)
+        .vertex_attribute_descriptions(&vertex_attributes);
+
+    let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(desc.topology)
+        .primitive_restart_enable(false);
+
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+    // Viewport and scissor are dynamic, only the counts matter here
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    let rasterizer = &desc.rasterizer;
+    let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(rasterizer.depth_clamp)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(rasterizer.polygon_mode)
+        .line_width(1.0)
+        .cull_mode(rasterizer.cull_mode)
+        .front_face(rasterizer.front_face)
+        .depth_bias_enable(false);
+
+    let samples = match desc.target {
+        RenderTarget::Formats(ref formats) => formats.samples,
+        RenderTarget::RenderPass { .. } => vk::SampleCountFlags::TYPE_1,
+    };
+    let multisample_info = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(samples);
+
+    let blend_attachments: Vec<vk::PipelineColorBlendAttachmentState> = desc
+        .blend
+        .iter()
+        .map(|b| {
+            let attachment =
+                vk::PipelineColorBlendAttachmentState::builder().color_write_mask(b.write_mask);
+            match b.blend {
+                Some(f) => attachment
+                    .blend_enable(true)
+                    .src_color_blend_factor(f.src_color)
+                    .dst_color_blend_factor(f.dst_color)
+                    .color_blend_op(f.color_op)
+                    .src_alpha_blend_factor(f.src_alpha)
+                    .dst_alpha_blend_factor(f.dst_alpha)
+                    .alpha_blend_op(f.alpha_op),
+                None => attachment.blend_enable(false),
+            }
+            .build()
+        })
+        .collect();
+    let color_blend_info = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .attachments(&blend_attachments);
+
+    let depth_stencil = &desc.depth_stencil;
+    let stencil_op = |face: StencilFace, state: StencilState| vk::StencilOpState {
+        fail_op: face.fail_op,
+        pass_op: face.pass_op,
+        depth_fail_op: face.depth_fail_op,
+        compare_op: face.compare_op,
+        compare_mask: state.compare_mask,
+        write_mask: state.write_mask,
+        reference: state.reference,
+    };
+    let (front, back) = match depth_stencil.stencil {
+        Some(s) => (stencil_op(s.front, s), stencil_op(s.back, s)),
+        None => Default::default(),
+    };
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(depth_stencil.depth_test)
+        .depth_write_enable(depth_stencil.depth_write)
+        .depth_compare_op(depth_stencil.depth_compare_op)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(depth_stencil.stencil.is_some())
+        .front(front)
+        .back(back);
+
+    let mut rendering_info = formats.map(PipelineRenderingCreateInfo::new);
+    let mut pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly)
+        .dynamic_state(&dynamic_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterizer_info)
+        .multisample_state(&multisample_info)
+        .color_blend_state(&color_blend_info)
+        .depth_stencil_state(&depth_stencil_state)
+        .layout(desc.layout)
+        .render_pass(render_pass)
+        .subpass(subpass);
+    if let Some(rendering_info) = &mut rendering_info {
+        pipeline_info = pipeline_info.push_next(rendering_info);
+    }
+
+    unsafe {
+        let pipelines = device
+            .create_graphics_pipelines(vk_cache, &[pipeline_info.build()], None)
+            .map_err(|_| "Failed to create graphics pipeline")?;
+        Ok(pipelines[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn descs_hash_by_state() {
+        let layout = VertexLayout::default();
+        let target = RenderTarget::Formats(AttachmentFormats {
+            color: vec![vk::Format::B8G8R8A8_SRGB],
+            depth: Some(vk::Format::D32_SFLOAT),
+            samples: vk::SampleCountFlags::TYPE_1,
+        });
+        let opaque = GraphicsPipelineDesc::new(
+            ShaderStage::new(
+                vk::ShaderStageFlags::VERTEX,
+                vk::ShaderModule::null(),
+                "main",
+            ),
+            ShaderStage::new(
+                vk::ShaderStageFlags::FRAGMENT,
+                vk::ShaderModule::null(),
+                "main",
+            ),
+            layout,
+            vk::PipelineLayout::null(),
+            target,
+        );
+        let mut blended = opaque.clone();
+        blended.blend = vec![BlendState::alpha()];
+
+        let mut descs = HashSet::new();
+        descs.insert(opaque.clone());
+        descs.insert(opaque.clone());
+        descs.insert(blended);
+        let mut renamed = opaque.clone();
+        renamed.stages[0].entry_point = "VSMain".to_string();
+        descs.insert(renamed);
+        assert_eq!(descs.len(), 3);
+        assert!(opaque.uses_module(vk::ShaderModule::null()));
+    }
+
+    #[test]
+    fn cache_data_checked_against_device() {
+        let mut properties = vk::PhysicalDeviceProperties {
+            vendor_id: 0x10de,
+            device_id: 0x1b80,
+            pipeline_cache_uuid: [7; vk::UUID_SIZE],
+            ..Default::default()
+        };
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&(CACHE_HEADER_SIZE as u32).to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&0x10deu32.to_le_bytes());
+        data.extend_from_slice(&0x1b80u32.to_le_bytes());
+        data.extend_from_slice(&[7; vk::UUID_SIZE]);
+        data.extend_from_slice(b"driver data");
+        assert!(cache_data_matches(&data, &properties));
+        assert!(!cache_data_matches(&data[..20], &properties));
+
+        properties.pipeline_cache_uuid[0] = 8;
+        assert!(!cache_data_matches(&data, &properties));
+    }
+}
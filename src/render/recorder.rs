@@ -0,0 +1,192 @@
+// Records one pass on several threads. The work is split into contiguous chunks, each worker
+// records its chunk into a secondary command buffer from its own pool, and the buffers are
+// executed in chunk order so the result doesn't depend on which thread finished first.
+
+use std::sync::Mutex;
+use std::thread;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use super::dynamic_rendering::InheritanceRenderingInfo;
+use super::graph::PassContext;
+
+// Fewer than this per worker isn't worth a thread
+const MIN_ITEMS_PER_WORKER: usize = 64;
+
+// Only ever touched by one thread at a time, pools are externally synchronized
+struct Worker {
+    pool: vk::CommandPool,
+    buffers: Vec<vk::CommandBuffer>,
+    used: usize,
+}
+
+impl Worker {
+    fn next_buffer(&mut self, device: &ash::Device) -> vk::CommandBuffer {
+        if self.used == self.buffers.len() {
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(self.pool)
+                .command_buffer_count(1)
+                .level(vk::CommandBufferLevel::SECONDARY);
+            let buffer = unsafe { device.allocate_command_buffers(&alloc_info).unwrap()[0] };
+            self.buffers.push(buffer);
+        }
+        self.used += 1;
+        self.buffers[self.used - 1]
+    }
+}
+
+pub struct ParallelRecorder {
+    // [frame in flight][worker]
+    workers: Vec<Vec<Mutex<Worker>>>,
+}
+
+impl ParallelRecorder {
+    pub fn new(
+        device: &ash::Device,
+        queue_family_index: u32,
+        frames_in_flight: usize,
+        threads: usize,
+    ) -> Result<Self, &'static str> {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+
+        let mut recorder = ParallelRecorder {
+            workers: Vec::with_capacity(frames_in_flight),
+        };
+        for _ in 0..frames_in_flight {
+            let mut frame = Vec::with_capacity(threads.max(1));
+            for _ in 0..threads.max(1) {
+                let pool = match unsafe { device.create_command_pool(&pool_info, None) } {
+                    Ok(pool) => pool,
+                    Err(e) => {
+                        error!("Failed to create worker command pool: {}", e);
+                        recorder.destroy(device);
+                        return Err("Failed to create worker command pool");
+                    }
+                };
+                frame.push(Mutex::new(Worker {
+                    pool,
+                    buffers: Vec::new(),
+                    used: 0,
+                }));
+            }
+            recorder.workers.push(frame);
+        }
+        Ok(recorder)
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.first().map_or(1, |frame| frame.len())
+    }
+
+    // Once the frame's fence has signalled, its secondary buffers are free to reuse
+    pub fn begin_frame(&self, device: &ash::Device, frame: usize) {
+        for worker in &self.workers[frame] {
+            let mut worker = worker.lock().unwrap();
+            unsafe {
+                device
+                    .reset_command_pool(worker.pool, vk::CommandPoolResetFlags::empty())
+                    .unwrap();
+            }
+            worker.used = 0;
+        }
+    }
+
+    // For a pass declared with PassBuilder::secondary. `record` gets called once per chunk with
+    // a secondary command buffer that's already inside the pass, viewport and scissor set.
+    pub fn record<T, F>(&self, frame: usize, context: &PassContext, items: &[T], record: F)
+    where
+        T: Sync,
+        F: Fn(vk::CommandBuffer, &[T]) + Sync,
+    {
+        if items.is_empty() {
+            return;
+        }
+
+        let workers = &self.workers[frame];
+        let chunk_size = chunk_size(items.len(), workers.len());
+
+        let device = context.device;
+        // Under dynamic rendering there's no render pass to inherit, only the formats
+        let formats = context.dynamic_rendering();
+        let record_chunk = |worker: &Mutex<Worker>, chunk: &[T]| {
+            let buffer = worker.lock().unwrap().next_buffer(device);
+            let mut rendering_info = formats.as_ref().map(InheritanceRenderingInfo::new);
+            let mut inheritance = vk::CommandBufferInheritanceInfo::builder()
+                .render_pass(context.render_pass)
+                .subpass(0)
+                .framebuffer(context.framebuffer);
+            if let Some(rendering_info) = &mut rendering_info {
+                inheritance = inheritance.push_next(rendering_info);
+            }
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(
+                    vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                        | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+                )
+                .inheritance_info(&inheritance);
+            unsafe { device.begin_command_buffer(buffer, &begin_info).unwrap() };
+            context.set_viewport(buffer);
+            record(buffer, chunk);
+            unsafe { device.end_command_buffer(buffer).unwrap() };
+            buffer
+        };
+
+        // The calling thread takes the first chunk instead of sitting idle
+        let mut chunks = items.chunks(chunk_size).zip(workers.iter());
+        let (first_chunk, first_worker) = chunks.next().unwrap();
+        let buffers: Vec<vk::CommandBuffer> = thread::scope(|scope| {
+            let handles: Vec<_> = chunks
+                .map(|(chunk, worker)| scope.spawn(move || record_chunk(worker, chunk)))
+                .collect();
+            let first = record_chunk(first_worker, first_chunk);
+            std::iter::once(first)
+                .chain(handles.into_iter().map(|h| h.join().unwrap()))
+                .collect()
+        });
+
+        unsafe {
+            device.cmd_execute_commands(context.command_buffer, &buffers);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for frame in self.workers.drain(..) {
+            for worker in frame {
+                let worker = worker.into_inner().unwrap();
+                unsafe { device.destroy_command_pool(worker.pool, None) };
+            }
+        }
+    }
+}
+
+// How many workers to record with, leaving a core for everything else
+pub fn default_threads() -> usize {
+    thread::available_parallelism()
+        .map_or(1, |n| n.get().saturating_sub(1))
+        .clamp(1, 8)
+}
+
+// Even chunks over as many workers as the item count justifies
+fn chunk_size(items: usize, workers: usize) -> usize {
+    let chunks = items
+        .div_ceil(MIN_ITEMS_PER_WORKER)
+        .clamp(1, workers.max(1));
+    items.div_ceil(chunks).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_only_split_large_lists() {
+        assert_eq!(chunk_size(10, 4), 10);
+        assert_eq!(chunk_size(1000, 4), 250);
+        // Three chunks' worth, not eight tiny ones
+        assert_eq!(chunk_size(130, 8), 44);
+        assert_eq!(chunk_size(1000, 1), 1000);
+    }
+}
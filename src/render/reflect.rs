@@ -0,0 +1,812 @@
+// Just enough SPIR-V parsing to get descriptor bindings, push constants and vertex inputs out of
+// a compiled module, so pipeline layouts don't have to be kept in sync with the shaders by hand.
+
+use std::collections::HashMap;
+
+use ash::vk;
+
+const MAGIC: u32 = 0x0723_0203;
+
+// Opcodes
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Decorations
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+// Execution models
+const EXECUTION_VERTEX: u32 = 0;
+const EXECUTION_FRAGMENT: u32 = 4;
+const EXECUTION_GL_COMPUTE: u32 = 5;
+
+// Image dims
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    // 0 for runtime sized arrays, the caller has to pick a size
+    pub count: u32,
+    pub stages: vk::ShaderStageFlags,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PushConstants {
+    pub stages: vk::ShaderStageFlags,
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub format: vk::Format,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    // "main" for GLSL, whatever the HLSL function was called otherwise
+    pub entry_point: String,
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constants: Option<PushConstants>,
+    // Sorted by location, only filled in for vertex shaders
+    pub inputs: Vec<VertexInput>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Type {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct,
+    Pointer { pointee: u32 },
+}
+
+#[derive(Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    array_stride: Option<u32>,
+    built_in: bool,
+    block: bool,
+    buffer_block: bool,
+}
+
+#[derive(Default)]
+struct Member {
+    offset: u32,
+    matrix_stride: Option<u32>,
+}
+
+struct Module {
+    types: HashMap<u32, Type>,
+    struct_members: HashMap<u32, Vec<u32>>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<u32, Decorations>,
+    members: HashMap<(u32, u32), Member>,
+    // (id, pointer type, storage class)
+    variables: Vec<(u32, u32, u32)>,
+    stage: Option<vk::ShaderStageFlags>,
+    entry_point: String,
+    interface: Vec<u32>,
+}
+
+fn parse(spirv: &[u32]) -> Result<Module, &'static str> {
+    if spirv.len() < 5 || spirv[0] != MAGIC {
+        return Err("Not a SPIR-V module");
+    }
+
+    let mut module = Module {
+        types: HashMap::new(),
+        struct_members: HashMap::new(),
+        constants: HashMap::new(),
+        decorations: HashMap::new(),
+        members: HashMap::new(),
+        variables: Vec::new(),
+        stage: None,
+        entry_point: String::new(),
+        interface: Vec::new(),
+    };
+
+    let mut i = 5;
+    while i < spirv.len() {
+        let word_count = (spirv[i] >> 16) as usize;
+        let opcode = spirv[i] & 0xffff;
+        if word_count == 0 || i + word_count > spirv.len() {
+            return Err("Malformed SPIR-V instruction");
+        }
+        let ops = &spirv[i + 1..i + word_count];
+        i += word_count;
+
+        // Fewest operands of the instructions read below, so indexing them can't go out of bounds
+        let min_operands = match opcode {
+            OP_TYPE_SAMPLER | OP_TYPE_STRUCT => 1,
+            OP_TYPE_FLOAT | OP_TYPE_RUNTIME_ARRAY | OP_TYPE_SAMPLED_IMAGE => 2,
+            OP_ENTRY_POINT | OP_TYPE_INT | OP_TYPE_VECTOR | OP_TYPE_MATRIX | OP_TYPE_ARRAY
+            | OP_TYPE_POINTER | OP_VARIABLE => 3,
+            OP_TYPE_IMAGE => 8,
+            _ => 0,
+        };
+        if ops.len() < min_operands {
+            return Err("Malformed SPIR-V instruction");
+        }
+
+        match opcode {
+            OP_ENTRY_POINT if module.stage.is_none() => {
+                module.stage = Some(match ops[0] {
+                    EXECUTION_VERTEX => vk::ShaderStageFlags::VERTEX,
+                    EXECUTION_FRAGMENT => vk::ShaderStageFlags::FRAGMENT,
+                    EXECUTION_GL_COMPUTE => vk::ShaderStageFlags::COMPUTE,
+                    _ => return Err("Unsupported shader stage"),
+                });
+                // Name is a nul terminated string packed into words, interface ids follow it
+                let name_words = ops[2..]
+                    .iter()
+                    .position(|w| w.to_le_bytes().contains(&0))
+                    .ok_or("Malformed entry point")?
+                    + 1;
+                let name: Vec<u8> = ops[2..2 + name_words]
+                    .iter()
+                    .flat_map(|w| w.to_le_bytes().to_vec())
+                    .take_while(|&b| b != 0)
+                    .collect();
+                module.entry_point =
+                    String::from_utf8(name).map_err(|_| "Malformed entry point")?;
+                module.interface = ops[2 + name_words..].to_vec();
+            }
+            OP_TYPE_INT => {
+                module.types.insert(
+                    ops[0],
+                    Type::Int {
+                        width: ops[1],
+                        signed: ops[2] != 0,
+                    },
+                );
+            }
+            OP_TYPE_FLOAT => {
+                module.types.insert(ops[0], Type::Float { width: ops[1] });
+            }
+            OP_TYPE_VECTOR => {
+                module.types.insert(
+                    ops[0],
+                    Type::Vector {
+                        component: ops[1],
+                        count: ops[2],
+                    },
+                );
+            }
+            OP_TYPE_MATRIX => {
+                module.types.insert(
+                    ops[0],
+                    Type::Matrix {
+                        column: ops[1],
+                        count: ops[2],
+                    },
+                );
+            }
+            OP_TYPE_IMAGE => {
+                module.types.insert(
+                    ops[0],
+                    Type::Image {
+                        dim: ops[2],
+                        sampled: ops[6],
+                    },
+                );
+            }
+            OP_TYPE_SAMPLER => {
+                module.types.insert(ops[0], Type::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                module.types.insert(ops[0], Type::SampledImage);
+            }
+            OP_TYPE_ARRAY => {
+                module.types.insert(
+                    ops[0],
+                    Type::Array {
+                        element: ops[1],
+                        length: ops[2],
+                    },
+                );
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                module
+                    .types
+                    .insert(ops[0], Type::RuntimeArray { element: ops[1] });
+            }
+            OP_TYPE_STRUCT => {
+                module.types.insert(ops[0], Type::Struct);
+                module.struct_members.insert(ops[0], ops[1..].to_vec());
+            }
+            OP_TYPE_POINTER => {
+                module
+                    .types
+                    .insert(ops[0], Type::Pointer { pointee: ops[2] });
+            }
+            // Only 32 bit constants are needed, for array lengths
+            OP_CONSTANT if ops.len() >= 3 => {
+                module.constants.insert(ops[1], ops[2]);
+            }
+            OP_VARIABLE => {
+                module.variables.push((ops[1], ops[0], ops[2]));
+            }
+            OP_DECORATE if ops.len() >= 2 => {
+                let decorations = module.decorations.entry(ops[0]).or_default();
+                let value = ops.get(2).copied();
+                match ops[1] {
+                    DECORATION_BLOCK => decorations.block = true,
+                    DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                    DECORATION_ARRAY_STRIDE => decorations.array_stride = value,
+                    DECORATION_BUILT_IN => decorations.built_in = true,
+                    DECORATION_LOCATION => decorations.location = value,
+                    DECORATION_BINDING => decorations.binding = value,
+                    DECORATION_DESCRIPTOR_SET => decorations.set = value,
+                    _ => {}
+                }
+            }
+            OP_MEMBER_DECORATE if ops.len() >= 4 => {
+                let member = module.members.entry((ops[0], ops[1])).or_default();
+                match ops[2] {
+                    DECORATION_OFFSET => member.offset = ops[3],
+                    DECORATION_MATRIX_STRIDE => member.matrix_stride = Some(ops[3]),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(module)
+}
+
+impl Module {
+    fn get_type(&self, id: u32) -> Result<Type, &'static str> {
+        self.types
+            .get(&id)
+            .copied()
+            .ok_or("SPIR-V references an unknown type")
+    }
+
+    fn decorations(&self, id: u32) -> Option<&Decorations> {
+        self.decorations.get(&id)
+    }
+
+    // Size of a type in a push constant or buffer block, going by the explicit layout decorations
+    fn size_of(&self, id: u32, matrix_stride: Option<u32>) -> Result<u32, &'static str> {
+        Ok(match self.get_type(id)? {
+            Type::Int { width, .. } | Type::Float { width } => width / 8,
+            Type::Vector { component, count } => self.size_of(component, None)? * count,
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => stride * count,
+                None => self.size_of(column, None)? * count,
+            },
+            Type::Array { element, length } => {
+                let length = *self
+                    .constants
+                    .get(&length)
+                    .ok_or("Array length isn't a constant")?;
+                let stride = match self.decorations(id).and_then(|d| d.array_stride) {
+                    Some(stride) => stride,
+                    None => self.size_of(element, None)?,
+                };
+                stride * length
+            }
+            Type::RuntimeArray { .. } => 0,
+            Type::Struct => {
+                let members = self
+                    .struct_members
+                    .get(&id)
+                    .ok_or("Malformed SPIR-V instruction")?;
+                let mut size = 0;
+                for (index, &member_type) in members.iter().enumerate() {
+                    let member = self.members.get(&(id, index as u32));
+                    let offset = member.map_or(0, |m| m.offset);
+                    let stride = member.and_then(|m| m.matrix_stride);
+                    size = size.max(offset + self.size_of(member_type, stride)?);
+                }
+                size
+            }
+            _ => return Err("Type has no size"),
+        })
+    }
+
+    // Lowest member offset, push constant ranges don't have to start at 0
+    fn struct_offset(&self, id: u32) -> u32 {
+        (0..self.struct_members.get(&id).map_or(0, |m| m.len()))
+            .filter_map(|index| self.members.get(&(id, index as u32)).map(|m| m.offset))
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn descriptor_type(
+        &self,
+        pointee: u32,
+        storage: u32,
+    ) -> Result<(vk::DescriptorType, u32), &'static str> {
+        let (mut ty, mut count) = (pointee, 1);
+        match self.get_type(ty)? {
+            Type::Array { element, length } => {
+                ty = element;
+                count = *self
+                    .constants
+                    .get(&length)
+                    .ok_or("Array length isn't a constant")?;
+            }
+            Type::RuntimeArray { element } => {
+                ty = element;
+                count = 0;
+            }
+            _ => {}
+        }
+
+        let descriptor_type = match (storage, self.get_type(ty)?) {
+            (STORAGE_UNIFORM_CONSTANT, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (STORAGE_UNIFORM_CONSTANT, Type::SampledImage) => {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+            (STORAGE_UNIFORM_CONSTANT, Type::Image { dim, sampled }) => match (dim, sampled) {
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            (STORAGE_UNIFORM, Type::Struct) => {
+                if matches!(self.decorations(ty), Some(d) if d.buffer_block) {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                }
+            }
+            (STORAGE_STORAGE_BUFFER, Type::Struct) => vk::DescriptorType::STORAGE_BUFFER,
+            _ => return Err("Unsupported descriptor type in shader"),
+        };
+
+        Ok((descriptor_type, count))
+    }
+
+    // Matrices take up one location per column
+    fn input_formats(&self, id: u32) -> Result<Vec<vk::Format>, &'static str> {
+        let (component, count) = match self.get_type(id)? {
+            Type::Matrix { column, count } => {
+                if !matches!(self.get_type(column)?, Type::Vector { .. }) {
+                    return Err("Malformed SPIR-V instruction");
+                }
+                let column_format = self.input_formats(column)?;
+                return Ok(vec![column_format[0]; count as usize]);
+            }
+            Type::Vector { component, count } => (component, count),
+            _ => (id, 1),
+        };
+
+        use vk::Format as F;
+        let formats = match self.get_type(component)? {
+            Type::Float { width: 32 } => [
+                F::R32_SFLOAT,
+                F::R32G32_SFLOAT,
+                F::R32G32B32_SFLOAT,
+                F::R32G32B32A32_SFLOAT,
+            ],
+            Type::Int {
+                width: 32,
+                signed: true,
+            } => [
+                F::R32_SINT,
+                F::R32G32_SINT,
+                F::R32G32B32_SINT,
+                F::R32G32B32A32_SINT,
+            ],
+            Type::Int {
+                width: 32,
+                signed: false,
+            } => [
+                F::R32_UINT,
+                F::R32G32_UINT,
+                F::R32G32B32_UINT,
+                F::R32G32B32A32_UINT,
+            ],
+            _ => return Err("Unsupported vertex input type"),
+        };
+        let format = (count as usize)
+            .checked_sub(1)
+            .and_then(|index| formats.get(index))
+            .copied()
+            .ok_or("Malformed SPIR-V instruction")?;
+        Ok(vec![format])
+    }
+}
+
+pub fn reflect(spirv: &[u32]) -> Result<ShaderReflection, &'static str> {
+    let module = parse(spirv)?;
+    let stage = module.stage.ok_or("SPIR-V module has no entry point")?;
+
+    let mut reflection = ShaderReflection {
+        stage,
+        entry_point: module.entry_point.clone(),
+        bindings: Vec::new(),
+        push_constants: None,
+        inputs: Vec::new(),
+    };
+
+    for &(id, pointer, storage) in &module.variables {
+        let pointee = match module.get_type(pointer)? {
+            Type::Pointer { pointee, .. } => pointee,
+            _ => return Err("Variable isn't a pointer"),
+        };
+
+        match storage {
+            STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                let decorations = module.decorations(id);
+                let binding = decorations
+                    .and_then(|d| d.binding)
+                    .ok_or("Shader resource without a binding")?;
+                let set = decorations.and_then(|d| d.set).unwrap_or(0);
+                let (descriptor_type, count) = module.descriptor_type(pointee, storage)?;
+                reflection.bindings.push(DescriptorBinding {
+                    set,
+                    binding,
+                    descriptor_type,
+                    count,
+                    stages: stage,
+                });
+            }
+            STORAGE_PUSH_CONSTANT => {
+                let offset = module.struct_offset(pointee);
+                let size = module.size_of(pointee, None)? - offset;
+                reflection.push_constants = Some(PushConstants {
+                    stages: stage,
+                    offset,
+                    size,
+                });
+            }
+            STORAGE_INPUT if stage == vk::ShaderStageFlags::VERTEX => {
+                // Interface blocks and builtins aren't vertex attributes
+                let decorations = module.decorations(id);
+                if matches!(decorations, Some(d) if d.built_in) || !module.interface.contains(&id) {
+                    continue;
+                }
+                let location = decorations
+                    .and_then(|d| d.location)
+                    .ok_or("Vertex input without a location")?;
+                for (i, format) in module.input_formats(pointee)?.into_iter().enumerate() {
+                    reflection.inputs.push(VertexInput {
+                        location: location + i as u32,
+                        format,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+    reflection.inputs.sort_by_key(|i| i.location);
+    Ok(reflection)
+}
+
+// Everything the stages of one pipeline need, merged into what the pipeline layout is built from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceLayout {
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constants: Option<PushConstants>,
+}
+
+impl ResourceLayout {
+    pub fn merge(shaders: &[&ShaderReflection]) -> Result<Self, &'static str> {
+        let mut layout = ResourceLayout::default();
+
+        for shader in shaders {
+            for binding in &shader.bindings {
+                match layout
+                    .bindings
+                    .iter_mut()
+                    .find(|b| b.set == binding.set && b.binding == binding.binding)
+                {
+                    Some(existing) => {
+                        if existing.descriptor_type != binding.descriptor_type
+                            || existing.count != binding.count
+                        {
+                            error!(
+                                "Set {} binding {} is {:?} in one stage and {:?} in another",
+                                binding.set,
+                                binding.binding,
+                                existing.descriptor_type,
+                                binding.descriptor_type
+                            );
+                            return Err("Shader stages disagree on a descriptor binding");
+                        }
+                        existing.stages |= binding.stages;
+                    }
+                    None => layout.bindings.push(*binding),
+                }
+            }
+
+            // One range covering every stage, simplest thing that's always valid
+            if let Some(push) = shader.push_constants {
+                layout.push_constants = Some(match layout.push_constants {
+                    Some(existing) => {
+                        let offset = existing.offset.min(push.offset);
+                        let end = (existing.offset + existing.size).max(push.offset + push.size);
+                        PushConstants {
+                            stages: existing.stages | push.stages,
+                            offset,
+                            size: end - offset,
+                        }
+                    }
+                    None => push,
+                });
+            }
+        }
+
+        layout.bindings.sort_by_key(|b| (b.set, b.binding));
+        Ok(layout)
+    }
+
+    // Number of descriptor set layouts the pipeline layout needs
+    pub fn set_count(&self) -> u32 {
+        self.bindings.iter().map(|b| b.set + 1).max().unwrap_or(0)
+    }
+
+    pub fn set_layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.bindings
+            .iter()
+            .filter(|b| b.set == set)
+            .map(|b| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(b.binding)
+                    .descriptor_type(b.descriptor_type)
+                    .descriptor_count(b.count)
+                    .stage_flags(b.stages)
+                    .build()
+            })
+            .collect()
+    }
+
+    // Push constants have to fit in the device's maxPushConstantsSize, 128 bytes on some hardware
+    pub fn check_push_constants(
+        &self,
+        limits: &vk::PhysicalDeviceLimits,
+    ) -> Result<(), &'static str> {
+        match self.push_constants {
+            Some(push) if push.offset + push.size > limits.max_push_constants_size => {
+                error!(
+                    "Push constants end at byte {} but the device only has {}",
+                    push.offset + push.size,
+                    limits.max_push_constants_size
+                );
+                Err("Push constants are larger than maxPushConstantsSize")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.push_constants
+            .iter()
+            .map(|p| vk::PushConstantRange {
+                stage_flags: p.stages,
+                offset: p.offset,
+                size: p.size,
+            })
+            .collect()
+    }
+}
+
+// Every input the vertex shader reads has to come from an attribute of the same format. Extra
+// attributes are fine, the shader just doesn't use them.
+pub fn check_vertex_inputs(
+    inputs: &[VertexInput],
+    attributes: &[vk::VertexInputAttributeDescription],
+) -> Result<(), &'static str> {
+    for input in inputs {
+        match attributes.iter().find(|a| a.location == input.location) {
+            Some(attribute) if attribute.format == input.format => {}
+            Some(attribute) => {
+                error!(
+                    "Vertex shader input at location {} is {:?} but the vertex layout gives it {:?}",
+                    input.location, input.format, attribute.format
+                );
+                return Err("Vertex layout doesn't match the vertex shader inputs");
+            }
+            None => {
+                error!(
+                    "Vertex shader input at location {} ({:?}) isn't in the vertex layout",
+                    input.location, input.format
+                );
+                return Err("Vertex layout doesn't match the vertex shader inputs");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hand assembled, equivalent to:
+    //   layout(location = 0) in vec3 position;
+    //   layout(location = 1) in mat2 transform;
+    //   layout(set = 0, binding = 0) uniform Ubo { mat4 m; } ubo;
+    //   layout(set = 0, binding = 1) uniform sampler2D textures[4];
+    //   layout(push_constant) uniform Push { mat4 model; uint material; } push;
+    fn op(code: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | code];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    fn vertex_module() -> Vec<u32> {
+        let main = u32::from_le_bytes(*b"main");
+        let mut spirv = vec![MAGIC, 0x0001_0000, 0, 100, 0];
+        for instruction in &[
+            op(OP_ENTRY_POINT, &[EXECUTION_VERTEX, 1, main, 0, 30, 31]),
+            op(OP_DECORATE, &[30, DECORATION_LOCATION, 0]),
+            op(OP_DECORATE, &[31, DECORATION_LOCATION, 1]),
+            op(OP_DECORATE, &[11, DECORATION_BLOCK]),
+            op(OP_MEMBER_DECORATE, &[11, 0, DECORATION_OFFSET, 0]),
+            op(OP_MEMBER_DECORATE, &[11, 0, DECORATION_MATRIX_STRIDE, 16]),
+            op(OP_DECORATE, &[32, DECORATION_DESCRIPTOR_SET, 0]),
+            op(OP_DECORATE, &[32, DECORATION_BINDING, 0]),
+            op(OP_DECORATE, &[33, DECORATION_DESCRIPTOR_SET, 0]),
+            op(OP_DECORATE, &[33, DECORATION_BINDING, 1]),
+            op(OP_DECORATE, &[16, DECORATION_BLOCK]),
+            op(OP_MEMBER_DECORATE, &[16, 0, DECORATION_OFFSET, 0]),
+            op(OP_MEMBER_DECORATE, &[16, 0, DECORATION_MATRIX_STRIDE, 16]),
+            op(OP_MEMBER_DECORATE, &[16, 1, DECORATION_OFFSET, 64]),
+            op(OP_TYPE_FLOAT, &[2, 32]),
+            op(OP_TYPE_VECTOR, &[3, 2, 3]),
+            op(OP_TYPE_VECTOR, &[4, 2, 4]),
+            op(OP_TYPE_MATRIX, &[5, 4, 4]),
+            op(OP_TYPE_VECTOR, &[6, 2, 2]),
+            op(OP_TYPE_MATRIX, &[7, 6, 2]),
+            op(OP_TYPE_INT, &[8, 32, 0]),
+            op(OP_CONSTANT, &[8, 9, 4]),
+            op(OP_TYPE_IMAGE, &[12, 2, 1, 0, 0, 0, 1, 0]),
+            op(OP_TYPE_SAMPLED_IMAGE, &[13, 12]),
+            op(OP_TYPE_ARRAY, &[14, 13, 9]),
+            op(OP_TYPE_STRUCT, &[11, 5]),
+            op(OP_TYPE_STRUCT, &[16, 5, 8]),
+            op(OP_TYPE_POINTER, &[20, STORAGE_INPUT, 3]),
+            op(OP_TYPE_POINTER, &[21, STORAGE_INPUT, 7]),
+            op(OP_TYPE_POINTER, &[22, STORAGE_UNIFORM, 11]),
+            op(OP_TYPE_POINTER, &[23, STORAGE_UNIFORM_CONSTANT, 14]),
+            op(OP_TYPE_POINTER, &[24, STORAGE_PUSH_CONSTANT, 16]),
+            op(OP_VARIABLE, &[20, 30, STORAGE_INPUT]),
+            op(OP_VARIABLE, &[21, 31, STORAGE_INPUT]),
+            op(OP_VARIABLE, &[22, 32, STORAGE_UNIFORM]),
+            op(OP_VARIABLE, &[23, 33, STORAGE_UNIFORM_CONSTANT]),
+            op(OP_VARIABLE, &[24, 34, STORAGE_PUSH_CONSTANT]),
+        ] {
+            spirv.extend_from_slice(instruction);
+        }
+        spirv
+    }
+
+    #[test]
+    fn reflect_vertex_shader() {
+        let reflection = reflect(&vertex_module()).unwrap();
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(reflection.entry_point, "main");
+
+        assert_eq!(
+            reflection.inputs,
+            vec![
+                VertexInput {
+                    location: 0,
+                    format: vk::Format::R32G32B32_SFLOAT
+                },
+                VertexInput {
+                    location: 1,
+                    format: vk::Format::R32G32_SFLOAT
+                },
+                VertexInput {
+                    location: 2,
+                    format: vk::Format::R32G32_SFLOAT
+                },
+            ]
+        );
+
+        assert_eq!(reflection.bindings.len(), 2);
+        assert_eq!(
+            reflection.bindings[0].descriptor_type,
+            vk::DescriptorType::UNIFORM_BUFFER
+        );
+        assert_eq!(
+            reflection.bindings[1].descriptor_type,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+        );
+        assert_eq!(reflection.bindings[1].count, 4);
+
+        assert_eq!(
+            reflection.push_constants,
+            Some(PushConstants {
+                stages: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: 68
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_modules_are_errors() {
+        let mut spirv = vec![MAGIC, 0x0001_0000, 0, 100, 0];
+        spirv.extend(op(OP_ENTRY_POINT, &[EXECUTION_VERTEX]));
+        assert!(reflect(&spirv).is_err());
+
+        // vec3 position as a vector of no components
+        let module = vertex_module();
+        let vector = op(OP_TYPE_VECTOR, &[3, 2, 3]);
+        let at = module
+            .windows(vector.len())
+            .position(|words| words == vector.as_slice())
+            .unwrap();
+        let mut spirv = module.clone();
+        spirv[at + 3] = 0;
+        assert!(reflect(&spirv).is_err());
+
+        // Every truncation either parses or fails, none of them panic
+        for len in 5..module.len() {
+            let _ = reflect(&module[..len]);
+        }
+    }
+
+    #[test]
+    fn vertex_input_mismatch() {
+        let reflection = reflect(&vertex_module()).unwrap();
+        let mut attributes: Vec<_> = reflection
+            .inputs
+            .iter()
+            .map(|i| vk::VertexInputAttributeDescription {
+                location: i.location,
+                format: i.format,
+                ..Default::default()
+            })
+            .collect();
+        assert!(check_vertex_inputs(&reflection.inputs, &attributes).is_ok());
+
+        attributes[0].format = vk::Format::R32G32B32A32_SFLOAT;
+        assert!(check_vertex_inputs(&reflection.inputs, &attributes).is_err());
+        attributes.remove(0);
+        assert!(check_vertex_inputs(&reflection.inputs, &attributes).is_err());
+    }
+}
@@ -3,80 +3,314 @@ use ash::extensions::{
     khr::{Surface, Swapchain},
 };
 use ash::version::{DeviceV1_0, EntryV1_0, InstanceV1_0};
-use ash::{util, vk};
+use ash::vk;
 use ash_window;
 
 use std::borrow::Cow;
 use std::ffi::CStr;
-use std::io::Cursor;
 use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
 
 use glam::{Mat4, Vec2, Vec3};
 
-#[derive(Clone, Copy, Debug)]
+use super::barrier::Access;
+use super::bindless::{self, BindlessTextures};
+use super::compute::ComputePipeline;
+use super::descriptor::{self, DescriptorAllocator, DescriptorResource};
+use super::draw::{Batch, Draw, DrawList, SortKey};
+use super::dynamic_rendering::{self, DynamicRendering, DynamicRenderingFeatures};
+use super::frame_sync::{self, FrameSync};
+use super::graph::{FrameGraph, ImageDesc, PassId, ResourceId};
+use super::pipeline::{self, GraphicsPipelineDesc, PipelineCache, RenderTarget, ShaderStage};
+use super::recorder::{self, ParallelRecorder};
+use super::reflect::{self, ResourceLayout, VertexInput};
+use super::shader::{self, Defines, ShaderCompiler};
+use super::sync2::{self, Synchronization2, Synchronization2Features};
+use super::tracker::ResourceTracker;
+use super::vertex::{self, MeshIndex, VertexLayout, VertexType};
+use crate::asset::{
+    self,
+    format::{IndexFormat, MeshData, TextureData, TextureFormat},
+    vfs::Vfs,
+    watch::{AssetKind, AssetWatcher},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, VertexLayout)]
 #[repr(C)]
 pub struct Vertex {
     pub position: Vec3,
     pub color: Vec3,
     pub tex_coord: Vec2,
+    pub normal: Vec3,
+    pub tangent: [f32; 4],
 }
 
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct UniformBufferObject {
-    pub model: Mat4,
     pub view: Mat4,
     pub proj: Mat4,
 }
 
+// A mesh made with Renderer::create_mesh, or the one loaded from MODEL_PATH. Goes stale when the
+// mesh is destroyed, even once its slot holds another one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle {
+    pub(crate) index: usize,
+    pub(crate) generation: u32,
+}
+
+// A material made with Renderer::create_material
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(pub(crate) usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Material {
+    // Into the bindless texture array, see Renderer::texture_index
+    pub texture_index: u32,
+}
+
+// Per batch data, mirrors DrawConstants in shader/include/draw.glsl
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct DrawPushConstants {
+    material_index: u32,
+    texture_index: u32,
+}
+
+// Per instance data of a batch, one transform per Renderer::draw in the order the batches use
+// them. Vertex binding 1, after Vertex's locations.
+#[derive(Clone, Copy, Debug, VertexLayout)]
+#[repr(C)]
+#[vertex(binding = 1, instance)]
+struct InstanceData {
+    #[vertex(location = 5)]
+    model: Mat4,
+}
+
 lazy_static! {
     static ref VERTICES: Vec<Vertex> = vec![
         Vertex {
             position: Vec3::new(-0.5, -0.5, 0.0),
             color: Vec3::new(1.0, 0.0, 0.0),
             tex_coord: Vec2::new(0.0, 0.0),
+            normal: Vec3::unit_z(),
+            tangent: [1.0, 0.0, 0.0, 1.0],
         },
         Vertex {
             position: Vec3::new(0.5, -0.5, 0.0),
             color: Vec3::new(0.0, 1.0, 0.0),
             tex_coord: Vec2::new(1.0, 0.0),
+            normal: Vec3::unit_z(),
+            tangent: [1.0, 0.0, 0.0, 1.0],
         },
         Vertex {
             position: Vec3::new(0.5, 0.5, 0.0),
             color: Vec3::new(0.0, 0.0, 1.0),
             tex_coord: Vec2::new(1.0, 1.0),
+            normal: Vec3::unit_z(),
+            tangent: [1.0, 0.0, 0.0, 1.0],
         },
         Vertex {
             position: Vec3::new(-0.5, 0.5, 0.0),
             color: Vec3::new(1.0, 1.0, 1.0),
             tex_coord: Vec2::new(0.0, 1.0),
+            normal: Vec3::unit_z(),
+            tangent: [1.0, 0.0, 0.0, 1.0],
         },
         Vertex {
             position: Vec3::new(-0.5, -0.5, -0.5),
             color: Vec3::new(1.0, 0.0, 0.0),
             tex_coord: Vec2::new(0.0, 0.0),
+            normal: Vec3::unit_z(),
+            tangent: [1.0, 0.0, 0.0, 1.0],
         },
         Vertex {
             position: Vec3::new(0.5, -0.5, -0.5),
             color: Vec3::new(0.0, 1.0, 0.0),
             tex_coord: Vec2::new(1.0, 0.0),
+            normal: Vec3::unit_z(),
+            tangent: [1.0, 0.0, 0.0, 1.0],
         },
         Vertex {
             position: Vec3::new(0.5, 0.5, -0.5),
             color: Vec3::new(0.0, 0.0, 1.0),
             tex_coord: Vec2::new(1.0, 1.0),
+            normal: Vec3::unit_z(),
+            tangent: [1.0, 0.0, 0.0, 1.0],
         },
         Vertex {
             position: Vec3::new(-0.5, 0.5, -0.5),
             color: Vec3::new(1.0, 1.0, 1.0),
             tex_coord: Vec2::new(0.0, 1.0),
+            normal: Vec3::unit_z(),
+            tangent: [1.0, 0.0, 0.0, 1.0],
         },
     ];
 }
 
 static INDICES: [u16; 12] = [0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
 
+// FIXME: Should come from the application
+const MODEL_PATH: &str = "models/viking_room.obj";
+const TEXTURE_PATH: &str = "textures/uv_test_1k.png";
+// HLSL sources work here too, e.g. triangle.vs.hlsl with VSMain
+const VERTEX_SHADER_PATH: &str = "shader/triangle/triangle.vert";
+const FRAGMENT_SHADER_PATH: &str = "shader/triangle/triangle.frag";
+const SHADER_INCLUDE_DIR: &str = "shader/include";
+const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+// Instances each frame's buffer has room for at first, it grows when a frame has more
+const INITIAL_INSTANCE_CAPACITY: usize = 1024;
+const CAMERA_NEAR: f32 = 0.1;
+const CAMERA_FAR: f32 = 10.0;
+
+// Fixed for now
+fn camera_view() -> Mat4 {
+    Mat4::look_at_rh(
+        Vec3::new(2.0, 2.0, 2.0),
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+    )
+}
+
+// What recording the main pass needs, split out of Renderer so worker threads can share it
+struct SceneState<'a> {
+    device: &'a ash::Device,
+    // Indexed by GpuMesh::pipeline
+    pipelines: Vec<vk::Pipeline>,
+    pipeline_layout: vk::PipelineLayout,
+    // The frame's set, then the bindless set if there is one
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    meshes: &'a [MeshSlot],
+    materials: &'a [Material],
+    instance_buffer: vk::Buffer,
+}
+
+impl<'a> SceneState<'a> {
+    fn draw(&self, buffer: vk::CommandBuffer, batches: &[Batch]) {
+        unsafe {
+            // Bind descriptor sets, every mesh pipeline shares the layout
+            self.device.cmd_bind_descriptor_sets(
+                buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.descriptor_sets,
+                &[],
+            );
+
+            self.device
+                .cmd_bind_vertex_buffers(buffer, 1, &[self.instance_buffer], &[0]);
+
+            // Pipelines and buffers are only rebound when they change
+            let mut bound_pipeline = None;
+            let mut bound = None;
+            for batch in batches {
+                // Destroyed after it was drawn
+                let mesh = match live_mesh(self.meshes, batch.mesh) {
+                    Some(mesh) => mesh,
+                    None => continue,
+                };
+                if bound_pipeline != Some(mesh.pipeline) {
+                    self.device.cmd_bind_pipeline(
+                        buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.pipelines[mesh.pipeline],
+                    );
+                    bound_pipeline = Some(mesh.pipeline);
+                }
+                if bound != Some(batch.mesh) {
+                    self.device
+                        .cmd_bind_vertex_buffers(buffer, 0, &[mesh.vertex_buffer], &[0]);
+                    self.device.cmd_bind_index_buffer(
+                        buffer,
+                        mesh.index_buffer,
+                        0,
+                        mesh.index_type,
+                    );
+                    bound = Some(batch.mesh);
+                }
+
+                let constants = DrawPushConstants {
+                    material_index: batch.material.0 as u32,
+                    texture_index: self.materials[batch.material.0].texture_index,
+                };
+                for range in &self.push_constant_ranges {
+                    pipeline::cmd_push_constants(
+                        self.device,
+                        buffer,
+                        self.pipeline_layout,
+                        range,
+                        &constants,
+                    );
+                }
+
+                self.device.cmd_draw_indexed(
+                    buffer,
+                    mesh.index_count,
+                    batch.instance_count,
+                    0,
+                    0,
+                    batch.first_instance,
+                );
+            }
+        }
+    }
+}
+
+// Everything one frame in flight records into or writes. It's only reused once FrameSync says
+// the frame that last had it is done.
+struct FrameResources {
+    // Reset as a whole every time the frame comes around, has the one command buffer it records into
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    image_available: vk::Semaphore,
+    render_finished: vk::Semaphore,
+    uniform_buffer: vk::Buffer,
+    uniform_buffer_mem: vk::DeviceMemory,
+    // InstanceData of the frame's batches, host visible
+    instance_buffer: vk::Buffer,
+    instance_buffer_mem: vk::DeviceMemory,
+    instance_capacity: usize,
+}
+
+struct GpuMesh {
+    vertex_buffer: vk::Buffer,
+    vertex_buffer_mem: vk::DeviceMemory,
+    index_buffer: vk::Buffer,
+    index_buffer_mem: vk::DeviceMemory,
+    index_count: u32,
+    index_type: vk::IndexType,
+    // Into Renderer::mesh_pipelines, the one for its vertex layout
+    pipeline: usize,
+}
+
+// The generation goes up every time the slot's mesh is destroyed, so old handles stop matching
+#[derive(Default)]
+struct MeshSlot {
+    generation: u32,
+    mesh: Option<GpuMesh>,
+}
+
+fn live_mesh(slots: &[MeshSlot], handle: MeshHandle) -> Option<&GpuMesh> {
+    slots
+        .get(handle.index)
+        .filter(|slot| slot.generation == handle.generation)
+        .and_then(|slot| slot.mesh.as_ref())
+}
+
+struct GpuTexture {
+    image: vk::Image,
+    image_mem: vk::DeviceMemory,
+    image_view: vk::ImageView,
+}
+
 pub struct Renderer {
+    vfs: Arc<Vfs>,
+    asset_watcher: Option<AssetWatcher>,
+
     entry: ash::Entry,
     instance: ash::Instance,
     device: ash::Device,
@@ -98,43 +332,50 @@ pub struct Renderer {
     surface_loader: Surface,
     swapchain_loader: Swapchain,
 
-    render_pass: vk::RenderPass,
-    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    descriptor_set_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
-    graphics_pipeline: vk::Pipeline,
-    framebuffers: Vec<vk::Framebuffer>,
-
-    command_pool: vk::CommandPool,
-    command_buffers: Vec<vk::CommandBuffer>,
-
-    vertices: Vec<Vertex>,
-    indices: Vec<u32>,
-    vertex_buffer: vk::Buffer,
-    vertex_buffer_mem: vk::DeviceMemory,
-    index_buffer: vk::Buffer,
-    index_buffer_mem: vk::DeviceMemory,
-
-    uniform_buffers: Vec<vk::Buffer>,
-    uniform_buffers_mem: Vec<vk::DeviceMemory>,
-
-    mip_levels: u32,
-    texture_image: vk::Image,
-    texture_image_mem: vk::DeviceMemory,
-    texture_image_view: vk::ImageView,
+    pipeline_cache: PipelineCache,
+    // One per vertex layout meshes were made with, Vertex's first
+    mesh_pipelines: Vec<GraphicsPipelineDesc>,
+    // Of the current vertex shader, what a mesh's vertex layout has to provide
+    vertex_inputs: Vec<VertexInput>,
+    shader_compiler: ShaderCompiler,
+    resource_layout: ResourceLayout,
+    // For the fragment shader, BINDLESS with bindless textures
+    shader_defines: Defines,
+    vertex_shader: vk::ShaderModule,
+    fragment_shader: vk::ShaderModule,
+    frame_graph: FrameGraph,
+    resource_tracker: ResourceTracker,
+    swapchain_target: ResourceId,
+    main_pass: PassId,
+
+    // One per frame in flight, indexed by FrameSync::frame_index
+    frames: Vec<FrameResources>,
+    // Every descriptor set and set layout, the frame's set 0 is allocated fresh every frame
+    descriptor_allocator: DescriptorAllocator,
+    // Frame that last rendered to each swapchain image, waited for before it's rendered to again
+    images_in_flight: Vec<u64>,
+    // Secondary command buffers for the main pass, recorded on worker threads
+    recorder: ParallelRecorder,
+
+    // Destroyed meshes leave an empty slot, reused by the next create_mesh
+    meshes: Vec<MeshSlot>,
+    model: MeshHandle,
+    materials: Vec<Material>,
+
+    // Submitted between begin_frame and end_frame, sorted and batched by end_frame
+    draws: DrawList,
+    batches: Vec<Batch>,
+    instance_transforms: Vec<Mat4>,
+
+    texture: GpuTexture,
     texture_sampler: vk::Sampler,
+    // Every loaded texture in one set, when the device supports descriptor indexing
+    bindless: Option<BindlessTextures>,
+    texture_index: u32,
 
-    depth_image: vk::Image,
-    depth_image_mem: vk::DeviceMemory,
-    depth_image_view: vk::ImageView,
-
-    descriptor_pool: vk::DescriptorPool,
-    descriptor_sets: Vec<vk::DescriptorSet>,
-
-    frames_in_flight: usize,
-    current_frame: usize,
-    in_flight_fences: Vec<vk::Fence>,
-    image_available_sems: Vec<vk::Semaphore>,
-    render_finished_sems: Vec<vk::Semaphore>,
+    frame_sync: FrameSync,
 
     // Debug
     pub debug_utils: Option<DebugUtils>,
@@ -143,7 +384,7 @@ pub struct Renderer {
 
 impl Renderer {
     // TODO: Don't really need window here, just required exts
-    pub fn new(window: &winit::window::Window) -> Result<Renderer, &'static str> {
+    pub fn new(window: &winit::window::Window, vfs: Arc<Vfs>) -> Result<Renderer, &'static str> {
         let entry = ash::Entry::new().unwrap();
 
         unsafe {
@@ -172,6 +413,15 @@ impl Renderer {
                 info!("Debug not available");
             };
 
+            // Newer device extensions like dynamic rendering depend on it before Vulkan 1.1
+            let properties2_name = vk::KhrGetPhysicalDeviceProperties2Fn::name();
+            let properties2_enabled = supported_extensions
+                .iter()
+                .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == properties2_name);
+            if properties2_enabled {
+                surface_extensions.push(properties2_name);
+            }
+
             let surface_extensions_raw = surface_extensions
                 .iter()
                 .map(|ext| ext.as_ptr())
@@ -278,18 +528,102 @@ impl Renderer {
                 .queue_priorities(&prios)
                 .build()];
 
-            let device_extensions = [Swapchain::name().as_ptr()];
+            let supported_extensions = instance
+                .enumerate_device_extension_properties(physical_device)
+                .unwrap_or_default();
+            let supports = |name: &CStr| {
+                supported_extensions
+                    .iter()
+                    .any(|extension| CStr::from_ptr(extension.extension_name.as_ptr()) == name)
+            };
+            // Both need VK_KHR_get_physical_device_properties2 on a 1.0 instance
+            let sync2_supported = properties2_enabled && supports(sync2::extension_name());
+            let timeline_supported = properties2_enabled && supports(frame_sync::extension_name());
+            let dynamic_rendering_supported = properties2_enabled
+                && dynamic_rendering::extension_names()
+                    .iter()
+                    .all(|name| supports(name));
+            let bindless_capacity = if properties2_enabled
+                && bindless::extension_names()
+                    .iter()
+                    .all(|name| supports(name))
+            {
+                bindless::query_capacity(&entry, &instance, physical_device)
+            } else {
+                None
+            };
+
+            let mut device_extensions = vec![Swapchain::name().as_ptr()];
             let device_features = vk::PhysicalDeviceFeatures::default();
+            let mut sync2_features = Synchronization2Features::default();
+            let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder()
+                .timeline_semaphore(true)
+                .build();
+            let mut dynamic_rendering_features = DynamicRenderingFeatures::default();
+            let mut descriptor_indexing_features = bindless::features();
 
-            let device_create_info = vk::DeviceCreateInfo::builder()
+            let mut device_create_info = vk::DeviceCreateInfo::builder()
                 .queue_create_infos(&queue_info)
-                .enabled_extension_names(&device_extensions)
                 .enabled_features(&device_features);
+            if sync2_supported {
+                device_extensions.push(sync2::extension_name().as_ptr());
+                device_create_info = device_create_info.push_next(&mut sync2_features);
+            }
+            if timeline_supported {
+                device_extensions.push(frame_sync::extension_name().as_ptr());
+                device_create_info = device_create_info.push_next(&mut timeline_features);
+            }
+            if dynamic_rendering_supported {
+                for name in dynamic_rendering::extension_names().iter() {
+                    device_extensions.push(name.as_ptr());
+                }
+                device_create_info = device_create_info.push_next(&mut dynamic_rendering_features);
+            }
+            if bindless_capacity.is_some() {
+                for name in bindless::extension_names().iter() {
+                    device_extensions.push(name.as_ptr());
+                }
+                device_create_info =
+                    device_create_info.push_next(&mut descriptor_indexing_features);
+            }
+            let device_create_info = device_create_info.enabled_extension_names(&device_extensions);
 
             let device = instance
                 .create_device(physical_device, &device_create_info, None)
                 .unwrap();
 
+            let sync2 = if sync2_supported {
+                Synchronization2::load(&instance, &device)
+            } else {
+                None
+            };
+            if sync2.is_some() {
+                info!("Using VK_KHR_synchronization2 for barriers");
+            }
+            let mut resource_tracker = ResourceTracker::new(sync2);
+
+            // Otherwise the frame graph and pipelines fall back to render passes
+            let dynamic_rendering = if dynamic_rendering_supported {
+                DynamicRendering::load(&instance, &device)
+            } else {
+                None
+            };
+            if dynamic_rendering.is_some() {
+                info!("Using VK_KHR_dynamic_rendering");
+            }
+
+            // Otherwise every frame's descriptor set has the one texture in it
+            let mut bindless = match bindless_capacity {
+                Some(capacity) => {
+                    info!(
+                        "Bindless textures, {} textures and {} samplers",
+                        capacity.0, capacity.1
+                    );
+                    Some(BindlessTextures::new(&device, capacity)?)
+                }
+                None => None,
+            };
+
             // Queue
             let present_queue = device.get_device_queue(queue_family_index as u32, 0);
 
@@ -377,723 +711,187 @@ impl Renderer {
                 })
                 .collect();
 
-            // Render pass
-            let renderpass_attachments = [
-                // Color attachment
-                vk::AttachmentDescription::builder()
-                    .format(surface_format.format)
-                    .samples(vk::SampleCountFlags::TYPE_1)
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::STORE)
-                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                    .initial_layout(vk::ImageLayout::UNDEFINED)
-                    .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-                    .build(),
-                // Depth attachment
-                vk::AttachmentDescription::builder()
-                    .format(vk::Format::D32_SFLOAT)
-                    .samples(vk::SampleCountFlags::TYPE_1)
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
-                    .store_op(vk::AttachmentStoreOp::DONT_CARE)
-                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                    .initial_layout(vk::ImageLayout::UNDEFINED)
-                    .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                    .build(),
-            ];
-
-            let color_attachment_ref = [vk::AttachmentReference::builder()
-                .attachment(0)
-                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                .build()];
-
-            let depth_attachment_ref = vk::AttachmentReference::builder()
-                .attachment(1)
-                .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
-
-            let subpass = [vk::SubpassDescription::builder()
-                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-                .color_attachments(&color_attachment_ref)
-                .depth_stencil_attachment(&depth_attachment_ref)
-                .build()];
-
-            let dependency = [vk::SubpassDependency::builder()
-                .src_subpass(vk::SUBPASS_EXTERNAL)
-                .dst_subpass(0)
-                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-                .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-                .build()];
-
-            let render_pass_info = vk::RenderPassCreateInfo::builder()
-                .attachments(&renderpass_attachments)
-                .subpasses(&subpass)
-                .dependencies(&dependency);
-
-            let render_pass = device.create_render_pass(&render_pass_info, None).unwrap();
-
-            // Shader modules
-            // FIXME
-            let (vs_spirv, fs_spirv) = {
-                let vs_source = include_str!("../bin/shader/triangle/triangle.vert");
-                let fs_source = include_str!("../bin/shader/triangle/triangle.frag");
-                let mut compiler = shaderc::Compiler::new().unwrap();
-                let vs_spirv = compiler
-                    .compile_into_spirv(
-                        vs_source,
-                        shaderc::ShaderKind::Vertex,
-                        "triangle.vert",
-                        "main",
-                        None,
-                    )
-                    .unwrap();
-                let fs_spirv = compiler
-                    .compile_into_spirv(
-                        fs_source,
-                        shaderc::ShaderKind::Fragment,
-                        "triangle.frag",
-                        "main",
-                        None,
-                    )
-                    .unwrap();
-                (vs_spirv, fs_spirv)
+            // Frame graph, for now just the one pass drawing the scene straight to the swapchain
+            let mut frame_graph = FrameGraph::new();
+            frame_graph.set_dynamic_rendering(dynamic_rendering);
+            let swapchain_target = frame_graph.import_image(
+                "swapchain",
+                surface_format.format,
+                Access::Acquire,
+                Access::Present,
+            );
+            let depth_target =
+                frame_graph.create_image("depth", ImageDesc::new(vk::Format::D32_SFLOAT));
+            let main_pass = frame_graph
+                .add_pass("main")
+                .color(swapchain_target, Some([0.0, 0.0, 0.0, 1.0]))
+                .depth(depth_target, Some(1.0))
+                .secondary()
+                .id();
+
+            // Shader modules, kept around so pipelines can be rebuilt when they change
+            let mut shader_compiler = ShaderCompiler::new(vfs.clone())?;
+            shader_compiler.add_include_dir(SHADER_INCLUDE_DIR);
+            if let Some(dir) = shader::default_cache_dir() {
+                shader_compiler.set_cache_dir(dir);
+            }
+            let shader_defines = if bindless.is_some() {
+                Defines::new().with("BINDLESS")
+            } else {
+                Defines::new()
             };
+            let vs_spirv = shader_compiler
+                .variant(VERTEX_SHADER_PATH, &Defines::new())?
+                .to_vec();
+            let fs_spirv = shader_compiler
+                .variant(FRAGMENT_SHADER_PATH, &shader_defines)?
+                .to_vec();
+            let vs_reflection = reflect::reflect(&vs_spirv)?;
+            let fs_reflection = reflect::reflect(&fs_spirv)?;
+            let vertex_shader = shader::create_module(&device, &vs_spirv)?;
+            let fragment_shader = shader::create_module(&device, &fs_spirv)?;
+
+            // Descriptor set, straight from what the shaders use. The bindless set comes after it.
+            let resource_layout = ResourceLayout::merge(&[&vs_reflection, &fs_reflection])?;
+            if bindless.is_some() {
+                if resource_layout.set_count() > bindless::SET + 1 {
+                    return Err("Only descriptor set 0 and the bindless set are supported");
+                }
+                bindless::check_layout(&resource_layout)?;
+            } else if resource_layout.set_count() > 1 {
+                return Err("Only descriptor set 0 is supported");
+            }
 
-            let (vs_spirv_bytes, fs_spirv_bytes) =
-                (vs_spirv.as_binary_u8(), fs_spirv.as_binary_u8());
-
-            let vs_code = util::read_spv(&mut Cursor::new(vs_spirv_bytes)).unwrap();
-            let vs_module_info = vk::ShaderModuleCreateInfo::builder().code(&vs_code);
-            let vs_module = device.create_shader_module(&vs_module_info, None).unwrap();
-
-            let fs_code = util::read_spv(&mut Cursor::new(fs_spirv_bytes)).unwrap();
-            let fs_module_info = vk::ShaderModuleCreateInfo::builder().code(&fs_code);
-            let fs_module = device.create_shader_module(&fs_module_info, None).unwrap();
-
-            // Shader entry
-            let vs_entry = vk::PipelineShaderStageCreateInfo::builder()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .module(vs_module)
-                .name(to_cstr!("main"))
-                .build();
-            let fs_entry = vk::PipelineShaderStageCreateInfo::builder()
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .module(fs_module)
-                .name(to_cstr!("main"))
-                .build();
-
-            // Vertex input/attrib
-            let vertex_input_bindings = [vk::VertexInputBindingDescription {
-                binding: 0,
-                stride: mem::size_of::<Vertex>() as u32,
-                input_rate: vk::VertexInputRate::VERTEX,
-                ..Default::default()
-            }];
-
-            let vertex_input_attributes = [
-                vk::VertexInputAttributeDescription {
-                    binding: 0,
-                    location: 0,
-                    format: vk::Format::R32G32B32_SFLOAT,
-                    offset: offset_of!(Vertex, position) as u32,
-                    ..Default::default()
-                },
-                vk::VertexInputAttributeDescription {
-                    binding: 0,
-                    location: 1,
-                    format: vk::Format::R32G32B32_SFLOAT,
-                    offset: offset_of!(Vertex, color) as u32,
-                    ..Default::default()
-                },
-                vk::VertexInputAttributeDescription {
-                    binding: 0,
-                    location: 2,
-                    format: vk::Format::R32G32_SFLOAT,
-                    offset: offset_of!(Vertex, tex_coord) as u32,
-                    ..Default::default()
-                },
-            ];
-
-            let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
-                .vertex_binding_descriptions(&vertex_input_bindings)
-                .vertex_attribute_descriptions(&vertex_input_attributes);
-
-            // Fixed function
-            let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
-                .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-                .primitive_restart_enable(false);
-
-            let viewport = [vk::Viewport::builder()
-                .x(0.0)
-                .y(0.0)
-                .width(surface_extent.width as f32) // FIXME: Swapchain image size vs surface
-                .height(surface_extent.height as f32)
-                .min_depth(0.0)
-                .max_depth(1.0)
-                .build()];
-
-            let scissor = [vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: surface_extent,
-            }];
-
-            let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
-                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
-
-            let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-                .viewport_count(1)
-                .viewports(&viewport)
-                .scissor_count(1)
-                .scissors(&scissor);
-
-            let rasterizer_info = vk::PipelineRasterizationStateCreateInfo::builder()
-                .depth_clamp_enable(false)
-                .rasterizer_discard_enable(false)
-                .polygon_mode(vk::PolygonMode::FILL)
-                .line_width(1.0)
-                .cull_mode(vk::CullModeFlags::BACK)
-                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-                .depth_bias_enable(false);
-
-            let multisample_info = vk::PipelineMultisampleStateCreateInfo::builder()
-                .sample_shading_enable(false)
-                .rasterization_samples(vk::SampleCountFlags::TYPE_1);
-
-            let color_blend_attachment = [vk::PipelineColorBlendAttachmentState::builder()
-                .color_write_mask(
-                    vk::ColorComponentFlags::R
-                        | vk::ColorComponentFlags::G
-                        | vk::ColorComponentFlags::B
-                        | vk::ColorComponentFlags::A,
-                )
-                .blend_enable(false)
-                .src_color_blend_factor(vk::BlendFactor::ONE)
-                .dst_color_blend_factor(vk::BlendFactor::ZERO)
-                .color_blend_op(vk::BlendOp::ADD)
-                .src_alpha_blend_factor(vk::BlendFactor::ONE)
-                .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-                .alpha_blend_op(vk::BlendOp::ADD)
-                .build()];
-
-            let color_blend_info = vk::PipelineColorBlendStateCreateInfo::builder()
-                .logic_op_enable(false)
-                .attachments(&color_blend_attachment);
-
-            // Descriptor set
-            let ubo_layout_binding = vk::DescriptorSetLayoutBinding::builder()
-                .binding(0)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .stage_flags(vk::ShaderStageFlags::VERTEX)
-                .descriptor_count(1)
-                .build();
-
-            let sampler_layout_binding = vk::DescriptorSetLayoutBinding::builder()
-                .binding(1)
-                .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-                .build();
-
-            let layout_bindings = [ubo_layout_binding, sampler_layout_binding];
-
-            let layout_info =
-                vk::DescriptorSetLayoutCreateInfo::builder().bindings(&layout_bindings);
-
-            let descriptor_set_layout = device
-                .create_descriptor_set_layout(&layout_info, None)
-                .unwrap();
+            // Draws push a whole DrawPushConstants, the shaders can't expect more
+            let device_properties = instance.get_physical_device_properties(physical_device);
+            resource_layout.check_push_constants(&device_properties.limits)?;
+            if let Some(push) = resource_layout.push_constants {
+                if (push.offset + push.size) as usize > mem::size_of::<DrawPushConstants>() {
+                    return Err("Shader push constants are larger than DrawPushConstants");
+                }
+            }
 
-            let desc_set_layouts = [descriptor_set_layout];
+            let mut descriptor_allocator = DescriptorAllocator::new(DEFAULT_FRAMES_IN_FLIGHT);
+            let descriptor_set_layout = descriptor_allocator
+                .set_layout(&device, &resource_layout.set_layout_bindings(0))?;
 
-            // Depth stencil state
-            let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo {
-                depth_test_enable: vk::TRUE,
-                depth_write_enable: vk::TRUE,
-                depth_compare_op: vk::CompareOp::LESS,
-                depth_bounds_test_enable: vk::FALSE,
-                stencil_test_enable: vk::FALSE,
-                ..Default::default()
-            };
+            let mut desc_set_layouts = vec![descriptor_set_layout];
+            if let Some(ref bindless) = bindless {
+                desc_set_layouts.push(bindless.layout());
+            }
 
             // Pipeline
-            let pipeline_layout_info =
-                vk::PipelineLayoutCreateInfo::builder().set_layouts(&desc_set_layouts);
+            let push_constant_ranges = resource_layout.push_constant_ranges();
+            let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&desc_set_layouts)
+                .push_constant_ranges(&push_constant_ranges);
             let pipeline_layout = device
                 .create_pipeline_layout(&pipeline_layout_info, None)
                 .unwrap();
 
-            let pipeline_info = [vk::GraphicsPipelineCreateInfo::builder()
-                .stages(&[vs_entry, fs_entry])
-                .vertex_input_state(&vertex_input_state)
-                .input_assembly_state(&input_assembly)
-                .dynamic_state(&dynamic_state)
-                .viewport_state(&viewport_state)
-                .rasterization_state(&rasterizer_info)
-                .multisample_state(&multisample_info)
-                .color_blend_state(&color_blend_info)
-                .depth_stencil_state(&depth_stencil_state)
-                .layout(pipeline_layout)
-                .render_pass(render_pass)
-                .subpass(0)
-                .build()];
-
-            let graphics_pipeline = device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_info, None)
-                .unwrap();
-
-            device.destroy_shader_module(vs_module, None);
-            device.destroy_shader_module(fs_module, None);
+            let mut vertex_layout = Vertex::vertex_layout();
+            vertex_layout.extend(InstanceData::vertex_layout());
+            reflect::check_vertex_inputs(
+                &vs_reflection.inputs,
+                &vertex_layout.attribute_descriptions(),
+            )?;
+            let mesh_pipeline = GraphicsPipelineDesc::new(
+                ShaderStage::new(
+                    vk::ShaderStageFlags::VERTEX,
+                    vertex_shader,
+                    &vs_reflection.entry_point,
+                ),
+                ShaderStage::new(
+                    vk::ShaderStageFlags::FRAGMENT,
+                    fragment_shader,
+                    &fs_reflection.entry_point,
+                ),
+                vertex_layout,
+                pipeline_layout,
+                RenderTarget::Formats(frame_graph.attachment_formats(main_pass)),
+            );
+            let mut pipeline_cache =
+                PipelineCache::new(&device, &device_properties, pipeline::default_cache_path())?;
+            pipeline_cache.set_dynamic_rendering(dynamic_rendering.is_some());
+            pipeline_cache.graphics_pipeline(&device, &mesh_pipeline)?;
 
             let mem_properties = instance.get_physical_device_memory_properties(physical_device);
 
             // Load model
-            let (vertices, indices) = load_model();
-
-            // Vertex buffer
-            let buffer_size = (mem::size_of::<Vertex>() * vertices.len()) as u64;
-
-            let (staging_buffer, staging_buffer_mem) = Renderer::create_buffer(
-                &device,
-                buffer_size,
-                mem_properties,
-                vk::BufferUsageFlags::TRANSFER_SRC,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            )
-            .unwrap();
-
-            let data = device
-                .map_memory(
-                    staging_buffer_mem,
-                    0,
-                    buffer_size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .unwrap();
-
-            let mut align =
-                ash::util::Align::new(data, mem::align_of::<Vertex>() as u64, buffer_size);
-            align.copy_from_slice(&vertices);
-            device.unmap_memory(staging_buffer_mem);
-
-            let (vertex_buffer, vertex_buffer_mem) = Renderer::create_buffer(
+            let model = Renderer::upload_mesh(
                 &device,
-                buffer_size,
+                present_queue,
+                queue_family_index as u32,
                 mem_properties,
-                vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            )
-            .unwrap();
+                &asset::load_baked_mesh(&vfs, MODEL_PATH)?,
+            )?;
 
-            Renderer::copy_buffer(
+            // Texture image
+            let texture = Renderer::upload_texture(
                 &device,
                 present_queue,
                 queue_family_index as u32,
-                staging_buffer,
-                vertex_buffer,
-                buffer_size,
-            );
-
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_buffer_mem, None);
+                mem_properties,
+                &mut resource_tracker,
+                &asset::load_baked_texture(&vfs, TEXTURE_PATH)?,
+            )?;
 
-            // Index buffer
-            let buffer_size = (mem::size_of::<Vertex>() * indices.len()) as u64;
+            // Texture sampler
+            let sampler_info = vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::REPEAT)
+                .address_mode_v(vk::SamplerAddressMode::REPEAT)
+                .address_mode_w(vk::SamplerAddressMode::REPEAT)
+                .anisotropy_enable(false)
+                .max_anisotropy(16.0_f32)
+                .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+                .unnormalized_coordinates(false)
+                .compare_enable(false)
+                .compare_op(vk::CompareOp::ALWAYS)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .mip_lod_bias(0.0_f32)
+                .min_lod(0.0_f32)
+                .max_lod(vk::LOD_CLAMP_NONE);
 
-            let (staging_buffer, staging_buffer_mem) = Renderer::create_buffer(
-                &device,
-                buffer_size,
-                mem_properties,
-                vk::BufferUsageFlags::TRANSFER_SRC,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            )
-            .unwrap();
+            let texture_sampler = device.create_sampler(&sampler_info, None).unwrap();
 
-            let data = device
-                .map_memory(
-                    staging_buffer_mem,
-                    0,
-                    buffer_size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .unwrap();
+            // Shaders sample bindless textures with sampler 0
+            let texture_index = match bindless {
+                Some(ref mut bindless) => {
+                    bindless.register_sampler(&device, texture_sampler)?;
+                    bindless.register_texture(&device, texture.image_view)?
+                }
+                None => 0,
+            };
 
-            let mut align = ash::util::Align::new(data, mem::align_of::<u16>() as u64, buffer_size);
-            align.copy_from_slice(&indices);
-            device.unmap_memory(staging_buffer_mem);
+            frame_graph.compile(&device, &mem_properties, surface_extent)?;
 
-            let (index_buffer, index_buffer_mem) = Renderer::create_buffer(
+            let frames_in_flight = DEFAULT_FRAMES_IN_FLIGHT;
+            let frames = Renderer::create_frames(
                 &device,
-                buffer_size,
+                queue_family_index as u32,
                 mem_properties,
-                vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            )
-            .unwrap();
-
-            Renderer::copy_buffer(
+                frames_in_flight,
+            )?;
+            let recorder = ParallelRecorder::new(
                 &device,
-                present_queue,
                 queue_family_index as u32,
-                staging_buffer,
-                index_buffer,
-                buffer_size,
-            );
-
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_buffer_mem, None);
-
-            // Uniform buffers
-            let buffer_size = mem::size_of::<UniformBufferObject>();
-            let mut uniform_buffers = Vec::with_capacity(present_images.len());
-            let mut uniform_buffers_mem = Vec::with_capacity(present_images.len());
-
-            for _i in 0..present_images.len() {
-                let (buf, buf_mem) = Renderer::create_buffer(
-                    &device,
-                    buffer_size as u64,
-                    mem_properties,
-                    vk::BufferUsageFlags::UNIFORM_BUFFER,
-                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-                )
-                .unwrap();
-                uniform_buffers.push(buf);
-                uniform_buffers_mem.push(buf_mem);
-            }
-
-            // Command pool
-            let cmd_pool_info =
-                vk::CommandPoolCreateInfo::builder().queue_family_index(queue_family_index as u32);
-
-            let command_pool = device.create_command_pool(&cmd_pool_info, None).unwrap();
-
-            // Texture image
-            // FIXME: Lazy
-            let image = image::load_from_memory(include_bytes!("../bin/textures/uv_test_1k.png"))
-                .unwrap()
-                .to_rgba();
-            let image_dims = image.dimensions();
-            let image_data = image.into_raw();
-            let image_size = (mem::size_of::<u8>() * image_data.len()) as u64;
-            let mip_levels = (image_dims.0.max(image_dims.1) as f32).log2().floor() as u32 + 1;
-
-            let (staging_buffer, staging_buffer_mem) = Renderer::create_buffer(
-                &device,
-                image_size,
-                mem_properties,
-                vk::BufferUsageFlags::TRANSFER_SRC,
-                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            )
-            .unwrap();
-
-            let data = device
-                .map_memory(
-                    staging_buffer_mem,
-                    0,
-                    image_size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .unwrap();
-
-            let mut align = ash::util::Align::new(
-                data,
-                mem::align_of::<u8>() as u64,
-                device.get_buffer_memory_requirements(staging_buffer).size,
-            );
-            align.copy_from_slice(&image_data);
-            device.unmap_memory(staging_buffer_mem);
-
-            let (texture_image, texture_image_mem) = Renderer::create_image(
-                &device,
-                image_dims.0,
-                image_dims.1,
-                mip_levels,
-                vk::Format::R8G8B8A8_SRGB,
-                vk::ImageTiling::OPTIMAL,
-                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-                mem_properties,
-            )
-            .unwrap();
-
-            Renderer::transition_image_layout(
-                &device,
-                present_queue,
-                queue_family_index as u32,
-                texture_image,
-                mip_levels,
-                vk::Format::R8G8B8A8_SRGB,
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            );
-
-            Renderer::copy_buffer_to_image(
-                &device,
-                present_queue,
-                queue_family_index as u32,
-                staging_buffer,
-                texture_image,
-                image_dims.0,
-                image_dims.1,
-            );
-
-            Renderer::transition_image_layout(
-                &device,
-                present_queue,
-                queue_family_index as u32,
-                texture_image,
-                mip_levels,
-                vk::Format::R8G8B8A8_SRGB,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            );
-
-            device.destroy_buffer(staging_buffer, None);
-            device.free_memory(staging_buffer_mem, None);
-
-            // Texture image view
-            let view_info = vk::ImageViewCreateInfo::builder()
-                .image(texture_image)
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .format(vk::Format::R8G8B8A8_SRGB)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                });
-
-            let texture_image_view = device.create_image_view(&view_info, None).unwrap();
-
-            // Texture sampler
-            let sampler_info = vk::SamplerCreateInfo::builder()
-                .mag_filter(vk::Filter::LINEAR)
-                .min_filter(vk::Filter::LINEAR)
-                .address_mode_u(vk::SamplerAddressMode::REPEAT)
-                .address_mode_v(vk::SamplerAddressMode::REPEAT)
-                .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                .anisotropy_enable(false)
-                .max_anisotropy(16.0_f32)
-                .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-                .unnormalized_coordinates(false)
-                .compare_enable(false)
-                .compare_op(vk::CompareOp::ALWAYS)
-                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-                .mip_lod_bias(0.0_f32)
-                .min_lod(0.0_f32)
-                .max_lod(0.0_f32);
-
-            let texture_sampler = device.create_sampler(&sampler_info, None).unwrap();
-
-            // Depth image
-            let (depth_image, depth_image_mem) = Renderer::create_image(
-                &device,
-                surface_extent.width,
-                surface_extent.height,
-                1,
-                vk::Format::D32_SFLOAT,
-                vk::ImageTiling::OPTIMAL,
-                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-                mem_properties,
-            )
-            .unwrap();
-
-            // Depth image view
-            let view_info = vk::ImageViewCreateInfo::builder()
-                .image(depth_image)
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .format(vk::Format::D32_SFLOAT)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                });
-
-            let depth_image_view = device.create_image_view(&view_info, None).unwrap();
-
-            // Framebuffer
-            let mut framebuffers = Vec::with_capacity(present_image_views.len());
-
-            for &view in present_image_views.iter() {
-                let attachments = [view, depth_image_view];
-                let framebuffer_info = vk::FramebufferCreateInfo::builder()
-                    .render_pass(render_pass)
-                    .attachments(&attachments)
-                    .width(surface_extent.width)
-                    .height(surface_extent.height)
-                    .layers(1);
-
-                framebuffers.push(device.create_framebuffer(&framebuffer_info, None).unwrap());
-            }
-
-            // Descriptor pool
-            let pool_sizes = [
-                vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(present_images.len() as u32)
-                    .build(),
-                vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .descriptor_count(present_images.len() as u32)
-                    .build(),
-            ];
-
-            let pool_info = vk::DescriptorPoolCreateInfo::builder()
-                .pool_sizes(&pool_sizes)
-                .max_sets(present_images.len() as u32);
-
-            let descriptor_pool = device.create_descriptor_pool(&pool_info, None).unwrap();
-
-            // Descriptor sets
-            let mut descriptor_set_layouts = Vec::with_capacity(present_images.len());
-            for _i in 0..descriptor_set_layouts.capacity() {
-                descriptor_set_layouts.push(descriptor_set_layout);
-            }
-
-            let descriptor_set_info = vk::DescriptorSetAllocateInfo::builder()
-                .descriptor_pool(descriptor_pool)
-                .set_layouts(&descriptor_set_layouts);
-
-            let descriptor_sets = device
-                .allocate_descriptor_sets(&descriptor_set_info)
-                .unwrap();
-
-            for (i, s) in descriptor_sets.iter().enumerate() {
-                let buffer_info = vk::DescriptorBufferInfo::builder()
-                    .buffer(uniform_buffers[i])
-                    .offset(0)
-                    .range(mem::size_of::<UniformBufferObject>() as u64)
-                    .build();
-
-                let image_info = vk::DescriptorImageInfo::builder()
-                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                    .image_view(texture_image_view)
-                    .sampler(texture_sampler)
-                    .build();
-
-                let descriptor_writes = [
-                    vk::WriteDescriptorSet::builder()
-                        .dst_set(*s)
-                        .dst_binding(0)
-                        .dst_array_element(0)
-                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                        .buffer_info(&[buffer_info])
-                        .build(),
-                    vk::WriteDescriptorSet::builder()
-                        .dst_set(*s)
-                        .dst_binding(1)
-                        .dst_array_element(0)
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .image_info(&[image_info])
-                        .build(),
-                ];
-
-                device.update_descriptor_sets(&descriptor_writes, &[]);
-            }
-
-            // Command buffers
-            // One buffer for each framebuffer
-            let buf_alloc_info = vk::CommandBufferAllocateInfo::builder()
-                .command_pool(command_pool)
-                .command_buffer_count(present_image_views.len() as u32)
-                .level(vk::CommandBufferLevel::PRIMARY);
-
-            let command_buffers = device.allocate_command_buffers(&buf_alloc_info).unwrap();
-
-            for (i, buffer) in command_buffers.iter().enumerate() {
-                let buf_begin_info = vk::CommandBufferBeginInfo::default();
-                device
-                    .begin_command_buffer(*buffer, &buf_begin_info)
-                    .unwrap();
-
-                let clear_values = [
-                    vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    },
-                    vk::ClearValue {
-                        depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 0.0,
-                            stencil: 1,
-                        },
-                    },
-                ];
-
-                let render_begin_info = vk::RenderPassBeginInfo::builder()
-                    .render_pass(render_pass)
-                    .framebuffer(framebuffers[i])
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: surface_extent,
-                    })
-                    .clear_values(&clear_values);
-
-                device.cmd_begin_render_pass(
-                    *buffer,
-                    &render_begin_info,
-                    vk::SubpassContents::INLINE,
-                );
-
-                device.cmd_bind_pipeline(
-                    *buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    graphics_pipeline[0],
-                ); // FIXME: graphics_pipeline
-
-                // bind vertex buffer
-                let vertex_buffers = vec![vertex_buffer];
-                let offsets = vec![0];
-                device.cmd_bind_vertex_buffers(*buffer, 0, &vertex_buffers, &offsets);
-
-                // Bind index buffer
-                device.cmd_bind_index_buffer(*buffer, index_buffer, 0, vk::IndexType::UINT32);
-
-                // Dynamic state
-                device.cmd_set_viewport(*buffer, 0, &viewport);
-                device.cmd_set_scissor(*buffer, 0, &scissor);
-
-                // Bind descriptor sets
-                device.cmd_bind_descriptor_sets(
-                    *buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    pipeline_layout,
-                    0,
-                    &[descriptor_sets[i]],
-                    &[],
-                );
-
-                //device.cmd_draw_indexed(*buffer, indices.len() as u32, 1, 0, 0, 0);
-                device.cmd_draw(*buffer, vertices.len() as u32, 1, 0, 0);
-
-                device.cmd_end_render_pass(*buffer);
-
-                device.end_command_buffer(*buffer).unwrap();
-            }
-
-            let frames_in_flight = 2;
-
-            let semaphore_info = vk::SemaphoreCreateInfo::default();
-            let mut image_available_sems = Vec::with_capacity(frames_in_flight);
-            let mut render_finished_sems = Vec::with_capacity(frames_in_flight);
-            for _ in 0..frames_in_flight {
-                image_available_sems.push(device.create_semaphore(&semaphore_info, None).unwrap());
-            }
-            for _i in 0..frames_in_flight {
-                render_finished_sems.push(device.create_semaphore(&semaphore_info, None).unwrap());
-            }
-
-            let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
-            let mut in_flight_fences = Vec::with_capacity(frames_in_flight);
-            for _ in 0..frames_in_flight {
-                in_flight_fences.push(device.create_fence(&fence_info, None).unwrap());
+                frames_in_flight,
+                recorder::default_threads(),
+            )?;
+            info!("Recording draws on up to {} threads", recorder.threads());
+
+            let images_in_flight = vec![0; present_images.len()];
+            let frame_sync =
+                FrameSync::new(&instance, &device, timeline_supported, frames_in_flight)?;
+            if frame_sync.is_timeline() {
+                info!("Pacing frames with a timeline semaphore");
             }
 
             Ok(Renderer {
+                vfs,
+                asset_watcher: None,
                 entry,
                 instance,
                 device,
@@ -1110,55 +908,141 @@ impl Renderer {
                 should_recreate_swapchain: false,
                 surface_loader,
                 swapchain_loader,
-                render_pass,
-                descriptor_set_layouts,
+                descriptor_set_layout,
                 pipeline_layout,
-                graphics_pipeline: graphics_pipeline[0], // FIXME
-                framebuffers,
-                command_pool,
-                command_buffers,
-                vertices,
-                indices,
-                vertex_buffer,
-                vertex_buffer_mem,
-                index_buffer,
-                index_buffer_mem,
-                uniform_buffers,
-                uniform_buffers_mem,
-                mip_levels,
-                texture_image,
-                texture_image_mem,
-                texture_image_view,
+                pipeline_cache,
+                mesh_pipelines: vec![mesh_pipeline],
+                vertex_inputs: vs_reflection.inputs,
+                shader_compiler,
+                resource_layout,
+                shader_defines,
+                vertex_shader,
+                fragment_shader,
+                frame_graph,
+                resource_tracker,
+                swapchain_target,
+                main_pass,
+                frames,
+                descriptor_allocator,
+                images_in_flight,
+                recorder,
+                meshes: vec![MeshSlot {
+                    generation: 0,
+                    mesh: Some(model),
+                }],
+                model: MeshHandle {
+                    index: 0,
+                    generation: 0,
+                },
+                materials: vec![Material { texture_index }],
+                draws: DrawList::new(),
+                batches: Vec::new(),
+                instance_transforms: Vec::new(),
+                texture,
                 texture_sampler,
-                depth_image,
-                depth_image_mem,
-                depth_image_view,
-                descriptor_pool,
-                descriptor_sets,
-                frames_in_flight,
-                current_frame: 0,
-                in_flight_fences,
-                image_available_sems,
-                render_finished_sems,
+                bindless,
+                texture_index,
+                frame_sync,
                 debug_utils,
                 debug_messenger,
             })
         }
     }
 
-    pub fn render(&mut self) {
+    // The mesh loaded from MODEL_PATH
+    pub fn model(&self) -> MeshHandle {
+        self.model
+    }
+
+    // Material::texture_index of the texture loaded from TEXTURE_PATH. Stays the same when it's
+    // reloaded.
+    pub fn texture_index(&self) -> u32 {
+        self.texture_index
+    }
+
+    pub fn create_material(&mut self, material: Material) -> MaterialHandle {
+        self.materials.push(material);
+        MaterialHandle(self.materials.len() - 1)
+    }
+
+    // Samples the texture loaded from TEXTURE_PATH
+    pub fn default_material(&self) -> MaterialHandle {
+        MaterialHandle(0)
+    }
+
+    // Starts collecting the draws for end_frame
+    pub fn begin_frame(&mut self) {
+        self.apply_asset_reloads();
+        self.draws.clear();
+    }
+
+    pub fn draw(&mut self, mesh: MeshHandle, material: MaterialHandle, transform: Mat4) {
+        let pipeline = match live_mesh(&self.meshes, mesh) {
+            Some(gpu_mesh) => gpu_mesh.pipeline,
+            None => {
+                warn!("Drawing destroyed mesh {:?}", mesh);
+                return;
+            }
+        };
+
+        // Distance along the view direction, the camera looks down -z
+        let view_position = camera_view() * transform.w_axis();
+        let key = SortKey {
+            // One pass for now
+            pass: 0,
+            pipeline: pipeline as u32,
+            material: material.0 as u32,
+            depth: -view_position.z() / CAMERA_FAR,
+            mesh: mesh.index as u32,
+        };
+        self.draws.push(
+            &key,
+            Draw {
+                mesh,
+                material,
+                transform,
+            },
+        );
+    }
+
+    // The texture the frames' descriptor sets point at, None when shaders read it from the
+    // bindless set instead
+    fn frame_texture(&self) -> Option<(vk::ImageView, vk::Sampler)> {
+        match self.bindless {
+            Some(_) => None,
+            None => Some((self.texture.image_view, self.texture_sampler)),
+        }
+    }
+
+    // Sorts and batches what was drawn since begin_frame, records it and presents
+    pub fn end_frame(&mut self) {
+        let frame_index = self.frame_sync.frame_index();
         unsafe {
-            let fences = vec![self.in_flight_fences[self.current_frame]];
-            self.device
-                .wait_for_fences(&fences, true, std::u64::MAX)
-                .unwrap();
-            self.device.reset_fences(&fences).unwrap();
+            self.frame_sync.begin_frame(&self.device);
+            if let Some(ref mut bindless) = self.bindless {
+                bindless.collect(self.frame_sync.completed_frame(&self.device));
+            }
+            self.descriptor_allocator
+                .begin_frame(&self.device, frame_index);
+            self.draws
+                .build_batches(&mut self.instance_transforms, &mut self.batches);
+            if let Err(e) = self.upload_instances(frame_index) {
+                error!("{}", e);
+                return;
+            }
+            let descriptor_set = match self.write_frame_descriptors(frame_index) {
+                Ok(set) => set,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
             let (image_index, mut is_suboptimal) = self
                 .swapchain_loader
                 .acquire_next_image(
                     self.swapchain,
                     std::u64::MAX,
-                    self.image_available_sems[self.current_frame],
+                    self.frames[frame_index].image_available,
                     vk::Fence::null(),
                 )
                 .unwrap_or((0, true)); //FIXME: Bad
@@ -1168,29 +1052,29 @@ impl Renderer {
                 return;
             }
 
+            // With more images than frames in flight, an older frame can still be drawing to it
+            let image_frame = &mut self.images_in_flight[image_index as usize];
+            self.frame_sync.wait_for_frame(&self.device, *image_frame);
+            *image_frame = self.frame_sync.frame();
+
             // UBO
             //let current_time = std::time::Instant::now();
             //let time = current_time.duration_since(*START_TIME).as_secs();
 
             let ubo = UniformBufferObject {
-                model: glam::Mat4::from_rotation_z(0.0_f32.to_radians()),
-                view: glam::Mat4::look_at_rh(
-                    Vec3::new(2.0, 2.0, 2.0),
-                    Vec3::new(0.0, 0.0, 0.0),
-                    Vec3::new(0.0, 0.0, 1.0),
-                ),
+                view: camera_view(),
                 proj: glam::Mat4::perspective_rh(
                     45.0_f32.to_radians(),
                     self.surface_extent.width as f32 / self.surface_extent.height as f32,
-                    0.1,
-                    10.0,
+                    CAMERA_NEAR,
+                    CAMERA_FAR,
                 ),
             };
 
             let data = self
                 .device
                 .map_memory(
-                    self.uniform_buffers_mem[image_index as usize],
+                    self.frames[frame_index].uniform_buffer_mem,
                     0,
                     mem::size_of::<UniformBufferObject>() as u64,
                     vk::MemoryMapFlags::empty(),
@@ -1204,24 +1088,40 @@ impl Renderer {
             );
             align.copy_from_slice(&[ubo]);
             self.device
-                .unmap_memory(self.uniform_buffers_mem[image_index as usize]);
-
-            // Semaphore
-            let wait_semaphores = [self.image_available_sems[self.current_frame]];
-            let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-            let signal_semaphores = [self.render_finished_sems[self.current_frame]];
-            let command_buffer = [self.command_buffers[image_index as usize]];
-            let submit_info = vk::SubmitInfo::builder()
-                .wait_semaphores(&wait_semaphores)
-                .wait_dst_stage_mask(&wait_stages)
-                .command_buffers(&command_buffer)
-                .signal_semaphores(&signal_semaphores);
+                .unmap_memory(self.frames[frame_index].uniform_buffer_mem);
 
+            // The frame that last used this pool is done, so everything recorded from it can go
+            let command_buffer = self.frames[frame_index].command_buffer;
             self.device
-                .queue_submit(
+                .reset_command_pool(
+                    self.frames[frame_index].command_pool,
+                    vk::CommandPoolResetFlags::empty(),
+                )
+                .unwrap();
+            self.recorder.begin_frame(&self.device, frame_index);
+            self.frame_graph
+                .set_image(
+                    &self.device,
+                    self.swapchain_target,
+                    self.present_images[image_index as usize],
+                    self.present_image_views[image_index as usize],
+                )
+                .unwrap();
+            self.record_frame(command_buffer, frame_index, descriptor_set);
+
+            // Swapchain semaphores stay binary, the frame counter is signalled alongside them
+            let signal_semaphores = [self.frames[frame_index].render_finished];
+            self.frame_sync
+                .submit(
+                    &self.device,
                     self.present_queue,
-                    &[submit_info.build()],
-                    self.in_flight_fences[self.current_frame],
+                    &[command_buffer],
+                    &[(
+                        self.frames[frame_index].image_available,
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    )],
+                    &signal_semaphores,
+                    &[],
                 )
                 .unwrap();
 
@@ -1237,351 +1137,1061 @@ impl Renderer {
                 .queue_present(self.present_queue, &present_info)
                 .unwrap_or(true); //FIXME: Bad
 
-            if is_suboptimal || self.should_recreate_swapchain {
-                self.should_recreate_swapchain = false;
-                self.recreate_swapchain();
+            if is_suboptimal || self.should_recreate_swapchain {
+                self.should_recreate_swapchain = false;
+                self.recreate_swapchain();
+            }
+        }
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    // How many frames the CPU may get ahead of the GPU. Waits for the GPU to go idle and
+    // recreates everything that's kept per frame.
+    pub fn set_frames_in_flight(&mut self, count: usize) -> Result<(), &'static str> {
+        let count = count.max(1);
+        if count == self.frames.len() {
+            return Ok(());
+        }
+
+        unsafe { self.device.device_wait_idle().unwrap() };
+        let mem_properties = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.physical_device)
+        };
+        let frames =
+            Renderer::create_frames(&self.device, self.queue_family_index, mem_properties, count)?;
+        let recorder = match ParallelRecorder::new(
+            &self.device,
+            self.queue_family_index,
+            count,
+            self.recorder.threads(),
+        ) {
+            Ok(recorder) => recorder,
+            Err(e) => {
+                Renderer::destroy_frames(&self.device, &frames);
+                return Err(e);
+            }
+        };
+        self.frame_sync.set_frames_in_flight(&self.device, count)?;
+        self.descriptor_allocator
+            .set_frames_in_flight(&self.device, count);
+
+        Renderer::destroy_frames(&self.device, &self.frames);
+        self.frames = frames;
+        mem::replace(&mut self.recorder, recorder).destroy(&self.device);
+        info!("Now {} frames in flight", count);
+        Ok(())
+    }
+
+    // FIXME: Sus
+    pub fn recreate_swapchain(&mut self) {
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+            self.destroy_swapchain();
+
+            let surface_caps = self
+                .surface_loader
+                .get_physical_device_surface_capabilities(self.physical_device, self.surface)
+                .unwrap();
+
+            self.surface_extent = match surface_caps.current_extent.width {
+                std::u32::MAX => {
+                    vk::Extent2D {
+                        // FIXME: Awful
+                        width: 800,
+                        height: 600,
+                    }
+                }
+                _ => surface_caps.current_extent,
+            };
+
+            let mem_properties = self
+                .instance
+                .get_physical_device_memory_properties(self.physical_device);
+
+            let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
+                .surface(self.surface)
+                .min_image_count(self.present_images.len() as u32) //FIXME: Sus
+                .image_format(self.surface_format.format)
+                .image_color_space(self.surface_format.color_space)
+                .image_extent(self.surface_extent)
+                .image_array_layers(1)
+                .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+                .image_sharing_mode(vk::SharingMode::EXCLUSIVE) // FIXME: Only if present_queue == graphics queue
+                .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY) // NOTE: Identity transform?
+                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+                .present_mode(self.present_mode)
+                .clipped(true);
+
+            self.swapchain = self
+                .swapchain_loader
+                .create_swapchain(&swapchain_create_info, None)
+                .unwrap();
+
+            self.present_images = self
+                .swapchain_loader
+                .get_swapchain_images(self.swapchain)
+                .unwrap();
+
+            self.present_image_views = self
+                .present_images
+                .iter()
+                .map(|i| {
+                    let image_view = vk::ImageViewCreateInfo::builder()
+                        .image(*i)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(self.surface_format.format)
+                        .components(vk::ComponentMapping {
+                            r: vk::ComponentSwizzle::R,
+                            g: vk::ComponentSwizzle::G,
+                            b: vk::ComponentSwizzle::B,
+                            a: vk::ComponentSwizzle::A,
+                        })
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        });
+                    self.device.create_image_view(&image_view, None).unwrap()
+                })
+                .collect();
+
+            self.frame_graph
+                .compile(&self.device, &mem_properties, self.surface_extent)
+                .unwrap();
+
+            // Nothing is in flight after the wait above
+            self.images_in_flight = vec![0; self.present_images.len()];
+        }
+    }
+
+    // Reload the model, texture and shaders whenever they change on disk
+    pub fn watch_assets(&mut self, interval: Duration) {
+        let watcher = AssetWatcher::new(self.vfs.clone(), interval);
+        watcher.watch(MODEL_PATH, AssetKind::Mesh);
+        watcher.watch(TEXTURE_PATH, AssetKind::Texture);
+        for dependency in self.shader_compiler.dependencies() {
+            watcher.watch(&dependency, AssetKind::Shader);
+        }
+        self.asset_watcher = Some(watcher);
+    }
+
+    // Swaps in anything the watcher finished importing. A failed reload keeps the old resource.
+    fn apply_asset_reloads(&mut self) {
+        let reloads = match self.asset_watcher {
+            Some(ref watcher) => watcher.poll(),
+            None => return,
+        };
+        if reloads.is_empty() {
+            return;
+        }
+
+        let mem_properties = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.physical_device)
+        };
+        for reload in reloads {
+            let bytes = match reload.result {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("Failed to reload {}: {}", reload.path, e);
+                    continue;
+                }
+            };
+
+            // Old resources can only go once no frame in flight still uses them
+            self.frame_sync.wait_all(&self.device);
+
+            match reload.kind {
+                // Unless it was destroyed, its slot may hold another mesh by now
+                AssetKind::Mesh if live_mesh(&self.meshes, self.model).is_none() => {}
+                AssetKind::Mesh => {
+                    match Renderer::upload_mesh(
+                        &self.device,
+                        self.present_queue,
+                        self.queue_family_index,
+                        mem_properties,
+                        &bytes,
+                    ) {
+                        Ok(model) => {
+                            let old = self.meshes[self.model.index].mesh.replace(model);
+                            if let Some(old) = old {
+                                Renderer::destroy_gpu_mesh(&self.device, &old);
+                            }
+                            info!("Reloaded {}", reload.path);
+                        }
+                        Err(e) => error!("Failed to reload {}: {}", reload.path, e),
+                    }
+                }
+                AssetKind::Texture => {
+                    match Renderer::upload_texture(
+                        &self.device,
+                        self.present_queue,
+                        self.queue_family_index,
+                        mem_properties,
+                        &mut self.resource_tracker,
+                        &bytes,
+                    ) {
+                        Ok(texture) => {
+                            let old = mem::replace(&mut self.texture, texture);
+                            self.write_texture_descriptors();
+                            Renderer::destroy_texture(
+                                &self.device,
+                                &mut self.resource_tracker,
+                                &old,
+                            );
+                            info!("Reloaded {}", reload.path);
+                        }
+                        Err(e) => error!("Failed to reload {}: {}", reload.path, e),
+                    }
+                }
+                AssetKind::Shader => match self.reload_shaders(&reload.path) {
+                    Ok(true) => info!("Reloaded {}", reload.path),
+                    Ok(false) => {}
+                    Err(e) => error!("Failed to reload {}: {}", reload.path, e),
+                },
+            }
+        }
+    }
+
+    pub fn device(&self) -> &ash::Device {
+        &self.device
+    }
+
+    // Layout comes from reflecting the shader, like the graphics pipelines'
+    pub fn create_compute_pipeline(
+        &mut self,
+        path: &str,
+        defines: &Defines,
+    ) -> Result<ComputePipeline, &'static str> {
+        let spirv = self.shader_compiler.variant(path, defines)?.to_vec();
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+        };
+        ComputePipeline::new(
+            &self.device,
+            &self.pipeline_cache,
+            &mut self.descriptor_allocator,
+            &spirv,
+            &properties.limits,
+        )
+    }
+
+    // Device local vertex and index buffers filled through staging. `V` can be any vertex type
+    // that gives every input of the vertex shader, meshes with the same layout share a pipeline.
+    pub fn create_mesh<V: VertexType, I: MeshIndex>(
+        &mut self,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<MeshHandle, &'static str> {
+        let mesh = self.build_mesh(vertices, indices)?;
+        let index = match self.meshes.iter().position(|slot| slot.mesh.is_none()) {
+            Some(index) => index,
+            None => {
+                self.meshes.push(MeshSlot::default());
+                self.meshes.len() - 1
+            }
+        };
+        let slot = &mut self.meshes[index];
+        slot.mesh = Some(mesh);
+        Ok(MeshHandle {
+            index,
+            generation: slot.generation,
+        })
+    }
+
+    // Replaces the mesh's buffers, the vertex type can change too. The handle stays valid.
+    pub fn update_mesh<V: VertexType, I: MeshIndex>(
+        &mut self,
+        handle: MeshHandle,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<(), &'static str> {
+        if live_mesh(&self.meshes, handle).is_none() {
+            return Err("Mesh was destroyed");
+        }
+        let mesh = self.build_mesh(vertices, indices)?;
+
+        // The old buffers can only go once no frame in flight still uses them
+        self.frame_sync.wait_all(&self.device);
+        if let Some(old) = self.meshes[handle.index].mesh.replace(mesh) {
+            Renderer::destroy_gpu_mesh(&self.device, &old);
+        }
+        Ok(())
+    }
+
+    // Draws, updates and destroys through the handle are ignored or fail afterwards, even once a
+    // later create_mesh reuses the slot
+    pub fn destroy_mesh(&mut self, handle: MeshHandle) {
+        if live_mesh(&self.meshes, handle).is_none() {
+            return;
+        }
+        let slot = &mut self.meshes[handle.index];
+        slot.generation = slot.generation.wrapping_add(1);
+        if let Some(mesh) = slot.mesh.take() {
+            self.frame_sync.wait_all(&self.device);
+            Renderer::destroy_gpu_mesh(&self.device, &mesh);
+        }
+    }
+
+    fn build_mesh<V: VertexType, I: MeshIndex>(
+        &mut self,
+        vertices: &[V],
+        indices: &[I],
+    ) -> Result<GpuMesh, &'static str> {
+        vertex::check_indices(indices, vertices.len())?;
+        let pipeline = self.vertex_pipeline(V::vertex_layout())?;
+
+        let mem_properties = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.physical_device)
+        };
+        let (index_type, index_data) = vertex::index_data(indices, vertices.len());
+        let (vertex_buffer, vertex_buffer_mem) = Renderer::create_device_local_buffer(
+            &self.device,
+            self.present_queue,
+            self.queue_family_index,
+            mem_properties,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vertex::as_bytes(vertices),
+        );
+        let (index_buffer, index_buffer_mem) = Renderer::create_device_local_buffer(
+            &self.device,
+            self.present_queue,
+            self.queue_family_index,
+            mem_properties,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            &index_data,
+        );
+
+        Ok(GpuMesh {
+            vertex_buffer,
+            vertex_buffer_mem,
+            index_buffer,
+            index_buffer_mem,
+            index_count: indices.len() as u32,
+            index_type,
+            pipeline,
+        })
+    }
+
+    // Index of the mesh pipeline for vertices laid out as `layout`, built the first time it's seen
+    fn vertex_pipeline(&mut self, layout: pipeline::VertexLayout) -> Result<usize, &'static str> {
+        match layout.bindings.as_slice() {
+            [binding]
+                if binding.binding == 0 && binding.input_rate == vk::VertexInputRate::VERTEX => {}
+            _ => return Err("Mesh vertices have to be per vertex data in binding 0"),
+        }
+        let instance_layout = InstanceData::vertex_layout();
+        if layout.attributes.iter().any(|a| {
+            instance_layout
+                .attributes
+                .iter()
+                .any(|i| i.location == a.location)
+        }) {
+            return Err("Mesh vertices use the locations of the per instance data");
+        }
+        let mut vertex_layout = layout;
+        vertex_layout.extend(instance_layout);
+        if let Some(index) = self
+            .mesh_pipelines
+            .iter()
+            .position(|desc| desc.vertex_layout == vertex_layout)
+        {
+            return Ok(index);
+        }
+
+        reflect::check_vertex_inputs(&self.vertex_inputs, &vertex_layout.attribute_descriptions())?;
+        let mesh_pipeline = GraphicsPipelineDesc {
+            vertex_layout,
+            ..self.mesh_pipelines[0].clone()
+        };
+        self.pipeline_cache
+            .graphics_pipeline(&self.device, &mesh_pipeline)?;
+        self.mesh_pipelines.push(mesh_pipeline);
+        Ok(self.mesh_pipelines.len() - 1)
+    }
+
+    // Number of the frame the next render call records
+    pub fn frame(&self) -> u64 {
+        self.frame_sync.frame()
+    }
+
+    // Whether the GPU is done with frame `frame`, e.g. to know when a resource it used can go
+    pub fn is_frame_complete(&self, frame: u64) -> bool {
+        self.frame_sync.is_frame_complete(&self.device, frame)
+    }
+
+    // Live as long as the renderer, from the same pools as its own long lived sets
+    pub fn allocate_compute_descriptor_sets(
+        &mut self,
+        pipeline: &ComputePipeline,
+        set: u32,
+        count: u32,
+    ) -> Result<Vec<vk::DescriptorSet>, &'static str> {
+        pipeline.allocate_descriptor_sets(&self.device, &mut self.descriptor_allocator, set, count)
+    }
+
+    pub fn destroy_compute_pipeline(&self, pipeline: ComputePipeline) {
+        self.frame_sync.wait_all(&self.device);
+        pipeline.destroy(&self.device);
+    }
+
+    // Device local and filled with `data`. `usage` is added to STORAGE_BUFFER, e.g. VERTEX_BUFFER
+    // for particles drawn straight from what a compute shader wrote.
+    pub fn create_storage_buffer(
+        &self,
+        usage: vk::BufferUsageFlags,
+        data: &[u8],
+    ) -> (vk::Buffer, vk::DeviceMemory) {
+        let mem_properties = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.physical_device)
+        };
+        Renderer::create_device_local_buffer(
+            &self.device,
+            self.present_queue,
+            self.queue_family_index,
+            mem_properties,
+            vk::BufferUsageFlags::STORAGE_BUFFER | usage,
+            data,
+        )
+    }
+
+    pub fn destroy_buffer(&self, buffer: vk::Buffer, memory: vk::DeviceMemory) {
+        unsafe {
+            self.frame_sync.wait_all(&self.device);
+            self.device.destroy_buffer(buffer, None);
+            self.device.free_memory(memory, None);
+        }
+    }
+
+    // Records `f` into a one-off command buffer, submits it and waits. For compute work outside
+    // of frames, like processing an image once after loading it.
+    pub fn run_commands<F: FnOnce(&ash::Device, vk::CommandBuffer)>(&self, f: F) {
+        unsafe {
+            let cmd_pool_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(self.queue_family_index)
+                .flags(
+                    vk::CommandPoolCreateFlags::TRANSIENT
+                        | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                );
+            let cmd_pool = self
+                .device
+                .create_command_pool(&cmd_pool_info, None)
+                .unwrap();
+
+            let cmd_buf_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(cmd_pool)
+                .command_buffer_count(1)
+                .level(vk::CommandBufferLevel::PRIMARY);
+            let cmd_buf = self.device.allocate_command_buffers(&cmd_buf_info).unwrap();
+
+            Renderer::do_single_command(&self.device, cmd_buf[0], self.present_queue, f);
+
+            self.device.free_command_buffers(cmd_pool, &cmd_buf);
+            self.device.destroy_command_pool(cmd_pool, None);
+        }
+    }
+
+    // Recompiles the variants that depend on `changed` and rebuilds every pipeline using them.
+    // On failure the previous pipeline stays in use. Returns whether anything was rebuilt.
+    fn reload_shaders(&mut self, changed: &str) -> Result<bool, &'static str> {
+        let stale = self.shader_compiler.invalidate(changed);
+        let vertex_stale = stale.iter().any(|(s, _)| s.path == VERTEX_SHADER_PATH);
+        let fragment_stale = stale.iter().any(|(s, _)| s.path == FRAGMENT_SHADER_PATH);
+        if !vertex_stale && !fragment_stale {
+            return Ok(false);
+        }
+
+        let mut new_modules = Vec::new();
+        let result = self.rebuild_pipelines(vertex_stale, fragment_stale, &mut new_modules);
+        if result.is_err() {
+            self.pipeline_cache.evict(&self.device, |desc| {
+                new_modules.iter().any(|&module| desc.uses_module(module))
+            });
+            for module in new_modules {
+                unsafe { self.device.destroy_shader_module(module, None) };
+            }
+        }
+        result?;
+
+        // Includes may have changed too
+        if let Some(ref watcher) = self.asset_watcher {
+            for dependency in self.shader_compiler.dependencies() {
+                watcher.watch(&dependency, AssetKind::Shader);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn rebuild_pipelines(
+        &mut self,
+        vertex_stale: bool,
+        fragment_stale: bool,
+        new_modules: &mut Vec<vk::ShaderModule>,
+    ) -> Result<(), &'static str> {
+        let defines = self.shader_defines.clone();
+
+        // Unchanged stages come straight out of the variant cache
+        let vs_spirv = self
+            .shader_compiler
+            .variant(VERTEX_SHADER_PATH, &Defines::new())?
+            .to_vec();
+        let fs_spirv = self
+            .shader_compiler
+            .variant(FRAGMENT_SHADER_PATH, &defines)?
+            .to_vec();
+        let vs_reflection = reflect::reflect(&vs_spirv)?;
+        let fs_reflection = reflect::reflect(&fs_spirv)?;
+
+        // The pipeline layout and descriptor sets are built once, so that can't change
+        if ResourceLayout::merge(&[&vs_reflection, &fs_reflection])? != self.resource_layout {
+            return Err("Shader descriptors or push constants changed, restart to pick them up");
+        }
+
+        let vertex_shader = if vertex_stale {
+            new_modules.push(shader::create_module(&self.device, &vs_spirv)?);
+            new_modules[new_modules.len() - 1]
+        } else {
+            self.vertex_shader
+        };
+        let fragment_shader = if fragment_stale {
+            new_modules.push(shader::create_module(&self.device, &fs_spirv)?);
+            new_modules[new_modules.len() - 1]
+        } else {
+            self.fragment_shader
+        };
+
+        // Every mesh made so far has to keep working with the new vertex shader
+        let mut mesh_pipelines = Vec::with_capacity(self.mesh_pipelines.len());
+        for old in &self.mesh_pipelines {
+            reflect::check_vertex_inputs(
+                &vs_reflection.inputs,
+                &old.vertex_layout.attribute_descriptions(),
+            )?;
+            let mesh_pipeline = GraphicsPipelineDesc::new(
+                ShaderStage::new(
+                    vk::ShaderStageFlags::VERTEX,
+                    vertex_shader,
+                    &vs_reflection.entry_point,
+                ),
+                ShaderStage::new(
+                    vk::ShaderStageFlags::FRAGMENT,
+                    fragment_shader,
+                    &fs_reflection.entry_point,
+                ),
+                old.vertex_layout.clone(),
+                self.pipeline_layout,
+                old.target.clone(),
+            );
+            self.pipeline_cache
+                .graphics_pipeline(&self.device, &mesh_pipeline)?;
+            mesh_pipelines.push(mesh_pipeline);
+        }
+        self.mesh_pipelines = mesh_pipelines;
+        self.vertex_inputs = vs_reflection.inputs;
+
+        // Whatever was built from the replaced modules goes with them
+        let old_vertex_shader = mem::replace(&mut self.vertex_shader, vertex_shader);
+        let old_fragment_shader = mem::replace(&mut self.fragment_shader, fragment_shader);
+        let mut old_modules = Vec::new();
+        if vertex_stale {
+            old_modules.push(old_vertex_shader);
+        }
+        if fragment_stale {
+            old_modules.push(old_fragment_shader);
+        }
+        self.pipeline_cache.evict(&self.device, |desc| {
+            old_modules.iter().any(|&module| desc.uses_module(module))
+        });
+        for module in old_modules {
+            unsafe { self.device.destroy_shader_module(module, None) };
+        }
+
+        Ok(())
+    }
+
+    // Frame sets pick the texture up when they're written, only the bindless set has to change
+    fn write_texture_descriptors(&self) {
+        if let Some(ref bindless) = self.bindless {
+            bindless.update_texture(&self.device, self.texture_index, self.texture.image_view);
+        }
+    }
+
+    // The frame's set 0 out of its transient pools, which begin_frame just reset
+    fn write_frame_descriptors(
+        &mut self,
+        frame_index: usize,
+    ) -> Result<vk::DescriptorSet, &'static str> {
+        let set = self.descriptor_allocator.allocate_frame(
+            &self.device,
+            frame_index,
+            self.descriptor_set_layout,
+        )?;
+        let mut writes = vec![(
+            0,
+            DescriptorResource::UniformBuffer {
+                buffer: self.frames[frame_index].uniform_buffer,
+                offset: 0,
+                range: mem::size_of::<UniformBufferObject>() as u64,
+            },
+        )];
+        if let Some((view, sampler)) = self.frame_texture() {
+            writes.push((
+                1,
+                DescriptorResource::CombinedImageSampler { view, sampler },
+            ));
+        }
+        descriptor::write_descriptor_set(&self.device, set, &writes);
+        Ok(set)
+    }
+
+    // Everything drawn this frame, with the resources of frame in flight `frame_index`
+    fn record_frame(
+        &self,
+        buffer: vk::CommandBuffer,
+        frame_index: usize,
+        descriptor_set: vk::DescriptorSet,
+    ) {
+        unsafe {
+            let buf_begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.device
+                .begin_command_buffer(buffer, &buf_begin_info)
+                .unwrap();
+        }
+
+        let scene = SceneState {
+            device: &self.device,
+            pipelines: self
+                .mesh_pipelines
+                .iter()
+                .map(|desc| self.pipeline_cache.get(desc).unwrap())
+                .collect(),
+            pipeline_layout: self.pipeline_layout,
+            descriptor_sets: std::iter::once(descriptor_set)
+                .chain(self.bindless.as_ref().map(|b| b.set()))
+                .collect(),
+            push_constant_ranges: self.resource_layout.push_constant_ranges(),
+            meshes: &self.meshes,
+            materials: &self.materials,
+            instance_buffer: self.frames[frame_index].instance_buffer,
+        };
+        self.frame_graph
+            .execute(&self.device, buffer, |pass, context| {
+                if pass == self.main_pass {
+                    self.recorder
+                        .record(frame_index, context, &self.batches, |buffer, batches| {
+                            scene.draw(buffer, batches)
+                        });
+                }
+            });
+
+        unsafe { self.device.end_command_buffer(buffer).unwrap() };
+    }
+
+    // `count` frames in flight, each with its own command pool, swapchain semaphores and uniform
+    // buffer
+    fn create_frames(
+        device: &ash::Device,
+        queue_family_index: u32,
+        mem_properties: vk::PhysicalDeviceMemoryProperties,
+        count: usize,
+    ) -> Result<Vec<FrameResources>, &'static str> {
+        unsafe {
+            let cmd_pool_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(queue_family_index)
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+            let semaphore_info = vk::SemaphoreCreateInfo::default();
+
+            let mut frames = Vec::with_capacity(count);
+            for _ in 0..count {
+                let command_pool = device.create_command_pool(&cmd_pool_info, None).unwrap();
+                let buf_alloc_info = vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(command_pool)
+                    .command_buffer_count(1)
+                    .level(vk::CommandBufferLevel::PRIMARY);
+                let command_buffer = device.allocate_command_buffers(&buf_alloc_info).unwrap()[0];
+
+                let (uniform_buffer, uniform_buffer_mem) = Renderer::create_buffer(
+                    device,
+                    mem::size_of::<UniformBufferObject>() as u64,
+                    mem_properties,
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+                .unwrap();
+                let (instance_buffer, instance_buffer_mem) = Renderer::create_instance_buffer(
+                    device,
+                    mem_properties,
+                    INITIAL_INSTANCE_CAPACITY,
+                );
+
+                frames.push(FrameResources {
+                    command_pool,
+                    command_buffer,
+                    image_available: device.create_semaphore(&semaphore_info, None).unwrap(),
+                    render_finished: device.create_semaphore(&semaphore_info, None).unwrap(),
+                    uniform_buffer,
+                    uniform_buffer_mem,
+                    instance_buffer,
+                    instance_buffer_mem,
+                    instance_capacity: INITIAL_INSTANCE_CAPACITY,
+                });
+            }
+
+            Ok(frames)
+        }
+    }
+
+    fn create_instance_buffer(
+        device: &ash::Device,
+        mem_properties: vk::PhysicalDeviceMemoryProperties,
+        capacity: usize,
+    ) -> (vk::Buffer, vk::DeviceMemory) {
+        Renderer::create_buffer(
+            device,
+            (capacity * mem::size_of::<InstanceData>()) as u64,
+            mem_properties,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+        .unwrap()
+    }
+
+    // The batches' transforms into the frame's instance buffer, which only grows. The frame that
+    // last used it is done by now.
+    fn upload_instances(&mut self, frame_index: usize) -> Result<(), &'static str> {
+        let count = self.instance_transforms.len();
+        if count == 0 {
+            return Ok(());
+        }
+
+        let frame = &mut self.frames[frame_index];
+        if count > frame.instance_capacity {
+            let mem_properties = unsafe {
+                self.instance
+                    .get_physical_device_memory_properties(self.physical_device)
+            };
+            let capacity = count.next_power_of_two();
+            let (buffer, memory) =
+                Renderer::create_instance_buffer(&self.device, mem_properties, capacity);
+            unsafe {
+                self.device.destroy_buffer(frame.instance_buffer, None);
+                self.device.free_memory(frame.instance_buffer_mem, None);
+            }
+            frame.instance_buffer = buffer;
+            frame.instance_buffer_mem = memory;
+            frame.instance_capacity = capacity;
+        }
+
+        let size = (count * mem::size_of::<InstanceData>()) as u64;
+        unsafe {
+            let data = self
+                .device
+                .map_memory(
+                    frame.instance_buffer_mem,
+                    0,
+                    size,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .map_err(|_| "Failed to map instance buffer")?;
+            let mut align = ash::util::Align::new(data, mem::align_of::<Mat4>() as u64, size);
+            align.copy_from_slice(&self.instance_transforms);
+            self.device.unmap_memory(frame.instance_buffer_mem);
+        }
+        Ok(())
+    }
+
+    fn destroy_frames(device: &ash::Device, frames: &[FrameResources]) {
+        unsafe {
+            for frame in frames {
+                device.destroy_command_pool(frame.command_pool, None);
+                device.destroy_semaphore(frame.image_available, None);
+                device.destroy_semaphore(frame.render_finished, None);
+                device.destroy_buffer(frame.uniform_buffer, None);
+                device.free_memory(frame.uniform_buffer_mem, None);
+                device.destroy_buffer(frame.instance_buffer, None);
+                device.free_memory(frame.instance_buffer_mem, None);
+            }
+        }
+    }
+
+    // FIXME: Also sus
+    fn destroy_swapchain(&mut self) {
+        unsafe {
+            for i in self.present_image_views.iter() {
+                self.device.destroy_image_view(*i, None);
             }
+            self.swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
         }
+    }
 
-        self.current_frame = (self.current_frame + 1) % self.frames_in_flight;
+    // Baked mesh bytes into device local vertex/index buffers
+    fn upload_mesh(
+        device: &ash::Device,
+        queue: vk::Queue,
+        queue_family_index: u32,
+        mem_properties: vk::PhysicalDeviceMemoryProperties,
+        bytes: &[u8],
+    ) -> Result<GpuMesh, &'static str> {
+        let mesh = MeshData::parse(bytes)?;
+        if mesh.vertex_stride as usize != mem::size_of::<Vertex>() {
+            return Err("Baked mesh vertex layout doesn't match Vertex, rebake it");
+        }
+        let index_type = match mesh.index_format {
+            IndexFormat::U16 => vk::IndexType::UINT16,
+            IndexFormat::U32 => vk::IndexType::UINT32,
+        };
+
+        let (vertex_buffer, vertex_buffer_mem) = Renderer::create_device_local_buffer(
+            device,
+            queue,
+            queue_family_index,
+            mem_properties,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            mesh.vertices,
+        );
+        let (index_buffer, index_buffer_mem) = Renderer::create_device_local_buffer(
+            device,
+            queue,
+            queue_family_index,
+            mem_properties,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            mesh.indices,
+        );
+
+        Ok(GpuMesh {
+            vertex_buffer,
+            vertex_buffer_mem,
+            index_buffer,
+            index_buffer_mem,
+            index_count: mesh.index_count,
+            index_type,
+            pipeline: 0,
+        })
     }
 
-    // FIXME: Sus
-    pub fn recreate_swapchain(&mut self) {
+    fn destroy_gpu_mesh(device: &ash::Device, mesh: &GpuMesh) {
         unsafe {
-            self.device.device_wait_idle().unwrap();
-            self.destroy_swapchain();
-
-            let surface_caps = self
-                .surface_loader
-                .get_physical_device_surface_capabilities(self.physical_device, self.surface)
-                .unwrap();
+            device.destroy_buffer(mesh.vertex_buffer, None);
+            device.free_memory(mesh.vertex_buffer_mem, None);
+            device.destroy_buffer(mesh.index_buffer, None);
+            device.free_memory(mesh.index_buffer_mem, None);
+        }
+    }
 
-            self.surface_extent = match surface_caps.current_extent.width {
-                std::u32::MAX => {
-                    vk::Extent2D {
-                        // FIXME: Awful
-                        width: 800,
-                        height: 600,
-                    }
-                }
-                _ => surface_caps.current_extent,
-            };
+    // Baked texture bytes into a sampled image with every mip uploaded
+    fn upload_texture(
+        device: &ash::Device,
+        queue: vk::Queue,
+        queue_family_index: u32,
+        mem_properties: vk::PhysicalDeviceMemoryProperties,
+        resource_tracker: &mut ResourceTracker,
+        bytes: &[u8],
+    ) -> Result<GpuTexture, &'static str> {
+        let texture = TextureData::parse(bytes)?;
+        let texture_format = match texture.format {
+            TextureFormat::Rgba8Srgb => vk::Format::R8G8B8A8_SRGB,
+            TextureFormat::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+        };
+        let image_size = texture.data.len() as u64;
+        let mip_levels = texture.mips.len() as u32;
 
-            // Uniform buffers
-            let mem_properties = self
-                .instance
-                .get_physical_device_memory_properties(self.physical_device);
-            let buffer_size = mem::size_of::<UniformBufferObject>();
+        unsafe {
+            let (staging_buffer, staging_buffer_mem) = Renderer::create_buffer(
+                device,
+                image_size,
+                mem_properties,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .unwrap();
 
-            self.uniform_buffers.clear();
-            self.uniform_buffers_mem.clear();
-            for _i in 0..self.present_images.len() {
-                let (buf, buf_mem) = Renderer::create_buffer(
-                    &self.device,
-                    buffer_size as u64,
-                    mem_properties,
-                    vk::BufferUsageFlags::UNIFORM_BUFFER,
-                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            let data = device
+                .map_memory(
+                    staging_buffer_mem,
+                    0,
+                    image_size,
+                    vk::MemoryMapFlags::empty(),
                 )
                 .unwrap();
-                self.uniform_buffers.push(buf);
-                self.uniform_buffers_mem.push(buf_mem);
-            }
 
-            // Depth image
-            let (depth_image, depth_image_mem) = Renderer::create_image(
-                &self.device,
-                self.surface_extent.width,
-                self.surface_extent.height,
-                1,
-                vk::Format::D32_SFLOAT,
+            let mut align = ash::util::Align::new(
+                data,
+                mem::align_of::<u8>() as u64,
+                device.get_buffer_memory_requirements(staging_buffer).size,
+            );
+            align.copy_from_slice(texture.data);
+            device.unmap_memory(staging_buffer_mem);
+
+            let (image, image_mem) = Renderer::create_image(
+                device,
+                texture.width,
+                texture.height,
+                mip_levels,
+                texture_format,
                 vk::ImageTiling::OPTIMAL,
-                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
                 vk::MemoryPropertyFlags::DEVICE_LOCAL,
                 mem_properties,
             )
             .unwrap();
 
-            // Depth image view
-            let view_info = vk::ImageViewCreateInfo::builder()
-                .image(depth_image)
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .format(vk::Format::D32_SFLOAT)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                });
-
-            self.depth_image_view = self.device.create_image_view(&view_info, None).unwrap();
-            self.depth_image = depth_image;
-            self.depth_image_mem = depth_image_mem;
-
-            let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
-                .surface(self.surface)
-                .min_image_count(self.present_images.len() as u32) //FIXME: Sus
-                .image_format(self.surface_format.format)
-                .image_color_space(self.surface_format.color_space)
-                .image_extent(self.surface_extent)
-                .image_array_layers(1)
-                .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
-                .image_sharing_mode(vk::SharingMode::EXCLUSIVE) // FIXME: Only if present_queue == graphics queue
-                .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY) // NOTE: Identity transform?
-                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-                .present_mode(self.present_mode)
-                .clipped(true);
-
-            self.swapchain = self
-                .swapchain_loader
-                .create_swapchain(&swapchain_create_info, None)
-                .unwrap();
-
-            self.present_images = self
-                .swapchain_loader
-                .get_swapchain_images(self.swapchain)
-                .unwrap();
-
-            self.present_image_views = self
-                .present_images
+            resource_tracker.track_image(image, texture_format, mip_levels, 1, Access::Nothing);
+            Renderer::transition_image(
+                device,
+                queue,
+                queue_family_index,
+                resource_tracker,
+                image,
+                Access::TransferWrite,
+            )?;
+
+            let copy_regions: Vec<vk::BufferImageCopy> = texture
+                .mips
                 .iter()
-                .map(|i| {
-                    let image_view = vk::ImageViewCreateInfo::builder()
-                        .image(*i)
-                        .view_type(vk::ImageViewType::TYPE_2D)
-                        .format(self.surface_format.format)
-                        .components(vk::ComponentMapping {
-                            r: vk::ComponentSwizzle::R,
-                            g: vk::ComponentSwizzle::G,
-                            b: vk::ComponentSwizzle::B,
-                            a: vk::ComponentSwizzle::A,
-                        })
-                        .subresource_range(vk::ImageSubresourceRange {
+                .enumerate()
+                .map(|(level, mip)| {
+                    vk::BufferImageCopy::builder()
+                        .buffer_offset(mip.offset as u64)
+                        .image_subresource(vk::ImageSubresourceLayers {
                             aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: 1,
+                            mip_level: level as u32,
                             base_array_layer: 0,
                             layer_count: 1,
-                        });
-                    self.device.create_image_view(&image_view, None).unwrap()
+                        })
+                        .image_extent(vk::Extent3D {
+                            width: mip.width,
+                            height: mip.height,
+                            depth: 1,
+                        })
+                        .build()
                 })
                 .collect();
 
-            // Framebuffer
-            self.framebuffers = Vec::with_capacity(self.present_image_views.len());
-
-            for &view in self.present_image_views.iter() {
-                let attachments = [view, self.depth_image_view];
-                let framebuffer_info = vk::FramebufferCreateInfo::builder()
-                    .render_pass(self.render_pass)
-                    .attachments(&attachments)
-                    .width(self.surface_extent.width)
-                    .height(self.surface_extent.height)
-                    .layers(1);
-
-                self.framebuffers.push(
-                    self.device
-                        .create_framebuffer(&framebuffer_info, None)
-                        .unwrap(),
-                );
-            }
-
-            // Descriptor pool
-            let pool_sizes = [
-                vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(self.present_images.len() as u32)
-                    .build(),
-                vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .descriptor_count(self.present_images.len() as u32)
-                    .build(),
-            ];
-
-            let pool_info = vk::DescriptorPoolCreateInfo::builder()
-                .pool_sizes(&pool_sizes)
-                .max_sets(self.present_images.len() as u32);
-
-            self.descriptor_pool = self
-                .device
-                .create_descriptor_pool(&pool_info, None)
-                .unwrap();
-
-            // Descriptor sets
-            let descriptor_set_info = vk::DescriptorSetAllocateInfo::builder()
-                .descriptor_pool(self.descriptor_pool)
-                .set_layouts(&self.descriptor_set_layouts);
+            Renderer::copy_buffer_to_image(
+                device,
+                queue,
+                queue_family_index,
+                staging_buffer,
+                image,
+                &copy_regions,
+            );
 
-            self.descriptor_sets = self
-                .device
-                .allocate_descriptor_sets(&descriptor_set_info)
-                .unwrap();
+            Renderer::transition_image(
+                device,
+                queue,
+                queue_family_index,
+                resource_tracker,
+                image,
+                Access::FragmentShaderRead,
+            )?;
 
-            for (i, s) in self.descriptor_sets.iter().enumerate() {
-                let buffer_info = vk::DescriptorBufferInfo::builder()
-                    .buffer(self.uniform_buffers[i])
-                    .offset(0)
-                    .range(mem::size_of::<UniformBufferObject>() as u64)
-                    .build();
-
-                let image_info = vk::DescriptorImageInfo::builder()
-                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                    .image_view(self.texture_image_view)
-                    .sampler(self.texture_sampler)
-                    .build();
-
-                let descriptor_writes = [
-                    vk::WriteDescriptorSet::builder()
-                        .dst_set(*s)
-                        .dst_binding(0)
-                        .dst_array_element(0)
-                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                        .buffer_info(&[buffer_info])
-                        .build(),
-                    vk::WriteDescriptorSet::builder()
-                        .dst_set(*s)
-                        .dst_binding(1)
-                        .dst_array_element(0)
-                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                        .image_info(&[image_info])
-                        .build(),
-                ];
-
-                self.device.update_descriptor_sets(&descriptor_writes, &[]);
-            }
+            device.destroy_buffer(staging_buffer, None);
+            device.free_memory(staging_buffer_mem, None);
 
-            // One buffer for each framebuffer
-            let buf_alloc_info = vk::CommandBufferAllocateInfo::builder()
-                .command_pool(self.command_pool)
-                .command_buffer_count(self.present_image_views.len() as u32)
-                .level(vk::CommandBufferLevel::PRIMARY);
+            let view_info = vk::ImageViewCreateInfo::builder()
+                .image(image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(texture_format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: mip_levels,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                });
 
-            self.command_buffers = self
-                .device
-                .allocate_command_buffers(&buf_alloc_info)
-                .unwrap();
+            let image_view = device.create_image_view(&view_info, None).unwrap();
 
-            for (i, buffer) in self.command_buffers.iter().enumerate() {
-                let buf_begin_info = vk::CommandBufferBeginInfo::default();
-                self.device
-                    .begin_command_buffer(*buffer, &buf_begin_info)
-                    .unwrap();
+            Ok(GpuTexture {
+                image,
+                image_mem,
+                image_view,
+            })
+        }
+    }
 
-                let clear_values = [
-                    vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    },
-                    vk::ClearValue {
-                        depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 1.0,
-                            stencil: 0,
-                        },
-                    },
-                ];
-
-                let render_begin_info = vk::RenderPassBeginInfo::builder()
-                    .render_pass(self.render_pass)
-                    .framebuffer(self.framebuffers[i])
-                    .render_area(vk::Rect2D {
-                        offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: self.surface_extent,
-                    })
-                    .clear_values(&clear_values);
-
-                self.device.cmd_begin_render_pass(
-                    *buffer,
-                    &render_begin_info,
-                    vk::SubpassContents::INLINE,
-                );
+    fn destroy_texture(
+        device: &ash::Device,
+        resource_tracker: &mut ResourceTracker,
+        texture: &GpuTexture,
+    ) {
+        resource_tracker.forget_image(texture.image);
+        unsafe {
+            device.destroy_image_view(texture.image_view, None);
+            device.destroy_image(texture.image, None);
+            device.free_memory(texture.image_mem, None);
+        }
+    }
 
-                self.device.cmd_bind_pipeline(
-                    *buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    self.graphics_pipeline,
-                );
+    // Goes through a staging buffer, `usage` gets TRANSFER_DST added
+    fn create_device_local_buffer(
+        device: &ash::Device,
+        queue: vk::Queue,
+        queue_family_index: u32,
+        mem_properties: vk::PhysicalDeviceMemoryProperties,
+        usage: vk::BufferUsageFlags,
+        data: &[u8],
+    ) -> (vk::Buffer, vk::DeviceMemory) {
+        let buffer_size = data.len() as u64;
 
-                // bind vertex buffer
-                let vertex_buffers = vec![self.vertex_buffer];
-                let offsets = vec![0];
-                self.device
-                    .cmd_bind_vertex_buffers(*buffer, 0, &vertex_buffers, &offsets);
+        unsafe {
+            let (staging_buffer, staging_buffer_mem) = Renderer::create_buffer(
+                device,
+                buffer_size,
+                mem_properties,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .unwrap();
 
-                // bind index buffer
-                self.device.cmd_bind_index_buffer(
-                    *buffer,
-                    self.index_buffer,
+            let mapped = device
+                .map_memory(
+                    staging_buffer_mem,
                     0,
-                    vk::IndexType::UINT32,
-                );
+                    buffer_size,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .unwrap();
 
-                let viewport = [vk::Viewport::builder()
-                    .x(0.0)
-                    .y(0.0)
-                    .width(self.surface_extent.width as f32) // FIXME: Swapchain image size vs surface
-                    .height(self.surface_extent.height as f32)
-                    .min_depth(0.0)
-                    .max_depth(1.0)
-                    .build()];
-
-                let scissor = [vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: self.surface_extent,
-                }];
-
-                // Dynamic state
-                self.device.cmd_set_viewport(*buffer, 0, &viewport);
-                self.device.cmd_set_scissor(*buffer, 0, &scissor);
-
-                // Bind descriptor sets
-                self.device.cmd_bind_descriptor_sets(
-                    *buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    self.pipeline_layout,
-                    0,
-                    &[self.descriptor_sets[i]],
-                    &[],
-                );
+            let mut align =
+                ash::util::Align::new(mapped, mem::align_of::<u8>() as u64, buffer_size);
+            align.copy_from_slice(data);
+            device.unmap_memory(staging_buffer_mem);
 
-                //self.device
-                //    .cmd_draw_indexed(*buffer, self.indices.len() as u32, 1, 0, 0, 0);
-                self.device
-                    .cmd_draw(*buffer, self.vertices.len() as u32, 1, 0, 0);
-                self.device.cmd_end_render_pass(*buffer);
+            let (buffer, buffer_mem) = Renderer::create_buffer(
+                device,
+                buffer_size,
+                mem_properties,
+                vk::BufferUsageFlags::TRANSFER_DST | usage,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
 
-                self.device.end_command_buffer(*buffer).unwrap();
-            }
-        }
-    }
+            Renderer::copy_buffer(
+                device,
+                queue,
+                queue_family_index,
+                staging_buffer,
+                buffer,
+                buffer_size,
+            );
 
-    // FIXME: Also sus
-    fn destroy_swapchain(&mut self) {
-        unsafe {
-            self.device.destroy_image_view(self.depth_image_view, None);
-            self.device.destroy_image(self.depth_image, None);
-            self.device.free_memory(self.depth_image_mem, None);
-            for f in self.framebuffers.iter() {
-                self.device.destroy_framebuffer(*f, None);
-            }
-            self.device
-                .free_command_buffers(self.command_pool, &self.command_buffers);
-            for i in self.present_image_views.iter() {
-                self.device.destroy_image_view(*i, None);
-            }
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, None);
+            device.destroy_buffer(staging_buffer, None);
+            device.free_memory(staging_buffer_mem, None);
 
-            for b in self.uniform_buffers.iter() {
-                self.device.destroy_buffer(*b, None);
-            }
-            for m in self.uniform_buffers_mem.iter() {
-                self.device.free_memory(*m, None);
-            }
-            self.device
-                .destroy_descriptor_pool(self.descriptor_pool, None);
+            (buffer, buffer_mem)
         }
     }
 
@@ -1618,6 +2228,8 @@ impl Renderer {
         }
     }
 
+    // Blocks until the GPU is done, so it stays outside of FrameSync's counter: the counter's
+    // values are frame numbers, and dependent work is only recorded after this returns anyway.
     fn do_single_command<D: DeviceV1_0, F: FnOnce(&D, vk::CommandBuffer)>(
         device: &D,
         command_buffer: vk::CommandBuffer,
@@ -1726,7 +2338,7 @@ impl Renderer {
                     height,
                     depth: 1,
                 })
-                .mip_levels(1)
+                .mip_levels(mip_levels)
                 .array_layers(1)
                 .format(format)
                 .tiling(tiling)
@@ -1752,16 +2364,15 @@ impl Renderer {
         }
     }
 
-    fn transition_image_layout(
+    // Every mip and layer of a tracked image, barrier worked out by the tracker
+    fn transition_image(
         device: &ash::Device,
         queue: vk::Queue,
         queue_family_index: u32,
+        resource_tracker: &mut ResourceTracker,
         image: vk::Image,
-        mip_levels: u32,
-        format: vk::Format,
-        old_layout: vk::ImageLayout,
-        new_layout: vk::ImageLayout,
-    ) {
+        to: Access,
+    ) -> Result<(), &'static str> {
         unsafe {
             // NOTE: Separate command pool for transient buffers?
             //       would need to store this
@@ -1782,64 +2393,25 @@ impl Renderer {
                 .allocate_command_buffers(&transition_buf_info)
                 .unwrap();
 
+            let mut result = Ok(());
             Renderer::do_single_command(
                 device,
                 transition_buf[0],
                 queue,
                 |device, transition_buf| {
-                    let mut barrier = vk::ImageMemoryBarrier {
-                        old_layout,
-                        new_layout,
-                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                        image,
-                        subresource_range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                            ..Default::default()
-                        },
-                        ..Default::default()
-                    };
-
-                    let source_stage;
-                    let dest_stage;
-
-                    if old_layout == vk::ImageLayout::UNDEFINED
-                        && new_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL
-                    {
-                        barrier.src_access_mask = vk::AccessFlags::empty();
-                        barrier.dst_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-
-                        source_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
-                        dest_stage = vk::PipelineStageFlags::TRANSFER;
-                    } else if old_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL
-                        && new_layout == vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
-                    {
-                        barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-                        barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-
-                        source_stage = vk::PipelineStageFlags::TRANSFER;
-                        dest_stage = vk::PipelineStageFlags::FRAGMENT_SHADER;
-                    } else {
-                        panic!("Undefined layout transition");
-                    }
-
-                    device.cmd_pipeline_barrier(
+                    result = resource_tracker.transition_image(
+                        device,
                         transition_buf,
-                        source_stage,
-                        dest_stage,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[],
-                        &[barrier],
+                        image,
+                        0..vk::REMAINING_MIP_LEVELS,
+                        0..vk::REMAINING_ARRAY_LAYERS,
+                        to,
                     );
                 },
             );
 
             device.destroy_command_pool(transient_cmd_pool, None);
+            result
         }
     }
 
@@ -1849,8 +2421,7 @@ impl Renderer {
         queue_family_index: u32,
         buffer: vk::Buffer,
         image: vk::Image,
-        width: u32,
-        height: u32,
+        regions: &[vk::BufferImageCopy],
     ) {
         unsafe {
             // NOTE: Separate command pool for transient buffers?
@@ -1871,30 +2442,12 @@ impl Renderer {
             let cmd_buf = device.allocate_command_buffers(&cmd_buf_info).unwrap();
 
             Renderer::do_single_command(device, cmd_buf[0], queue, |device, cmd_buf| {
-                let copy_region = vk::BufferImageCopy::builder()
-                    .buffer_offset(0)
-                    .buffer_row_length(0)
-                    .buffer_image_height(0)
-                    .image_subresource(vk::ImageSubresourceLayers {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        mip_level: 0,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                        ..Default::default()
-                    })
-                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
-                    .image_extent(vk::Extent3D {
-                        width,
-                        height,
-                        depth: 1,
-                    });
-
                 device.cmd_copy_buffer_to_image(
                     cmd_buf,
                     buffer,
                     image,
                     vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    &[copy_region.build()],
+                    regions,
                 );
             });
 
@@ -1909,32 +2462,25 @@ impl Drop for Renderer {
             self.device.device_wait_idle().unwrap();
             self.destroy_swapchain();
             self.device.destroy_sampler(self.texture_sampler, None);
+            Renderer::destroy_texture(&self.device, &mut self.resource_tracker, &self.texture);
+            if let Some(ref mut bindless) = self.bindless {
+                bindless.destroy(&self.device);
+            }
+            self.pipeline_cache.destroy(&self.device);
+            self.device.destroy_shader_module(self.vertex_shader, None);
             self.device
-                .destroy_image_view(self.texture_image_view, None);
-            self.device.destroy_image(self.texture_image, None);
-            self.device.free_memory(self.texture_image_mem, None);
-            self.device.destroy_pipeline(self.graphics_pipeline, None);
+                .destroy_shader_module(self.fragment_shader, None);
             self.device
                 .destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.destroy_render_pass(self.render_pass, None);
+            self.frame_graph.destroy(&self.device);
 
-            self.device
-                .destroy_descriptor_set_layout(self.descriptor_set_layouts[0], None);
-
-            self.device.destroy_buffer(self.vertex_buffer, None);
-            self.device.free_memory(self.vertex_buffer_mem, None);
-            self.device.destroy_buffer(self.index_buffer, None);
-            self.device.free_memory(self.index_buffer_mem, None);
-            self.device.destroy_command_pool(self.command_pool, None);
-            for s in self.image_available_sems.iter() {
-                self.device.destroy_semaphore(*s, None);
-            }
-            for s in self.render_finished_sems.iter() {
-                self.device.destroy_semaphore(*s, None);
-            }
-            for f in self.in_flight_fences.iter() {
-                self.device.destroy_fence(*f, None);
+            for mesh in self.meshes.iter().filter_map(|slot| slot.mesh.as_ref()) {
+                Renderer::destroy_gpu_mesh(&self.device, mesh);
             }
+            Renderer::destroy_frames(&self.device, &self.frames);
+            self.descriptor_allocator.destroy(&self.device);
+            self.recorder.destroy(&self.device);
+            self.frame_sync.destroy(&self.device);
             if let Some(ref utils) = self.debug_utils {
                 utils.destroy_debug_utils_messenger(self.debug_messenger, None);
             }
@@ -1945,33 +2491,6 @@ impl Drop for Renderer {
     }
 }
 
-pub fn load_model() -> (Vec<Vertex>, Vec<u32>) {
-    println!("STARTED LOAD");
-    let model =
-        obj::Obj::load("/home/bn/projects/ency/enegine/src/bin/models/viking_room.obj").unwrap();
-    let mut vertices = Vec::with_capacity(model.data.position.len());
-    let mut indices = Vec::with_capacity(model.data.position.len());
-
-    for o in model.data.objects {
-        for g in o.groups {
-            for poly in g.polys {
-                for index in &poly.0 {
-                    let vert = model.data.position[index.0];
-                    let tex = model.data.texture[index.1.unwrap()];
-                    vertices.push(Vertex {
-                        position: Vec3::new(vert[0], vert[1], vert[2]),
-                        color: Vec3::zero(),
-                        tex_coord: Vec2::new(tex[0], 1.0 - tex[1]),
-                    });
-                    indices.push(0 as u32);
-                }
-            }
-        }
-    }
-    println!("ENDED LOAD");
-    (vertices, indices)
-}
-
 pub fn find_memorytype_index(
     memory_req: &vk::MemoryRequirements,
     memory_prop: &vk::PhysicalDeviceMemoryProperties,
@@ -0,0 +1,530 @@
+// GLSL and HLSL to SPIR-V through shaderc. Stage and language come from the file name: GLSL is
+// .vert, .frag and .comp, HLSL is .hlsl with the stage in front of it (foo.vs.hlsl, foo.ps.hlsl,
+// foo.cs.hlsl, or foo.vert.hlsl etc.). Both can be overridden through ShaderSource.
+//
+// Every compile is a variant: a source file plus a set of defines. Variants are cached along with
+// the files they included, so a change to any of them only recompiles what actually uses it.
+//
+// Without the `runtime-shaders` feature there's no shaderc at all, variants come from the SPIR-V
+// build.rs embedded with `embedded-shaders` instead.
+
+// Otherwise every shader load fails at runtime
+#[cfg(not(any(feature = "runtime-shaders", feature = "embedded-shaders")))]
+compile_error!("enegine needs the runtime-shaders or the embedded-shaders feature to load shaders");
+
+#[cfg(feature = "runtime-shaders")]
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+#[cfg(feature = "runtime-shaders")]
+use super::shader_cache::{self, ShaderCache};
+use crate::asset::{import, vfs::Vfs};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SourceLanguage {
+    Glsl,
+    Hlsl,
+}
+
+pub fn source_language(path: &str) -> SourceLanguage {
+    match import::extension(path).as_deref() {
+        Some("hlsl") => SourceLanguage::Hlsl,
+        _ => SourceLanguage::Glsl,
+    }
+}
+
+// For .hlsl and .glsl the stage is the extension before that one
+pub fn shader_stage(path: &str) -> Result<vk::ShaderStageFlags, &'static str> {
+    let stage = match import::extension(path).as_deref() {
+        Some("hlsl") | Some("glsl") => import::extension(&path[..path.rfind('.').unwrap()]),
+        extension => extension.map(str::to_string),
+    };
+    match stage.as_deref() {
+        Some("vert") | Some("vs") => Ok(vk::ShaderStageFlags::VERTEX),
+        Some("frag") | Some("ps") => Ok(vk::ShaderStageFlags::FRAGMENT),
+        Some("comp") | Some("cs") => Ok(vk::ShaderStageFlags::COMPUTE),
+        _ => Err("Unknown shader stage, expected .vert, .frag, .comp or .vs/.ps/.cs.hlsl"),
+    }
+}
+
+// GLSL always starts at main, HLSL defaults to VSMain/PSMain/CSMain
+pub fn default_entry_point(language: SourceLanguage, stage: vk::ShaderStageFlags) -> &'static str {
+    match (language, stage) {
+        (SourceLanguage::Glsl, _) => "main",
+        (SourceLanguage::Hlsl, vk::ShaderStageFlags::VERTEX) => "VSMain",
+        (SourceLanguage::Hlsl, vk::ShaderStageFlags::FRAGMENT) => "PSMain",
+        (SourceLanguage::Hlsl, _) => "CSMain",
+    }
+}
+
+// Which file to compile and how. `new` works it all out from the path.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderSource {
+    pub path: String,
+    pub language: SourceLanguage,
+    pub stage: vk::ShaderStageFlags,
+    pub entry_point: String,
+}
+
+impl ShaderSource {
+    pub fn new(path: &str) -> Result<Self, &'static str> {
+        let language = source_language(path);
+        let stage = shader_stage(path)?;
+        Ok(ShaderSource {
+            path: path.to_string(),
+            language,
+            stage,
+            entry_point: default_entry_point(language, stage).to_string(),
+        })
+    }
+
+    // For sources whose name doesn't say what they are, e.g. a shared .hlsl with several entry
+    // points. The entry point goes back to the default for the language and stage.
+    pub fn explicit(path: &str, language: SourceLanguage, stage: vk::ShaderStageFlags) -> Self {
+        ShaderSource {
+            path: path.to_string(),
+            language,
+            stage,
+            entry_point: default_entry_point(language, stage).to_string(),
+        }
+    }
+
+    pub fn with_entry_point(mut self, entry_point: &str) -> Self {
+        self.entry_point = entry_point.to_string();
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptimizationLevel {
+    Zero,
+    Size,
+    Performance,
+}
+
+// Kept sorted so the same defines given in a different order hit the same variant
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Defines(Vec<(String, Option<String>)>);
+
+impl Defines {
+    pub fn new() -> Self {
+        Defines(Vec::new())
+    }
+
+    // `#define NAME`
+    pub fn with(self, name: &str) -> Self {
+        self.insert(name, None)
+    }
+
+    // `#define NAME VALUE`
+    pub fn with_value(self, name: &str, value: &str) -> Self {
+        self.insert(name, Some(value.to_string()))
+    }
+
+    fn insert(mut self, name: &str, value: Option<String>) -> Self {
+        match self.0.binary_search_by(|(n, _)| n.as_str().cmp(name)) {
+            Ok(i) => self.0[i].1 = value,
+            Err(i) => self.0.insert(i, (name.to_string(), value)),
+        }
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(n, _)| n == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_deref()))
+    }
+}
+
+// A variant compiled by build.rs, see embedded_shaders.rs in OUT_DIR. Always with the default
+// language, stage and entry point for its path.
+pub struct EmbeddedShader {
+    pub path: &'static str,
+    // Sorted the same way as Defines
+    pub defines: &'static [(&'static str, Option<&'static str>)],
+    pub spirv: &'static [u8],
+}
+
+#[cfg(feature = "embedded-shaders")]
+mod embedded {
+    use super::EmbeddedShader;
+
+    include!(concat!(env!("OUT_DIR"), "/embedded_shaders.rs"));
+}
+
+#[cfg(feature = "embedded-shaders")]
+fn embedded_variant(source: &ShaderSource, defines: &Defines) -> Option<Vec<u32>> {
+    if ShaderSource::new(&source.path).ok().as_ref() != Some(source) {
+        return None;
+    }
+    embedded::EMBEDDED_SHADERS
+        .iter()
+        .find(|s| s.path == source.path && s.defines.iter().copied().eq(defines.iter()))
+        .map(|s| spirv_from_bytes(s.spirv))
+}
+
+#[cfg(not(feature = "embedded-shaders"))]
+fn embedded_variant(_source: &ShaderSource, _defines: &Defines) -> Option<Vec<u32>> {
+    None
+}
+
+pub fn spirv_from_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+struct Variant {
+    spirv: Vec<u32>,
+    // The source itself and everything it included, empty for embedded variants
+    dependencies: Vec<String>,
+    // A dependency changed, recompiled on the next request
+    stale: bool,
+}
+
+pub struct ShaderCompiler {
+    #[cfg(feature = "runtime-shaders")]
+    compiler: shaderc::Compiler,
+    #[cfg(feature = "runtime-shaders")]
+    cache: Option<ShaderCache>,
+    // Only read when compiling, embedded variants don't need it
+    #[cfg_attr(not(feature = "runtime-shaders"), allow(dead_code))]
+    vfs: Arc<Vfs>,
+    include_dirs: Vec<String>,
+    optimization_level: OptimizationLevel,
+    variants: HashMap<(ShaderSource, Defines), Variant>,
+}
+
+// Next to the executable, so `cargo clean` gets rid of it during development
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    Some(exe.parent()?.join("shader_cache"))
+}
+
+impl ShaderCompiler {
+    pub fn new(vfs: Arc<Vfs>) -> Result<Self, &'static str> {
+        let optimization_level = if cfg!(debug_assertions) {
+            OptimizationLevel::Zero
+        } else {
+            OptimizationLevel::Performance
+        };
+
+        Ok(ShaderCompiler {
+            #[cfg(feature = "runtime-shaders")]
+            compiler: shaderc::Compiler::new().ok_or("Failed to create shader compiler")?,
+            #[cfg(feature = "runtime-shaders")]
+            cache: None,
+            vfs,
+            include_dirs: Vec::new(),
+            optimization_level,
+            variants: HashMap::new(),
+        })
+    }
+
+    // Searched in order for `#include <...>`, and for `#include "..."` that isn't found next to
+    // the including file
+    pub fn add_include_dir(&mut self, dir: &str) {
+        self.include_dirs
+            .push(dir.trim_end_matches('/').to_string());
+        self.variants.clear();
+    }
+
+    pub fn set_optimization_level(&mut self, level: OptimizationLevel) {
+        if level != self.optimization_level {
+            self.optimization_level = level;
+            self.variants.clear();
+        }
+    }
+
+    // Keep compiled SPIR-V in `dir` between runs. Does nothing without runtime-shaders.
+    pub fn set_cache_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        #[cfg(feature = "runtime-shaders")]
+        {
+            self.cache = Some(ShaderCache::new(dir));
+        }
+        #[cfg(not(feature = "runtime-shaders"))]
+        {
+            let _ = dir.into();
+        }
+    }
+
+    // SPIR-V for `path` compiled with `defines`, only compiled if it isn't cached already
+    pub fn variant(&mut self, path: &str, defines: &Defines) -> Result<&[u32], &'static str> {
+        self.source_variant(&ShaderSource::new(path)?, defines)
+    }
+
+    // Same as `variant`, for a source with an explicit language, stage or entry point
+    pub fn source_variant(
+        &mut self,
+        source: &ShaderSource,
+        defines: &Defines,
+    ) -> Result<&[u32], &'static str> {
+        let key = (source.clone(), defines.clone());
+
+        if !matches!(self.variants.get(&key), Some(v) if !v.stale) {
+            let variant = self.compile(source, defines)?;
+            self.variants.insert(key.clone(), variant);
+        }
+
+        Ok(&self.variants[&key].spirv)
+    }
+
+    // Every file read by a cached variant, for watching
+    pub fn dependencies(&self) -> Vec<String> {
+        let mut dependencies: Vec<String> = self
+            .variants
+            .values()
+            .flat_map(|v| v.dependencies.iter().cloned())
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+        dependencies
+    }
+
+    // Marks every variant that depends on `changed` as stale and returns them. A stale variant
+    // keeps its dependencies, so if recompiling it fails the next change still finds it.
+    pub fn invalidate(&mut self, changed: &str) -> Vec<(ShaderSource, Defines)> {
+        self.variants
+            .iter_mut()
+            .filter(|(_, v)| v.dependencies.iter().any(|d| d == changed))
+            .map(|(key, v)| {
+                v.stale = true;
+                key.clone()
+            })
+            .collect()
+    }
+
+    #[cfg(not(feature = "runtime-shaders"))]
+    fn compile(
+        &mut self,
+        source: &ShaderSource,
+        defines: &Defines,
+    ) -> Result<Variant, &'static str> {
+        match embedded_variant(source, defines) {
+            Some(spirv) => Ok(Variant {
+                spirv,
+                dependencies: Vec::new(),
+                stale: false,
+            }),
+            None => {
+                error!(
+                    "{} {} {:?} wasn't compiled in by build.rs",
+                    source.path, source.entry_point, defines
+                );
+                Err("Shader variant isn't embedded and runtime-shaders is disabled")
+            }
+        }
+    }
+
+    // Diagnostics go to the log, callers only get told that it failed
+    #[cfg(feature = "runtime-shaders")]
+    fn compile(
+        &mut self,
+        shader: &ShaderSource,
+        defines: &Defines,
+    ) -> Result<Variant, &'static str> {
+        let path = shader.path.as_str();
+        let source = match self.vfs.read_to_string(path) {
+            Ok(source) => source,
+            // Shipped without sources, the embedded SPIR-V is all there is
+            Err(e) => {
+                return embedded_variant(shader, defines)
+                    .map(|spirv| Variant {
+                        spirv,
+                        dependencies: Vec::new(),
+                        stale: false,
+                    })
+                    .ok_or(e)
+            }
+        };
+
+        let mut hasher = shader_cache::Hasher::new();
+        hasher.write_str(path);
+        hasher.write_str(&format!("{:?} {:?}", shader.language, shader.stage));
+        hasher.write_str(&shader.entry_point);
+        hasher.write_str(&source);
+        for (name, value) in defines.iter() {
+            hasher.write_str(name);
+            hasher.write_str(value.unwrap_or(""));
+        }
+        hasher.write_str(&format!("{:?}", self.optimization_level));
+        // Includes could resolve to other files with another search path
+        for dir in &self.include_dirs {
+            hasher.write_str(dir);
+        }
+        let cache_key = hasher.finish();
+
+        if let Some(ref cache) = self.cache {
+            if let Some((spirv, dependencies)) = cache.load(cache_key, &self.vfs) {
+                return Ok(Variant {
+                    spirv,
+                    dependencies,
+                    stale: false,
+                });
+            }
+        }
+
+        let kind = match shader.stage {
+            vk::ShaderStageFlags::VERTEX => shaderc::ShaderKind::Vertex,
+            vk::ShaderStageFlags::FRAGMENT => shaderc::ShaderKind::Fragment,
+            _ => shaderc::ShaderKind::Compute,
+        };
+
+        let vfs = &self.vfs;
+        let include_dirs = &self.include_dirs;
+        let included = RefCell::new(vec![path.to_string()]);
+
+        let mut options =
+            shaderc::CompileOptions::new().ok_or("Failed to create shader compile options")?;
+        options.set_optimization_level(match self.optimization_level {
+            OptimizationLevel::Zero => shaderc::OptimizationLevel::Zero,
+            OptimizationLevel::Size => shaderc::OptimizationLevel::Size,
+            OptimizationLevel::Performance => shaderc::OptimizationLevel::Performance,
+        });
+        if shader.language == SourceLanguage::Hlsl {
+            options.set_source_language(shaderc::SourceLanguage::HLSL);
+        }
+        for (name, value) in defines.iter() {
+            options.add_macro_definition(name, value);
+        }
+        options.set_include_callback(|name, include_type, requester, _depth| {
+            let relative = include_type == shaderc::IncludeType::Relative;
+            let resolved = resolve_include(vfs, include_dirs, name, relative, requester)?;
+            let content = vfs
+                .read_to_string(&resolved)
+                .map_err(|e| format!("{}: {}", resolved, e))?;
+            included.borrow_mut().push(resolved.clone());
+            Ok(shaderc::ResolvedInclude {
+                resolved_name: resolved,
+                content,
+            })
+        });
+
+        let result = self.compiler.compile_into_spirv(
+            &source,
+            kind,
+            path,
+            &shader.entry_point,
+            Some(&options),
+        );
+        drop(options);
+
+        match result {
+            Ok(artifact) => {
+                if artifact.get_num_warnings() > 0 {
+                    warn!("{}", artifact.get_warning_messages());
+                }
+
+                let mut dependencies = included.into_inner();
+                dependencies.sort();
+                dependencies.dedup();
+
+                let spirv = artifact.as_binary().to_vec();
+                if let Some(ref cache) = self.cache {
+                    if let Err(e) = cache.store(cache_key, &self.vfs, &spirv, &dependencies) {
+                        warn!("Failed to cache {}: {}", path, e);
+                    }
+                }
+
+                Ok(Variant {
+                    spirv,
+                    dependencies,
+                    stale: false,
+                })
+            }
+            Err(e) => {
+                error!("{}", e);
+                Err("Shader compilation failed")
+            }
+        }
+    }
+}
+
+// Relative includes are tried next to the including file first, as in C
+#[cfg(feature = "runtime-shaders")]
+fn resolve_include(
+    vfs: &Vfs,
+    include_dirs: &[String],
+    name: &str,
+    relative: bool,
+    requester: &str,
+) -> Result<String, String> {
+    if relative {
+        let dir = requester.rfind('/').map_or("", |i| &requester[..i + 1]);
+        let path = format!("{}{}", dir, name);
+        if vfs.exists(&path) {
+            return Ok(path);
+        }
+    }
+
+    include_dirs
+        .iter()
+        .map(|dir| format!("{}/{}", dir, name))
+        .find(|path| vfs.exists(path))
+        .ok_or_else(|| format!("Include {} not found", name))
+}
+
+pub fn create_module(
+    device: &ash::Device,
+    spirv: &[u32],
+) -> Result<vk::ShaderModule, &'static str> {
+    let module_info = vk::ShaderModuleCreateInfo::builder().code(spirv);
+    unsafe {
+        device
+            .create_shader_module(&module_info, None)
+            .map_err(|_| "Failed to create shader module")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defines_order_independent() {
+        let a = Defines::new().with("HAS_NORMAL_MAP").with("ALPHA_TEST");
+        let b = Defines::new().with("ALPHA_TEST").with("HAS_NORMAL_MAP");
+        assert_eq!(a, b);
+
+        let c = b.with_value("ALPHA_TEST", "1");
+        assert_ne!(a, c);
+        assert_eq!(
+            c.iter().collect::<Vec<_>>(),
+            vec![("ALPHA_TEST", Some("1")), ("HAS_NORMAL_MAP", None)]
+        );
+    }
+
+    #[test]
+    fn source_from_file_name() {
+        let glsl = ShaderSource::new("shader/triangle/triangle.frag").unwrap();
+        assert_eq!(glsl.language, SourceLanguage::Glsl);
+        assert_eq!(glsl.stage, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(glsl.entry_point, "main");
+
+        let hlsl = ShaderSource::new("shader/sky/sky.vs.hlsl").unwrap();
+        assert_eq!(hlsl.language, SourceLanguage::Hlsl);
+        assert_eq!(hlsl.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(hlsl.entry_point, "VSMain");
+
+        let stage = ShaderSource::new("shader/blur.comp.HLSL").unwrap();
+        assert_eq!(stage.stage, vk::ShaderStageFlags::COMPUTE);
+        assert_eq!(stage.entry_point, "CSMain");
+
+        assert!(ShaderSource::new("shader/common.hlsl").is_err());
+        let explicit = ShaderSource::explicit(
+            "shader/common.hlsl",
+            SourceLanguage::Hlsl,
+            vk::ShaderStageFlags::FRAGMENT,
+        )
+        .with_entry_point("ShadowPS");
+        assert_eq!(explicit.entry_point, "ShadowPS");
+    }
+}
@@ -0,0 +1,181 @@
+// Compiled SPIR-V on disk, so startup only runs shaderc for shaders that actually changed.
+//
+// Entries are named after a hash of the source, defines and compile options. What a shader
+// includes isn't known until it's compiled, so each entry also lists its includes with a hash of
+// their contents and is only used while those still match.
+//
+// Entry: magic "ESPV", version, include count, include count * (path length, path, hash),
+//        word count, SPIR-V words. All little endian.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::asset::vfs::Vfs;
+
+const MAGIC: [u8; 4] = *b"ESPV";
+// Bump when the entry layout or anything feeding the key changes
+const VERSION: u32 = 2;
+
+// FNV-1a, DefaultHasher isn't guaranteed to be stable between builds
+pub struct Hasher(u64);
+
+impl Hasher {
+    pub fn new() -> Self {
+        Hasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    // Length prefixed so ("ab", "c") and ("a", "bc") hash differently
+    pub fn write_str(&mut self, s: &str) {
+        self.write(&(s.len() as u64).to_le_bytes());
+        self.write(s.as_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Hasher::new()
+    }
+}
+
+pub fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = Hasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+pub struct ShaderCache {
+    dir: PathBuf,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let mut word = [0; 8];
+        word.copy_from_slice(self.take(8)?);
+        Some(u64::from_le_bytes(word))
+    }
+}
+
+impl ShaderCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        ShaderCache { dir: dir.into() }
+    }
+
+    fn entry_path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.spv", key))
+    }
+
+    // SPIR-V and includes of a still valid entry. Anything unreadable is just a miss.
+    pub fn load(&self, key: u64, vfs: &Vfs) -> Option<(Vec<u32>, Vec<String>)> {
+        let bytes = fs::read(self.entry_path(key)).ok()?;
+        let mut reader = Reader {
+            bytes: &bytes,
+            offset: 0,
+        };
+
+        if reader.take(4)? != MAGIC || reader.u32()? != VERSION {
+            return None;
+        }
+
+        let include_count = reader.u32()?;
+        let mut includes = Vec::new();
+        for _ in 0..include_count {
+            let len = reader.u32()? as usize;
+            let path = String::from_utf8(reader.take(len)?.to_vec()).ok()?;
+            let include_hash = reader.u64()?;
+            if vfs.read(&path).map(|b| hash(&b)).ok()? != include_hash {
+                return None;
+            }
+            includes.push(path);
+        }
+
+        let word_count = reader.u32()? as usize;
+        let spirv = reader
+            .take(word_count.checked_mul(4)?)?
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        Some((spirv, includes))
+    }
+
+    pub fn store(&self, key: u64, vfs: &Vfs, spirv: &[u32], includes: &[String]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(spirv.len() * 4 + 64);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(includes.len() as u32).to_le_bytes());
+        for include in includes {
+            let content = vfs
+                .read(include)
+                .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+            bytes.extend_from_slice(&(include.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(include.as_bytes());
+            bytes.extend_from_slice(&hash(&content).to_le_bytes());
+        }
+        bytes.extend_from_slice(&(spirv.len() as u32).to_le_bytes());
+        for word in spirv {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+
+        // Written to the side and renamed so a crash never leaves a torn entry behind
+        fs::create_dir_all(&self.dir)?;
+        let path = self.entry_path(key);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &bytes)?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_invalidated_by_includes() {
+        let dir = std::env::temp_dir().join(format!("enegine-shader-cache-{}", std::process::id()));
+        fs::create_dir_all(dir.join("include")).unwrap();
+        fs::write(dir.join("include/common.glsl"), b"float a;").unwrap();
+
+        let mut vfs = Vfs::new();
+        vfs.mount_dir("", &dir).unwrap();
+
+        let cache = ShaderCache::new(dir.join("cache"));
+        let includes = vec!["include/common.glsl".to_string()];
+        cache.store(42, &vfs, &[0x0723_0203, 7], &includes).unwrap();
+
+        assert_eq!(cache.load(42, &vfs), Some((vec![0x0723_0203, 7], includes)));
+        assert!(cache.load(43, &vfs).is_none());
+
+        fs::write(dir.join("include/common.glsl"), b"float b;").unwrap();
+        assert!(cache.load(42, &vfs).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+// VK_KHR_synchronization2, just the pipeline barrier part. ash doesn't know about it yet so the
+// structs are declared here. The new 64 bit stage and access masks keep the old bits, so legacy
+// flags convert by widening.
+
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+
+use ash::version::InstanceV1_0;
+use ash::vk;
+
+const MEMORY_BARRIER_2: vk::StructureType = vk::StructureType::from_raw(1_000_314_000);
+const BUFFER_MEMORY_BARRIER_2: vk::StructureType = vk::StructureType::from_raw(1_000_314_001);
+const IMAGE_MEMORY_BARRIER_2: vk::StructureType = vk::StructureType::from_raw(1_000_314_002);
+const DEPENDENCY_INFO: vk::StructureType = vk::StructureType::from_raw(1_000_314_003);
+const PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_007);
+
+pub fn extension_name() -> &'static CStr {
+    to_cstr!("VK_KHR_synchronization2")
+}
+
+// Chained into DeviceCreateInfo to turn the feature on
+#[repr(C)]
+pub struct Synchronization2Features {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    synchronization2: vk::Bool32,
+}
+
+impl Default for Synchronization2Features {
+    fn default() -> Self {
+        Synchronization2Features {
+            s_type: PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES,
+            p_next: ptr::null_mut(),
+            synchronization2: vk::TRUE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for Synchronization2Features {}
+
+#[repr(C)]
+struct MemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+}
+
+#[repr(C)]
+struct BufferMemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+#[repr(C)]
+struct ImageMemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+}
+
+#[repr(C)]
+struct DependencyInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    dependency_flags: vk::DependencyFlags,
+    memory_barrier_count: u32,
+    p_memory_barriers: *const MemoryBarrier2,
+    buffer_memory_barrier_count: u32,
+    p_buffer_memory_barriers: *const BufferMemoryBarrier2,
+    image_memory_barrier_count: u32,
+    p_image_memory_barriers: *const ImageMemoryBarrier2,
+}
+
+type CmdPipelineBarrier2 = unsafe extern "system" fn(vk::CommandBuffer, *const DependencyInfo);
+
+// A legacy barrier together with the stages it sits between
+pub struct Barrier<T> {
+    pub src_stage: vk::PipelineStageFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub barrier: T,
+}
+
+#[derive(Clone, Copy)]
+pub struct Synchronization2 {
+    cmd_pipeline_barrier2: CmdPipelineBarrier2,
+}
+
+impl Synchronization2 {
+    // Only valid for devices created with the extension and Synchronization2Features
+    pub fn load(instance: &ash::Instance, device: &ash::Device) -> Option<Self> {
+        let name = to_cstr!("vkCmdPipelineBarrier2KHR");
+        let function = unsafe { instance.get_device_proc_addr(device.handle(), name.as_ptr()) }?;
+        Some(Synchronization2 {
+            cmd_pipeline_barrier2: unsafe {
+                mem::transmute::<unsafe extern "system" fn() -> c_void, CmdPipelineBarrier2>(
+                    function,
+                )
+            },
+        })
+    }
+
+    // Unlike vkCmdPipelineBarrier every barrier keeps its own stages
+    pub fn cmd_pipeline_barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        memory_barriers: &[Barrier<vk::MemoryBarrier>],
+        buffer_barriers: &[Barrier<vk::BufferMemoryBarrier>],
+        image_barriers: &[Barrier<vk::ImageMemoryBarrier>],
+    ) {
+        let memory: Vec<_> = memory_barriers
+            .iter()
+            .map(|b| MemoryBarrier2 {
+                s_type: MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask: u64::from(b.src_stage.as_raw()),
+                src_access_mask: u64::from(b.barrier.src_access_mask.as_raw()),
+                dst_stage_mask: u64::from(b.dst_stage.as_raw()),
+                dst_access_mask: u64::from(b.barrier.dst_access_mask.as_raw()),
+            })
+            .collect();
+        let buffers: Vec<_> = buffer_barriers
+            .iter()
+            .map(|b| BufferMemoryBarrier2 {
+                s_type: BUFFER_MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask: u64::from(b.src_stage.as_raw()),
+                src_access_mask: u64::from(b.barrier.src_access_mask.as_raw()),
+                dst_stage_mask: u64::from(b.dst_stage.as_raw()),
+                dst_access_mask: u64::from(b.barrier.dst_access_mask.as_raw()),
+                src_queue_family_index: b.barrier.src_queue_family_index,
+                dst_queue_family_index: b.barrier.dst_queue_family_index,
+                buffer: b.barrier.buffer,
+                offset: b.barrier.offset,
+                size: b.barrier.size,
+            })
+            .collect();
+        let images: Vec<_> = image_barriers
+            .iter()
+            .map(|b| ImageMemoryBarrier2 {
+                s_type: IMAGE_MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask: u64::from(b.src_stage.as_raw()),
+                src_access_mask: u64::from(b.barrier.src_access_mask.as_raw()),
+                dst_stage_mask: u64::from(b.dst_stage.as_raw()),
+                dst_access_mask: u64::from(b.barrier.dst_access_mask.as_raw()),
+                old_layout: b.barrier.old_layout,
+                new_layout: b.barrier.new_layout,
+                src_queue_family_index: b.barrier.src_queue_family_index,
+                dst_queue_family_index: b.barrier.dst_queue_family_index,
+                image: b.barrier.image,
+                subresource_range: b.barrier.subresource_range,
+            })
+            .collect();
+
+        let dependency_info = DependencyInfo {
+            s_type: DEPENDENCY_INFO,
+            p_next: ptr::null(),
+            dependency_flags: vk::DependencyFlags::empty(),
+            memory_barrier_count: memory.len() as u32,
+            p_memory_barriers: memory.as_ptr(),
+            buffer_memory_barrier_count: buffers.len() as u32,
+            p_buffer_memory_barriers: buffers.as_ptr(),
+            image_memory_barrier_count: images.len() as u32,
+            p_image_memory_barriers: images.as_ptr(),
+        };
+        unsafe { (self.cmd_pipeline_barrier2)(command_buffer, &dependency_info) };
+    }
+}
@@ -0,0 +1,466 @@
+// Remembers the layout and last accesses of every mip and layer of tracked images, and of tracked
+// buffers as a whole, so a transition only needs to say what comes next. The barrier is worked
+// out from what happened before.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use super::barrier::{aspect_mask, Access};
+use super::sync2::{Barrier, Synchronization2};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct State {
+    layout: vk::ImageLayout,
+    // Last write, everything after it has to wait for it
+    write_stage: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    // Who already sees that write
+    visible_stages: vk::PipelineStageFlags,
+    visible_access: vk::AccessFlags,
+    // Reads since the last write, the next write or layout change waits for them
+    read_stages: vk::PipelineStageFlags,
+}
+
+impl State {
+    fn new(initial: Access) -> Self {
+        let mut state = State {
+            layout: initial.image_layout(),
+            write_stage: vk::PipelineStageFlags::empty(),
+            write_access: vk::AccessFlags::empty(),
+            visible_stages: vk::PipelineStageFlags::empty(),
+            visible_access: vk::AccessFlags::empty(),
+            read_stages: vk::PipelineStageFlags::empty(),
+        };
+        if initial.is_write() {
+            state.write_stage = initial.stage();
+            state.write_access = initial.access();
+        } else if !matches!(initial, Access::Nothing | Access::Acquire) {
+            state.read_stages = initial.stage();
+            state.visible_stages = initial.stage();
+            state.visible_access = initial.access();
+        }
+        state
+    }
+
+    // Moves on to `to`, returning the barrier that has to come first if any
+    fn transition(&mut self, to: Access, image: bool) -> Option<Dependency> {
+        // Buffers have no layout, and accesses without one keep whatever the image is in
+        let new_layout = if image && to.image_layout() != vk::ImageLayout::UNDEFINED {
+            to.image_layout()
+        } else {
+            self.layout
+        };
+        let layout_change = new_layout != self.layout;
+
+        if to.is_write() || layout_change {
+            let src_stage = self.write_stage | self.read_stages;
+            let dependency = Dependency {
+                src_stage: if src_stage.is_empty() {
+                    vk::PipelineStageFlags::TOP_OF_PIPE
+                } else {
+                    src_stage
+                },
+                src_access: self.write_access,
+                dst_stage: to.stage(),
+                dst_access: to.access(),
+                old_layout: self.layout,
+                new_layout,
+            };
+
+            if to.is_write() {
+                self.write_stage = to.stage();
+                self.write_access = to.access();
+                self.visible_stages = vk::PipelineStageFlags::empty();
+                self.visible_access = vk::AccessFlags::empty();
+                self.read_stages = vk::PipelineStageFlags::empty();
+            } else {
+                // The layout change counts as a write that only `to` sees so far
+                self.write_stage = to.stage();
+                self.write_access = vk::AccessFlags::empty();
+                self.visible_stages = to.stage();
+                self.visible_access = to.access();
+                self.read_stages = to.stage();
+            }
+            self.layout = new_layout;
+
+            // Nothing to wait for or transition on a fresh buffer
+            if src_stage.is_empty() && !layout_change {
+                None
+            } else {
+                Some(dependency)
+            }
+        } else {
+            let visible = self.visible_stages.contains(to.stage())
+                && self.visible_access.contains(to.access());
+            let dependency = if visible || self.write_stage.is_empty() {
+                None
+            } else {
+                Some(Dependency {
+                    src_stage: self.write_stage,
+                    src_access: self.write_access,
+                    dst_stage: to.stage(),
+                    dst_access: to.access(),
+                    old_layout: self.layout,
+                    new_layout: self.layout,
+                })
+            };
+            self.visible_stages |= to.stage();
+            self.visible_access |= to.access();
+            self.read_stages |= to.stage();
+            dependency
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Dependency {
+    src_stage: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_stage: vk::PipelineStageFlags,
+    dst_access: vk::AccessFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+}
+
+struct TrackedImage {
+    aspect_mask: vk::ImageAspectFlags,
+    mip_levels: u32,
+    array_layers: u32,
+    // Layer major, one per mip of every layer
+    states: Vec<State>,
+}
+
+impl TrackedImage {
+    // Neighbouring subresources that need the same barrier share one range, first runs of mips
+    // within a layer and then identical runs across layers
+    fn transition(
+        &mut self,
+        mips: Range<u32>,
+        layers: Range<u32>,
+        to: Access,
+    ) -> Vec<(vk::ImageSubresourceRange, Dependency)> {
+        let mips = mips.start..mips.end.min(self.mip_levels);
+        let layers = layers.start..layers.end.min(self.array_layers);
+
+        let mut barriers: Vec<(vk::ImageSubresourceRange, Dependency)> = Vec::new();
+        for layer in layers {
+            let mut runs: Vec<(vk::ImageSubresourceRange, Dependency)> = Vec::new();
+            for mip in mips.clone() {
+                let state = &mut self.states[(layer * self.mip_levels + mip) as usize];
+                let dependency = match state.transition(to, true) {
+                    Some(dependency) => dependency,
+                    None => continue,
+                };
+                match runs.last_mut() {
+                    Some((range, last))
+                        if *last == dependency
+                            && range.base_mip_level + range.level_count == mip =>
+                    {
+                        range.level_count += 1;
+                    }
+                    _ => runs.push((
+                        vk::ImageSubresourceRange {
+                            aspect_mask: self.aspect_mask,
+                            base_mip_level: mip,
+                            level_count: 1,
+                            base_array_layer: layer,
+                            layer_count: 1,
+                        },
+                        dependency,
+                    )),
+                }
+            }
+
+            for (run, dependency) in runs {
+                let merged = barriers.iter_mut().any(|(range, last)| {
+                    let extends = *last == dependency
+                        && range.base_mip_level == run.base_mip_level
+                        && range.level_count == run.level_count
+                        && range.base_array_layer + range.layer_count == layer;
+                    if extends {
+                        range.layer_count += 1;
+                    }
+                    extends
+                });
+                if !merged {
+                    barriers.push((run, dependency));
+                }
+            }
+        }
+        barriers
+    }
+}
+
+pub struct ResourceTracker {
+    images: HashMap<vk::Image, TrackedImage>,
+    buffers: HashMap<vk::Buffer, State>,
+    sync2: Option<Synchronization2>,
+}
+
+impl ResourceTracker {
+    // With synchronization2 every barrier keeps its own stages instead of sharing one OR'd pair
+    pub fn new(sync2: Option<Synchronization2>) -> Self {
+        ResourceTracker {
+            images: HashMap::new(),
+            buffers: HashMap::new(),
+            sync2,
+        }
+    }
+
+    // Every subresource starts out as `initial`, Access::Nothing for freshly created images
+    pub fn track_image(
+        &mut self,
+        image: vk::Image,
+        format: vk::Format,
+        mip_levels: u32,
+        array_layers: u32,
+        initial: Access,
+    ) {
+        self.images.insert(
+            image,
+            TrackedImage {
+                aspect_mask: aspect_mask(format),
+                mip_levels,
+                array_layers,
+                states: vec![State::new(initial); (mip_levels * array_layers) as usize],
+            },
+        );
+    }
+
+    pub fn track_buffer(&mut self, buffer: vk::Buffer, initial: Access) {
+        self.buffers.insert(buffer, State::new(initial));
+    }
+
+    // Call before destroying, handles get reused
+    pub fn forget_image(&mut self, image: vk::Image) {
+        self.images.remove(&image);
+    }
+
+    pub fn forget_buffer(&mut self, buffer: vk::Buffer) {
+        self.buffers.remove(&buffer);
+    }
+
+    pub fn image_layout(&self, image: vk::Image, mip: u32, layer: u32) -> Option<vk::ImageLayout> {
+        let tracked = self.images.get(&image)?;
+        if mip >= tracked.mip_levels || layer >= tracked.array_layers {
+            return None;
+        }
+        Some(tracked.states[(layer * tracked.mip_levels + mip) as usize].layout)
+    }
+
+    // Ranges are clamped to the image, so 0..vk::REMAINING_MIP_LEVELS and
+    // 0..vk::REMAINING_ARRAY_LAYERS cover all of it
+    pub fn transition_image(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        mips: Range<u32>,
+        layers: Range<u32>,
+        to: Access,
+    ) -> Result<(), &'static str> {
+        let tracked = match self.images.get_mut(&image) {
+            Some(tracked) => tracked,
+            None => return Err("Transition of an untracked image"),
+        };
+
+        let barriers: Vec<_> = tracked
+            .transition(mips, layers, to)
+            .into_iter()
+            .map(|(range, dependency)| Barrier {
+                src_stage: dependency.src_stage,
+                dst_stage: dependency.dst_stage,
+                barrier: vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(dependency.src_access)
+                    .dst_access_mask(dependency.dst_access)
+                    .old_layout(dependency.old_layout)
+                    .new_layout(dependency.new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(image)
+                    .subresource_range(range)
+                    .build(),
+            })
+            .collect();
+        self.record(device, command_buffer, &[], &barriers);
+        Ok(())
+    }
+
+    pub fn transition_buffer(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        to: Access,
+    ) -> Result<(), &'static str> {
+        let state = match self.buffers.get_mut(&buffer) {
+            Some(state) => state,
+            None => return Err("Transition of an untracked buffer"),
+        };
+
+        if let Some(dependency) = state.transition(to, false) {
+            let barrier = Barrier {
+                src_stage: dependency.src_stage,
+                dst_stage: dependency.dst_stage,
+                barrier: vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(dependency.src_access)
+                    .dst_access_mask(dependency.dst_access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .build(),
+            };
+            self.record(device, command_buffer, &[barrier], &[]);
+        }
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        buffers: &[Barrier<vk::BufferMemoryBarrier>],
+        images: &[Barrier<vk::ImageMemoryBarrier>],
+    ) {
+        if buffers.is_empty() && images.is_empty() {
+            return;
+        }
+
+        if let Some(sync2) = &self.sync2 {
+            sync2.cmd_pipeline_barrier(command_buffer, &[], buffers, images);
+            return;
+        }
+
+        let barrier_stages = buffers
+            .iter()
+            .map(|b| (b.src_stage, b.dst_stage))
+            .chain(images.iter().map(|b| (b.src_stage, b.dst_stage)));
+        let (src_stage, dst_stage) = barrier_stages.fold(
+            (
+                vk::PipelineStageFlags::empty(),
+                vk::PipelineStageFlags::empty(),
+            ),
+            |(src, dst), (b_src, b_dst)| (src | b_src, dst | b_dst),
+        );
+        let buffer_barriers: Vec<_> = buffers.iter().map(|b| b.barrier).collect();
+        let image_barriers: Vec<_> = images.iter().map(|b| b.barrier).collect();
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffer_barriers,
+                &image_barriers,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(format: vk::Format, mip_levels: u32, array_layers: u32) -> TrackedImage {
+        TrackedImage {
+            aspect_mask: aspect_mask(format),
+            mip_levels,
+            array_layers,
+            states: vec![State::new(Access::Nothing); (mip_levels * array_layers) as usize],
+        }
+    }
+
+    #[test]
+    fn mips_transition_separately() {
+        let mut image = image(vk::Format::R8G8B8A8_UNORM, 4, 1);
+
+        // Whole chain written, then only the top mip read
+        let barriers = image.transition(0..4, 0..1, Access::TransferWrite);
+        assert_eq!(barriers.len(), 1);
+        assert_eq!(barriers[0].0.level_count, 4);
+        assert_eq!(barriers[0].1.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(barriers[0].1.src_stage, vk::PipelineStageFlags::TOP_OF_PIPE);
+
+        let barriers = image.transition(0..1, 0..1, Access::TransferRead);
+        assert_eq!(barriers.len(), 1);
+        assert_eq!(barriers[0].0.base_mip_level, 0);
+        assert_eq!(barriers[0].0.level_count, 1);
+        assert_eq!(barriers[0].1.src_access, vk::AccessFlags::TRANSFER_WRITE);
+
+        // Mip 0 comes from TRANSFER_SRC, the rest still from TRANSFER_DST
+        let barriers = image.transition(
+            0..vk::REMAINING_MIP_LEVELS,
+            0..1,
+            Access::FragmentShaderRead,
+        );
+        assert_eq!(barriers.len(), 2);
+        assert_eq!(
+            barriers[0].1.old_layout,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+        );
+        assert_eq!(barriers[0].1.src_access, vk::AccessFlags::empty());
+        assert_eq!(barriers[1].0.base_mip_level, 1);
+        assert_eq!(barriers[1].0.level_count, 3);
+        assert_eq!(
+            barriers[1].1.old_layout,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL
+        );
+    }
+
+    #[test]
+    fn layers_merge_and_depth_keeps_its_aspect() {
+        let mut image = image(vk::Format::D24_UNORM_S8_UINT, 2, 6);
+
+        let barriers = image.transition(0..2, 0..6, Access::DepthAttachmentWrite);
+        assert_eq!(barriers.len(), 1);
+        let (range, dependency) = barriers[0];
+        assert_eq!(
+            range.aspect_mask,
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        );
+        assert_eq!(range.layer_count, 6);
+        assert_eq!(range.level_count, 2);
+        assert_eq!(
+            dependency.new_layout,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(dependency.dst_stage, Access::DepthAttachmentWrite.stage());
+    }
+
+    #[test]
+    fn reads_wait_once() {
+        let mut buffer = State::new(Access::Nothing);
+
+        // Nothing to wait for before the first write
+        assert!(buffer.transition(Access::TransferWrite, false).is_none());
+
+        let dependency = buffer.transition(Access::VertexBuffer, false).unwrap();
+        assert_eq!(dependency.src_stage, vk::PipelineStageFlags::TRANSFER);
+        assert_eq!(
+            dependency.dst_access,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+        );
+        assert!(buffer.transition(Access::VertexBuffer, false).is_none());
+
+        // A different reader still needs the write made visible
+        let dependency = buffer
+            .transition(Access::ComputeStorageRead, false)
+            .unwrap();
+        assert_eq!(dependency.src_stage, vk::PipelineStageFlags::TRANSFER);
+
+        // Overwriting waits on the readers as well as the last write
+        let dependency = buffer.transition(Access::TransferWrite, false).unwrap();
+        assert_eq!(
+            dependency.src_stage,
+            vk::PipelineStageFlags::TRANSFER
+                | vk::PipelineStageFlags::VERTEX_INPUT
+                | vk::PipelineStageFlags::COMPUTE_SHADER
+        );
+    }
+}
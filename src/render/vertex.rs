@@ -0,0 +1,184 @@
+// Vertex types that know their own input layout. Derive it with #[derive(VertexLayout)], see
+// enegine-derive for the attributes it takes.
+
+use std::mem;
+
+use ash::vk;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use super::pipeline::VertexLayout;
+
+pub use enegine_derive::VertexLayout;
+
+pub trait VertexType: Copy {
+    fn vertex_layout() -> VertexLayout;
+}
+
+// Vertex attribute format of a field type. Matrices take one location per column.
+pub trait AttributeType {
+    const FORMAT: vk::Format;
+    const LOCATIONS: u32 = 1;
+}
+
+macro_rules! attribute_type {
+    ($($ty:ty => $format:ident),* $(,)?) => {
+        $(
+            impl AttributeType for $ty {
+                const FORMAT: vk::Format = vk::Format::$format;
+            }
+        )*
+    };
+}
+
+attribute_type! {
+    f32 => R32_SFLOAT,
+    [f32; 2] => R32G32_SFLOAT,
+    [f32; 3] => R32G32B32_SFLOAT,
+    [f32; 4] => R32G32B32A32_SFLOAT,
+    Vec2 => R32G32_SFLOAT,
+    Vec3 => R32G32B32_SFLOAT,
+    Vec4 => R32G32B32A32_SFLOAT,
+    u32 => R32_UINT,
+    [u32; 2] => R32G32_UINT,
+    [u32; 3] => R32G32B32_UINT,
+    [u32; 4] => R32G32B32A32_UINT,
+    i32 => R32_SINT,
+    [i32; 2] => R32G32_SINT,
+    [i32; 3] => R32G32B32_SINT,
+    [i32; 4] => R32G32B32A32_SINT,
+    [u16; 2] => R16G16_UINT,
+    [u16; 4] => R16G16B16A16_UINT,
+    [u8; 4] => R8G8B8A8_UINT,
+}
+
+// What a mesh's indices can be given as
+pub trait MeshIndex: Copy {
+    const INDEX_TYPE: vk::IndexType;
+
+    fn to_u32(self) -> u32;
+}
+
+impl MeshIndex for u16 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+
+    fn to_u32(self) -> u32 {
+        u32::from(self)
+    }
+}
+
+impl MeshIndex for u32 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+
+    fn to_u32(self) -> u32 {
+        self
+    }
+}
+
+// Whether `indices` are a triangle list drawable from `vertex_count` vertices
+pub fn check_indices<I: MeshIndex>(indices: &[I], vertex_count: usize) -> Result<(), &'static str> {
+    if vertex_count == 0 || indices.is_empty() {
+        return Err("Meshes need vertices and indices");
+    }
+    if indices.len() % 3 != 0 {
+        return Err("Index count isn't a whole number of triangles");
+    }
+    if indices
+        .iter()
+        .any(|index| index.to_u32() as usize >= vertex_count)
+    {
+        return Err("Mesh index past the last vertex");
+    }
+    Ok(())
+}
+
+// Index buffer contents for `indices`. u32 ones are narrowed to u16 whenever that still addresses
+// `vertex_count` vertices, which halves the buffer.
+pub fn index_data<I: MeshIndex>(indices: &[I], vertex_count: usize) -> (vk::IndexType, Vec<u8>) {
+    if I::INDEX_TYPE == vk::IndexType::UINT16 || vertex_count <= usize::from(u16::MAX) + 1 {
+        let narrowed: Vec<u16> = indices.iter().map(|i| i.to_u32() as u16).collect();
+        (vk::IndexType::UINT16, as_bytes(&narrowed).to_vec())
+    } else {
+        let widened: Vec<u32> = indices.iter().map(|i| i.to_u32()).collect();
+        (vk::IndexType::UINT32, as_bytes(&widened).to_vec())
+    }
+}
+
+pub(crate) fn as_bytes<T: Copy>(slice: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(slice.as_ptr() as *const u8, mem::size_of_val(slice)) }
+}
+
+impl AttributeType for Mat4 {
+    const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+    const LOCATIONS: u32 = 4;
+}
+
+impl AttributeType for [[f32; 4]; 4] {
+    const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+    const LOCATIONS: u32 = 4;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, VertexLayout)]
+    #[repr(C)]
+    #[vertex(binding = 1, instance)]
+    struct Instance {
+        #[vertex(location = 5)]
+        model: Mat4,
+        #[vertex(format = "R8G8B8A8_UNORM")]
+        tint: [u8; 4],
+        #[vertex(skip)]
+        _padding: u32,
+        material: u32,
+    }
+
+    #[test]
+    fn derived_layout() {
+        let layout = Instance::vertex_layout();
+        assert_eq!(layout.bindings.len(), 1);
+        assert_eq!(layout.bindings[0].binding, 1);
+        assert_eq!(
+            layout.bindings[0].stride,
+            std::mem::size_of::<Instance>() as u32
+        );
+        assert_eq!(layout.bindings[0].input_rate, vk::VertexInputRate::INSTANCE);
+
+        let attributes: Vec<(u32, vk::Format, u32)> = layout
+            .attributes
+            .iter()
+            .map(|a| (a.location, a.format, a.offset))
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                (5, vk::Format::R32G32B32A32_SFLOAT, 0),
+                (6, vk::Format::R32G32B32A32_SFLOAT, 16),
+                (7, vk::Format::R32G32B32A32_SFLOAT, 32),
+                (8, vk::Format::R32G32B32A32_SFLOAT, 48),
+                (9, vk::Format::R8G8B8A8_UNORM, 64),
+                (10, vk::Format::R32_UINT, 72),
+            ]
+        );
+    }
+
+    #[test]
+    fn indices_narrow_when_they_fit() {
+        let (index_type, data) = index_data(&[0u32, 1, 65535], 65536);
+        assert_eq!(index_type, vk::IndexType::UINT16);
+        assert_eq!(data, as_bytes(&[0u16, 1, 65535]));
+
+        let (index_type, data) = index_data(&[0u32, 65536], 65537);
+        assert_eq!(index_type, vk::IndexType::UINT32);
+        assert_eq!(data, as_bytes(&[0u32, 65536]));
+
+        let (index_type, _) = index_data(&[0u16, 1, 2], 3);
+        assert_eq!(index_type, vk::IndexType::UINT16);
+
+        assert!(check_indices(&[0u16, 1, 2], 3).is_ok());
+        assert!(check_indices::<u16>(&[], 3).is_err());
+        assert!(check_indices(&[0u32, 1, 2, 0], 3).is_err());
+        assert!(check_indices(&[0u32, 1, 3], 3).is_err());
+    }
+}